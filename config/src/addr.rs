@@ -0,0 +1,83 @@
+use std::{io, net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs}};
+
+use crate::ParseError;
+
+/// Split an address of a net map, ip file or syncer file into its host and
+/// port. The host is a DNS name, an IPv4 address or an IPv6 address in
+/// brackets, e.g. `replica-1.local:8500`, `10.0.0.1:8500` or
+/// `[fd00::1]:8500`.
+pub fn split_address(address: &str) -> Result<(&str, u16), ParseError> {
+    let invalid = || ParseError::InvalidAddress(address.to_string());
+    let (host, port) = address.rsplit_once(':').ok_or_else(invalid)?;
+    let port = port.parse().map_err(|_| invalid())?;
+    let host = match host.strip_prefix('[') {
+        Some(v6) => v6.strip_suffix(']')
+            .filter(|ip| ip.parse::<Ipv6Addr>().is_ok())
+            .ok_or_else(invalid)?,
+        // An IPv6 address needs brackets to tell it from the port
+        None if host.is_empty() || host.contains(':') => return Err(invalid()),
+        None => host,
+    };
+    Ok((host, port))
+}
+
+/// Resolve `host`, a DNS name or an IPv4 or IPv6 address without brackets,
+/// to the first address the resolver returns. Names are looked up anew on
+/// every call.
+pub fn resolve_host(host: &str, port: u16) -> io::Result<SocketAddr> {
+    (host, port).to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", host)))
+}
+
+/// Resolve an address of a net map, ip file or syncer file.
+pub fn resolve(address: &str) -> io::Result<SocketAddr> {
+    let (host, port) = split_address(address)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    resolve_host(host, port)
+}
+
+/// The address to listen on to be reached at `address`: the port of
+/// `address` on every interface of the family `address` resolves to. On a
+/// dual-stack host, listening on every IPv6 interface also accepts IPv4.
+pub fn wildcard(address: &str) -> io::Result<SocketAddr> {
+    let addr = resolve(address)?;
+    let ip = if addr.is_ipv6() {
+        Ipv6Addr::UNSPECIFIED.into()
+    } else {
+        Ipv4Addr::UNSPECIFIED.into()
+    };
+    Ok(SocketAddr::new(ip, addr.port()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_of_every_kind_are_understood() {
+        assert_eq!(split_address("10.0.0.1:8500").unwrap(), ("10.0.0.1", 8500));
+        assert_eq!(split_address("[fd00::1]:8500").unwrap(), ("fd00::1", 8500));
+        assert_eq!(split_address("replica-1.local:8500").unwrap(), ("replica-1.local", 8500));
+        for bad in ["fd00::1:8500", "[fd00::1:8500", "[replica-1]:8500", "10.0.0.1", ":8500", "10.0.0.1:port"] {
+            assert!(split_address(bad).is_err(), "{}", bad);
+        }
+
+        assert_eq!(resolve("[::1]:8500").unwrap(), "[::1]:8500".parse().unwrap());
+        assert!(resolve("localhost:8500").unwrap().ip().is_loopback());
+        assert_eq!(wildcard("127.0.0.1:8500").unwrap(), "0.0.0.0:8500".parse().unwrap());
+        assert_eq!(wildcard("[::1]:8500").unwrap(), "[::]:8500".parse().unwrap());
+    }
+
+    #[test]
+    fn a_bad_ip_file_is_an_error() {
+        let mut config = crate::Node::new();
+        config.num_nodes = 1;
+        let ips = |ips: &[&str]| ips.iter().map(|ip| ip.to_string()).collect();
+        assert!(config.update_config(ips(&["127.0.0.1:8500", "10.0.0.1"])).is_err());
+        assert!(config.update_config(ips(&["no-such-host.invalid:8500", "10.0.0.1:5000"])).is_err());
+        config.update_config(ips(&["127.0.0.1:8500", "10.0.0.1:5000"])).unwrap();
+        assert_eq!(config.net_map[&0], "0.0.0.0:8500");
+        assert_eq!(config.client_addr, "10.0.0.1:5000");
+    }
+}
@@ -43,8 +43,8 @@ impl Client {
             }
         }
         match self.crypto_alg {
-            Algorithm::NOPKI => {
-                // In case of No PKI, use secret keys
+            Algorithm::NOPKI | Algorithm::ED25519 => {
+                // Clients do not authenticate replica traffic
             }
         }
         Ok(())
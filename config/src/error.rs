@@ -12,6 +12,14 @@ pub enum ParseError {
     InvalidSkSize(usize),
     // feature name that is not implemented
     Unimplemented(&'static str),
+    // TLS is enabled but a certificate or key is missing
+    MissingCertificates,
+    // an address that is not a host and a port
+    InvalidAddress(String),
+    // an address whose host cannot be looked up, and why
+    UnresolvedAddress(String, String),
+    // a keystore that cannot be read, opened or written
+    Keystore(String),
 }
 
 impl std::fmt::Display for ParseError {
@@ -29,6 +37,14 @@ impl std::fmt::Display for ParseError {
             write!(f, "{} feature is not yet implemented", feature),
             ParseError::InvalidSkSize(s) =>
             write!(f, "invalid secret key size ({})", s),
+            ParseError::MissingCertificates =>
+            write!(f, "tls is enabled but the certificates are missing"),
+            ParseError::InvalidAddress(ref address) =>
+            write!(f, "invalid address {}, expected host:port or [ipv6]:port", address),
+            ParseError::UnresolvedAddress(ref address, ref reason) =>
+            write!(f, "cannot resolve address {}: {}", address, reason),
+            ParseError::Keystore(ref reason) =>
+            write!(f, "keystore error: {}", reason),
         }
     }
 }
@@ -42,6 +58,10 @@ impl std::error::Error for ParseError {
             ParseError::InvalidPkSize(_) => "invalid public key size",
             ParseError::InvalidSkSize(_) => "invalid secret key size",
             ParseError::Unimplemented(_) => "feature unimplemented",
+            ParseError::MissingCertificates => "missing certificates",
+            ParseError::InvalidAddress(_) => "invalid address",
+            ParseError::UnresolvedAddress(_,_) => "unresolved address",
+            ParseError::Keystore(_) => "keystore error",
         }
     }
 
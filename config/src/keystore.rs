@@ -0,0 +1,206 @@
+//! Node secrets at rest: the Ed25519 identity, the pairwise keys, the
+//! threshold key share and the TLS key of a node, encrypted under a
+//! passphrase in a file of their own.
+
+use std::{fs::File, path::{Path, PathBuf}};
+
+use crypto::aead::{self, KdfParams, NONCE_SIZE};
+use fnv::FnvHashMap as HashMap;
+use serde::{Serialize, Deserialize};
+use types::Replica;
+use zeroize::Zeroizing;
+
+use crate::{Node, ParseError, Secret};
+
+/// The version of the keystore format. Version 2 added the threshold key
+/// share.
+pub const KEYSTORE_VERSION: u16 = 2;
+/// The environment variable a node and `genconfig` take the keystore
+/// passphrase from. Without it, a node prompts for it.
+pub const PASSPHRASE_VAR: &str = "KEYSTORE_PASSPHRASE";
+
+/// The secrets of a node config, zeroed once dropped.
+#[derive(Serialize, Deserialize, Default)]
+struct Secrets {
+    secret_key_bytes: Secret,
+    sk_map: Vec<(Replica, Secret)>,
+    key_share: Secret,
+    my_cert_key: Secret,
+}
+
+/// A keystore file. The secrets are sealed with ChaCha20-Poly1305 under a
+/// key derived from the passphrase with Argon2id, and bound to the replica
+/// they belong to.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Keystore {
+    pub version: u16,
+    pub id: Replica,
+    pub kdf: KdfParams,
+    pub nonce: [u8; NONCE_SIZE],
+    pub ciphertext: Vec<u8>,
+}
+
+fn keystore_error(e: impl std::fmt::Display) -> ParseError {
+    ParseError::Keystore(e.to_string())
+}
+
+fn aad(id: Replica) -> Vec<u8> {
+    bincode::serialize(&(KEYSTORE_VERSION, id)).expect("Failed to serialize keystore header")
+}
+
+impl Keystore {
+    /// Seal the secrets of `node` under `passphrase`.
+    pub fn seal(node: &Node, passphrase: &[u8], kdf: KdfParams) -> Result<Keystore, ParseError> {
+        let secrets = Secrets {
+            secret_key_bytes: node.secret_key_bytes.clone(),
+            sk_map: node.sk_map.iter().map(|(r, sk)| (*r, sk.clone())).collect(),
+            key_share: node.key_share.clone(),
+            my_cert_key: node.my_cert_key.clone(),
+        };
+        let plaintext = Zeroizing::new(bincode::serialize(&secrets).map_err(keystore_error)?);
+        let key = kdf.derive_key(passphrase).map_err(keystore_error)?;
+        let nonce = aead::random_nonce();
+        let ciphertext = aead::seal(&key, &nonce, &aad(node.id), &plaintext);
+        Ok(Keystore { version: KEYSTORE_VERSION, id: node.id, kdf, nonce, ciphertext })
+    }
+
+    /// Open the keystore of `node` with `passphrase`, and move the secrets
+    /// into it.
+    pub fn unseal(&self, node: &mut Node, passphrase: &[u8]) -> Result<(), ParseError> {
+        if self.version != KEYSTORE_VERSION {
+            return Err(keystore_error(format!("unknown keystore version {}", self.version)));
+        }
+        if self.id != node.id {
+            return Err(keystore_error(format!("the keystore is replica {}'s, not {}'s", self.id, node.id)));
+        }
+        let key = self.kdf.derive_key(passphrase).map_err(keystore_error)?;
+        let plaintext = Zeroizing::new(
+            aead::open(&key, &self.nonce, &aad(self.id), &self.ciphertext)
+                .map_err(|_| keystore_error("wrong passphrase, or the keystore is corrupt"))?
+        );
+        let mut secrets: Secrets = bincode::deserialize(&plaintext).map_err(keystore_error)?;
+        node.secret_key_bytes = std::mem::take(&mut secrets.secret_key_bytes);
+        node.sk_map = std::mem::take(&mut secrets.sk_map).into_iter().collect();
+        node.key_share = std::mem::take(&mut secrets.key_share);
+        node.my_cert_key = std::mem::take(&mut secrets.my_cert_key);
+        Ok(())
+    }
+
+    pub fn from_file(path: &Path) -> Result<Keystore, ParseError> {
+        let f = File::open(path)
+            .map_err(|e| keystore_error(format!("{}: {}", path.display(), e)))?;
+        serde_json::from_reader(f).map_err(keystore_error)
+    }
+
+    pub fn to_file(&self, path: &Path) -> Result<(), ParseError> {
+        let f = File::create(path)
+            .map_err(|e| keystore_error(format!("{}: {}", path.display(), e)))?;
+        serde_json::to_writer_pretty(f, self).map_err(keystore_error)
+    }
+}
+
+impl Node {
+    /// Whether the secrets of this config are in a keystore that was not
+    /// opened yet.
+    pub fn is_locked(&self) -> bool {
+        self.keystore.is_some()
+            && self.secret_key_bytes.is_empty()
+            && self.sk_map.is_empty()
+            && self.key_share.is_empty()
+            && self.my_cert_key.is_empty()
+    }
+
+    /// Move the secrets of this config into a keystore at `path`, sealed
+    /// under `passphrase`, and refer to it as `name`.
+    pub fn lock(&mut self, path: &Path, name: &str, passphrase: &[u8]) -> Result<(), ParseError> {
+        self.lock_with(path, name, passphrase, KdfParams::generate())
+    }
+
+    /// `lock`, deriving the key with `kdf`.
+    pub fn lock_with(&mut self, path: &Path, name: &str, passphrase: &[u8], kdf: KdfParams) -> Result<(), ParseError> {
+        Keystore::seal(self, passphrase, kdf)?.to_file(path)?;
+        // The secrets are zeroed as they are dropped
+        self.secret_key_bytes = Secret::default();
+        self.sk_map = HashMap::default();
+        self.key_share = Secret::default();
+        self.my_cert_key = Secret::default();
+        self.keystore = Some(name.to_string());
+        Ok(())
+    }
+
+    /// Load the secrets of this config from its keystore, if it has one. A
+    /// relative keystore path is taken from `dir`, the directory of the
+    /// config.
+    pub fn unlock(&mut self, dir: &Path, passphrase: &[u8]) -> Result<(), ParseError> {
+        if let Some(name) = self.keystore.clone() {
+            Keystore::from_file(&dir.join(name))?.unseal(self, passphrase)?;
+        }
+        Ok(())
+    }
+}
+
+/// The keystore of a running node, to keep the secrets the node makes, such
+/// as the key share of a DKG, along with the ones it was given.
+pub struct SecretStore {
+    node: Node,
+    dir: PathBuf,
+    passphrase: Zeroizing<String>,
+}
+
+impl SecretStore {
+    /// The keystore of the unlocked config `node`, which lies in `dir`.
+    pub fn new(node: &Node, dir: &Path, passphrase: Zeroizing<String>) -> Result<SecretStore, ParseError> {
+        if node.keystore.is_none() || node.is_locked() {
+            return Err(keystore_error("the config has no open keystore"));
+        }
+        Ok(SecretStore { node: node.clone(), dir: dir.to_path_buf(), passphrase })
+    }
+
+    /// Seal `key_share` into the keystore with the other secrets, under the
+    /// costs of the key derivation it had and a fresh salt.
+    pub fn save_key_share(&self, key_share: Secret) -> Result<(), ParseError> {
+        let name = self.node.keystore.clone().unwrap_or_default();
+        let path = self.dir.join(&name);
+        let kdf = KdfParams { salt: KdfParams::generate().salt, ..Keystore::from_file(&path)?.kdf };
+        let mut node = self.node.clone();
+        node.key_share = key_share;
+        node.lock_with(&path, &name, self.passphrase.as_bytes(), kdf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_survive_a_round_trip() {
+        let mut node = Node::new();
+        node.id = 1;
+        node.secret_key_bytes = vec![1u8; 64].into();
+        node.sk_map.insert(0, vec![2u8; 32].into());
+        node.key_share = vec![4u8; 40].into();
+        node.my_cert_key = vec![3u8; 10].into();
+        let cheap = KdfParams { m_cost: 64, t_cost: 1, ..KdfParams::generate() };
+        let keystore = Keystore::seal(&node, b"passphrase", cheap).unwrap();
+
+        let mut locked = node.clone();
+        locked.secret_key_bytes = Secret::default();
+        locked.sk_map = HashMap::default();
+        locked.key_share = Secret::default();
+        locked.my_cert_key = Secret::default();
+        locked.keystore = Some("keystore-1.json".to_string());
+        assert!(locked.is_locked());
+        assert!(matches!(keystore.unseal(&mut locked, b"Passphrase"), Err(ParseError::Keystore(_))));
+        keystore.unseal(&mut locked, b"passphrase").unwrap();
+        assert!(!locked.is_locked());
+        assert_eq!(locked.secret_key_bytes, node.secret_key_bytes);
+        assert_eq!(locked.sk_map, node.sk_map);
+        assert_eq!(locked.key_share, node.key_share);
+        assert_eq!(locked.my_cert_key, node.my_cert_key);
+
+        // Another replica's keystore
+        let mut other = Node::new();
+        other.id = 2;
+        assert!(keystore.unseal(&mut other, b"passphrase").is_err());
+    }
+}
@@ -7,6 +7,12 @@ pub use node::*;
 mod error;
 pub use error::*;
 
+mod addr;
+pub use addr::*;
+
+mod keystore;
+pub use keystore::*;
+
 fn is_valid_replica(r:types::Replica, n:usize) -> bool {
     n>r as usize
 }
@@ -2,22 +2,29 @@ use serde::{
     Serialize, 
     Deserialize
 };
-use types::Replica;
-use crypto::Algorithm;
+use types::{Priority, Replica};
+use crypto::{Algorithm, hash::{Algorithms, HashAlgorithm, MacAlgorithm}};
 use fnv::FnvHashMap as HashMap;
 use super::{
     ParseError,
-    is_valid_replica
+    is_valid_replica,
+    split_address,
+    wildcard,
 };
 use std::fs::File;
+use zeroize::Zeroizing;
 use std::io::prelude::*;
-use std::net::{SocketAddr, SocketAddrV4};
 use serde_json::from_reader;
 use toml::from_str;
 
+/// Key material, zeroed when dropped, and so are the copies that cloning
+/// a config makes.
+pub type Secret = Zeroizing<Vec<u8>>;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Node {
-    /// Node network config
+    /// Node network config. Addresses are `host:port`, where the host is a
+    /// DNS name, an IPv4 address or an IPv6 address in brackets
     pub net_map: HashMap<Replica, String>,
 
     /// Protocol details
@@ -26,22 +33,66 @@ pub struct Node {
     pub num_nodes: usize,
     pub num_faults: usize,
     pub block_size:usize,
+    /// Unused: nodes serve the syncer on their consensus port
     pub client_port: u16,
-    pub client_addr: SocketAddr,
+    /// The syncer's address, see `net_map`
+    pub client_addr: String,
     pub payload: usize,
     
     pub prot_payload: String,
     /// Crypto primitives
     pub crypto_alg: Algorithm,
     pub pk_map: HashMap<Replica, Vec<u8>>,
-    pub secret_key_bytes: Vec<u8>,
+    pub secret_key_bytes: Secret,
     /// For authenticated channels
-    pub sk_map: HashMap<Replica,Vec<u8>>,
+    pub sk_map: HashMap<Replica,Secret>,
+    /// The threshold key share generated by the DKG, serialized
+    #[serde(default)]
+    pub key_share: Secret,
+    /// The file holding `secret_key_bytes`, `sk_map`, `key_share` and
+    /// `my_cert_key`, which are then left empty here, see `Keystore`
+    #[serde(default)]
+    pub keystore: Option<String>,
+    /// Seconds between rotations of the pairwise keys derived with ED25519;
+    /// 0 never rotates them
+    #[serde(default)]
+    pub key_rotation: u64,
 
     /// OpenSSL Certificate Details
     pub my_cert: Vec<u8>,
-    pub my_cert_key: Vec<u8>,
+    pub my_cert_key: Secret,
     pub root_cert: Vec<u8>,
+    /// Use mutual TLS with the certificates above instead of plain TCP
+    #[serde(default)]
+    pub tls: bool,
+    /// Frames larger than this are rejected and their connection closed
+    #[serde(default = "default_max_frame_size")]
+    pub max_frame_size: usize,
+    /// The priority class of kinds of protocol messages, by name, for the
+    /// kinds whose default class should be overridden
+    #[serde(default)]
+    pub priorities: HashMap<String, Priority>,
+    /// Tasks verifying the MACs of received messages; 0 runs one per core
+    #[serde(default)]
+    pub verifiers: usize,
+    /// The hash function of digests and Merkle trees, and the MAC of
+    /// messages. Every replica of a deployment must use the same ones
+    #[serde(default)]
+    pub hash: HashAlgorithm,
+    #[serde(default)]
+    pub mac: MacAlgorithm,
+    /// Encrypt the payloads of protocol messages for their recipients under
+    /// the pairwise keys, besides MACing them
+    #[serde(default)]
+    pub confidential: bool,
+    /// Names this run of the deployment. Common coins are derived from it,
+    /// so it must be fresh for every run, or the coins of a run repeat
+    #[serde(default)]
+    pub session: String,
+}
+
+fn default_max_frame_size() -> usize {
+    types::MAX_FRAME_SIZE
 }
 
 impl Node {
@@ -52,11 +103,20 @@ impl Node {
         if 2*self.num_faults >= self.num_nodes {
             return Err(ParseError::IncorrectFaults(self.num_faults, self.num_nodes));
         }
+        for address in self.net_map.values().chain(std::iter::once(&self.client_addr)) {
+            split_address(address)?;
+        }
+        if self.is_locked() {
+            return Err(ParseError::Keystore("the secrets are still in the keystore".to_string()));
+        }
         // for repl in &self.net_map {
         //     if !is_valid_replica(*repl.0, self.num_nodes) {
         //         return Err(ParseError::InvalidMapEntry(*repl.0));
         //     }
         // }
+        if self.tls && (self.my_cert.is_empty() || self.my_cert_key.is_empty() || self.root_cert.is_empty()) {
+            return Err(ParseError::MissingCertificates);
+        }
         match self.crypto_alg {
             Algorithm::NOPKI => {
                 // In case of No PKI, use secret keys
@@ -69,6 +129,23 @@ impl Node {
                     }
                 }
             }
+            Algorithm::ED25519 => {
+                // Pairwise keys are derived from the identities of all replicas
+                if self.secret_key_bytes.len() != crypto::ED25519_PVT_SIZE {
+                    return Err(ParseError::InvalidSkSize(self.secret_key_bytes.len()));
+                }
+                if self.pk_map.len() != self.num_nodes {
+                    return Err(ParseError::InvalidMapLen(self.num_nodes, self.pk_map.len()));
+                }
+                for repl in &self.pk_map {
+                    if !is_valid_replica(*repl.0, self.num_nodes) {
+                        return Err(ParseError::InvalidMapEntry(*repl.0));
+                    }
+                    if repl.1.len() != crypto::ED25519_PK_SIZE {
+                        return Err(ParseError::InvalidPkSize(repl.1.len()));
+                    }
+                }
+            }
         }
         Ok(())
     }
@@ -77,7 +154,7 @@ impl Node {
         Node{
             block_size: 0,
             client_port: 0,
-            client_addr: SocketAddrV4::new("0.0.0.0".parse().unwrap(),5000).into(),
+            client_addr: "0.0.0.0:5000".to_string(),
             crypto_alg: Algorithm::NOPKI,
             delta: 50,
             id: 0,
@@ -85,13 +162,24 @@ impl Node {
             num_faults: 0,
             num_nodes: 0,
             pk_map: HashMap::default(),
-            secret_key_bytes: Vec::new(),
+            secret_key_bytes: Secret::default(),
             sk_map: HashMap::default(),
+            key_share: Secret::default(),
+            keystore: None,
+            key_rotation: 0,
             payload: 0,
             prot_payload: String::new(),
             my_cert: Vec::new(),
             root_cert:Vec::new(),
-            my_cert_key: Vec::new(),
+            my_cert_key: Secret::default(),
+            tls: false,
+            max_frame_size: types::MAX_FRAME_SIZE,
+            priorities: HashMap::default(),
+            verifiers: 0,
+            hash: HashAlgorithm::default(),
+            mac: MacAlgorithm::default(),
+            confidential: false,
+            session: String::new(),
         }
     }
 
@@ -134,25 +222,22 @@ impl Node {
         return c;
     }
 
-    pub fn update_config(&mut self, ips: Vec<String>) {
+    /// Take the addresses of the replicas, in order, and then the syncer's
+    /// from `ips`. We listen on every interface at the port of our own.
+    pub fn update_config(&mut self, ips: Vec<String>) -> Result<(), ParseError> {
         let mut idx = 0;
         let max_nodes = self.num_nodes;
         for ip in ips {
-            // For self ip, put 0.0.0.0 with the same port
+            split_address(&ip)?;
             if idx == max_nodes{
                 // Syncer address
-                let ip_a:Vec<&str> = ip.split(":").collect();
-                let port:u16 = ip_a.last().expect("invalid ip found").parse().expect("failed to parse port number");
-                let sock_addr = SocketAddrV4::new(ip_a.get(0).unwrap().parse().unwrap(), port);
-                self.client_addr = sock_addr.into();
+                self.client_addr = ip.clone();
             }
+            // For self ip, listen on every interface with the same port
             if idx == self.id {
-                let port:u16 = ip.split(":")
-                    .last()
-                    .expect("invalid ip found; unable to split at :")
-                    .parse()
-                    .expect("failed to parse the port after :");
-                self.net_map.insert(idx, format!("0.0.0.0:{}", port));
+                let listen = wildcard(&ip)
+                    .map_err(|e| ParseError::UnresolvedAddress(ip.clone(), e.to_string()))?;
+                self.net_map.insert(idx, listen.to_string());
                 idx += 1;
                 continue;
             }
@@ -161,6 +246,13 @@ impl Node {
             idx += 1;
         }
         log::info!("Talking to servers: {:?}", self.net_map);
+        Ok(())
+    }
+
+    /// The hash function and MAC of the deployment, which every protocol
+    /// is handed rather than picking them itself.
+    pub fn algorithms(&self) -> Algorithms {
+        Algorithms { hash: self.hash, mac: self.mac }
     }
 
     pub fn my_ip(&self) -> String {
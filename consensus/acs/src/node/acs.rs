@@ -0,0 +1,75 @@
+use transport::Transport;
+use types::{SyncMsg, acs::{ProtMsg, WrapperMsg}};
+
+use super::Context;
+
+impl<N: Transport<WrapperMsg>, S: Transport<SyncMsg>> Context<N, S> {
+    pub async fn start_acs(&mut self) {
+        // Reliably broadcast our own proposal
+        self.broadcast(ProtMsg::RbcInit(self.inp_message)).await;
+    }
+
+    /// Feed reliable broadcast deliveries and agreement decisions into the
+    /// agreement instances until nothing changes, then check for the output.
+    pub(crate) async fn acs_progress(&mut self) {
+        let n_minus_f = self.num_nodes - self.num_faults;
+        loop {
+            let mut progress = false;
+            let ones = self.ba.values()
+                .filter(|ba| ba.decided == Some(true))
+                .count();
+            for inst in 0..self.num_nodes {
+                let has_input = self.ba.get(&inst)
+                    .map(|ba| ba.est.is_some())
+                    .unwrap_or(false);
+                if has_input {
+                    continue;
+                }
+                let delivered = self.rbc.get(&inst)
+                    .map(|rbc| rbc.delivered.is_some())
+                    .unwrap_or(false);
+                if delivered {
+                    // Vote to include every proposal we received
+                    self.ba_input(inst, true).await;
+                    progress = true;
+                }
+                else if ones >= n_minus_f {
+                    // Enough proposals are in, vote to exclude the rest
+                    self.ba_input(inst, false).await;
+                    progress = true;
+                }
+            }
+            for inst in 0..self.num_nodes {
+                progress |= self.ba_progress(inst).await;
+            }
+            if !progress {
+                break;
+            }
+        }
+        self.check_output().await;
+    }
+
+    async fn check_output(&mut self) {
+        if self.acs_done {
+            return;
+        }
+        let mut output = Vec::with_capacity(self.num_nodes);
+        for inst in 0..self.num_nodes {
+            let decided = self.ba.get(&inst).and_then(|ba| ba.decided);
+            match decided {
+                Some(true) => {
+                    // The proposal is guaranteed to be delivered eventually
+                    match self.rbc.get(&inst).and_then(|rbc| rbc.delivered) {
+                        Some(value) => output.push(Some(value)),
+                        None => return,
+                    }
+                },
+                Some(false) => output.push(None),
+                None => return,
+            }
+        }
+        self.acs_done = true;
+        log::info!("Common subset: {:?}", output);
+        self.terminate(format!("{:?}", output)).await;
+    }
+}
@@ -0,0 +1,214 @@
+use std::{collections::{HashMap, HashSet}, time::Instant};
+
+use crypto::coin::{CoinShare, coin_share, verify_coin_share, combine_coin, coin_bit};
+use transport::Transport;
+use types::{Offence, Replica, SyncMsg, acs::{ProtMsg, WrapperMsg}};
+
+use super::Context;
+
+/// The state of one round of a binary agreement instance.
+#[derive(Debug, Default)]
+pub struct RoundState {
+    pub bval_sent: HashSet<bool>,
+    /// value -> replicas that sent BVal for it
+    pub bvals: HashMap<bool, HashSet<Replica>>,
+    /// Values that were BVal'ed by 2f+1 replicas
+    pub bin_values: Vec<bool>,
+    pub aux_sent: bool,
+    /// The first Aux value of every replica
+    pub aux: HashMap<Replica, bool>,
+    pub coin_sent: bool,
+    pub coin_shares: HashMap<Replica, CoinShare>,
+    pub coin: Option<bool>,
+}
+
+/// Randomized binary agreement (Mostéfaoui, Moumen and Raynal) with a
+/// threshold common coin, as used by HoneyBadgerBFT.
+#[derive(Debug, Default)]
+pub struct BaState {
+    pub round: usize,
+    /// None until this replica provides its input
+    pub est: Option<bool>,
+    pub rounds: HashMap<usize, RoundState>,
+    pub decided: Option<bool>,
+    /// value -> replicas that decided it
+    pub terms: HashMap<bool, HashSet<Replica>>,
+    pub term_sent: bool,
+    /// 2f+1 replicas decided, so no more messages are needed
+    pub halted: bool,
+}
+
+/// Rounds of an agreement instance we keep state for ahead of our own. The
+/// instance decides in each round with probability 1/2 at least, so
+/// correct replicas are hardly ever this far apart.
+pub const MAX_ROUNDS_AHEAD: usize = 16;
+
+/// The coin of a round, tagged with the session and the protocol so that
+/// no other run or protocol sharing the threshold key flips the same coin.
+fn coin_name(session: &str, inst: Replica, round: usize) -> Vec<u8> {
+    format!("{}/acs/coin/{}/{}", session, inst, round).into_bytes()
+}
+
+impl<N: Transport<WrapperMsg>, S: Transport<SyncMsg>> Context<N, S> {
+    /// Provide our input to the agreement on `inst`'s proposal.
+    pub async fn ba_input(&mut self, inst: Replica, value: bool) {
+        let ba = self.ba.entry(inst).or_default();
+        if ba.est.is_some() {
+            return;
+        }
+        log::info!("Input {} to the agreement on node {}", value, inst);
+        ba.est = Some(value);
+        let round = ba.round;
+        self.send_bval(inst, round, value).await;
+    }
+
+    async fn send_bval(&mut self, inst: Replica, round: usize, value: bool) {
+        let rs = self.ba.entry(inst).or_default()
+            .rounds.entry(round).or_default();
+        if !rs.bval_sent.insert(value) {
+            return;
+        }
+        self.broadcast(ProtMsg::BVal(inst, round, value)).await;
+    }
+
+    async fn decide(&mut self, inst: Replica, value: bool) {
+        let ba = self.ba.entry(inst).or_default();
+        if ba.decided.is_some() {
+            return;
+        }
+        log::info!("Agreement on node {} decided {}", inst, value);
+        ba.decided = Some(value);
+        if !ba.term_sent {
+            ba.term_sent = true;
+            self.broadcast(ProtMsg::Term(inst, value)).await;
+        }
+    }
+
+    pub async fn handle_bval(&mut self, inst: Replica, round: usize, value: bool, sender: Replica) {
+        let f_plus_one = self.num_faults + 1;
+        let two_f_plus_one = 2*self.num_faults + 1;
+        let ba = self.ba.entry(inst).or_default();
+        if ba.halted {
+            return;
+        }
+        let rs = ba.rounds.entry(round).or_default();
+        let senders = rs.bvals.entry(value).or_default();
+        senders.insert(sender);
+        let count = senders.len();
+        if count >= two_f_plus_one && !rs.bin_values.contains(&value) {
+            rs.bin_values.push(value);
+        }
+        // Relay values that at least one honest replica proposed
+        if count >= f_plus_one {
+            self.send_bval(inst, round, value).await;
+        }
+    }
+
+    pub fn handle_aux(&mut self, inst: Replica, round: usize, value: bool, sender: Replica) {
+        let ba = self.ba.entry(inst).or_default();
+        if ba.halted {
+            return;
+        }
+        ba.rounds.entry(round).or_default()
+            .aux.entry(sender).or_insert(value);
+    }
+
+    pub fn handle_coin(&mut self, inst: Replica, round: usize, share: CoinShare, sender: Replica) {
+        if share.index != sender
+            || !verify_coin_share(&self.key_share.commitment, &coin_name(&self.session, inst, round), &share) {
+            log::warn!("Invalid coin share from node {}", sender);
+            self.peers.reputation.penalize(sender, Offence::Malformed, Instant::now());
+            return;
+        }
+        let ba = self.ba.entry(inst).or_default();
+        if ba.halted {
+            return;
+        }
+        ba.rounds.entry(round).or_default()
+            .coin_shares.insert(sender, share);
+    }
+
+    pub async fn handle_term(&mut self, inst: Replica, value: bool, sender: Replica) {
+        let f_plus_one = self.num_faults + 1;
+        let two_f_plus_one = 2*self.num_faults + 1;
+        let ba = self.ba.entry(inst).or_default();
+        let senders = ba.terms.entry(value).or_default();
+        senders.insert(sender);
+        let count = senders.len();
+        if count >= two_f_plus_one && !ba.halted {
+            ba.halted = true;
+        }
+        // At least one honest replica decided
+        if count >= f_plus_one {
+            self.decide(inst, value).await;
+        }
+    }
+
+    /// Run the current round of `inst` as far as the received messages allow.
+    /// Returns whether any progress was made.
+    pub async fn ba_progress(&mut self, inst: Replica) -> bool {
+        let n_minus_f = self.num_nodes - self.num_faults;
+        let f_plus_one = self.num_faults + 1;
+        let mut progress = false;
+        loop {
+            let ba = self.ba.entry(inst).or_default();
+            let est = match ba.est {
+                Some(est) if !ba.halted => est,
+                _ => return progress,
+            };
+            let round = ba.round;
+            let rs = ba.rounds.entry(round).or_default();
+            if rs.bin_values.is_empty() {
+                return progress;
+            }
+            if !rs.aux_sent {
+                rs.aux_sent = true;
+                let value = if rs.bin_values.contains(&est) { est } else { rs.bin_values[0] };
+                self.broadcast(ProtMsg::Aux(inst, round, value)).await;
+                progress = true;
+                continue;
+            }
+            let vals: Vec<bool> = rs.aux.values()
+                .filter(|v| rs.bin_values.contains(v))
+                .cloned()
+                .collect();
+            if vals.len() < n_minus_f {
+                return progress;
+            }
+            // Only release the coin once the values of this round are fixed
+            if !rs.coin_sent {
+                rs.coin_sent = true;
+                let share = coin_share(&self.key_share, &coin_name(&self.session, inst, round));
+                self.broadcast(ProtMsg::Coin(inst, round, Box::new(share))).await;
+                progress = true;
+                continue;
+            }
+            let coin = match rs.coin {
+                Some(coin) => coin,
+                None if rs.coin_shares.len() >= f_plus_one => {
+                    let shares: Vec<CoinShare> = rs.coin_shares.values()
+                        .take(f_plus_one)
+                        .cloned()
+                        .collect();
+                    let coin = coin_bit(&combine_coin(self.hash, &shares));
+                    rs.coin = Some(coin);
+                    coin
+                },
+                None => return progress,
+            };
+            let single = if vals.iter().all(|v| *v == vals[0]) { Some(vals[0]) } else { None };
+            let next_est = match single {
+                Some(value) => value,
+                None => coin,
+            };
+            ba.est = Some(next_est);
+            ba.round += 1;
+            log::debug!("Agreement on node {} moves to round {} with estimate {}", inst, round+1, next_est);
+            if single == Some(coin) {
+                self.decide(inst, coin).await;
+            }
+            self.send_bval(inst, round+1, next_est).await;
+            progress = true;
+        }
+    }
+}
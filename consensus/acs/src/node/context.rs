@@ -0,0 +1,190 @@
+use std::{collections::HashMap, net::SocketAddr, time::{SystemTime, UNIX_EPOCH}};
+
+use anyhow::{Result, anyhow};
+use config::Node;
+use consensus::Peers;
+use crypto::{hash::HashAlgorithm, vss::KeyShare};
+use network::{plaintcp::CancelHandler, Acknowledgement};
+use tokio::sync::{oneshot};
+use transport::{Slot, Transport};
+use types::{Protocol, Replica, SyncMsg, SyncState, acs::{WrapperMsg, ProtMsg}};
+
+use super::{RbcState, BaState};
+
+pub struct Context<N, S> {
+    /// Networking context
+    pub peers: Peers<WrapperMsg, N>,
+    pub sync: S,
+
+    /// Data context
+    pub num_nodes: usize,
+    pub myid: usize,
+    pub num_faults: usize,
+    pub inp_message:u64,
+    pub byz: bool,
+
+    /// Our share of the threshold key from a previous DKG run, used for the
+    /// common coin
+    pub key_share: KeyShare,
+    /// The session of this run, see `Node::session`
+    pub session: String,
+    /// The hash function the coin values are derived with
+    pub hash: HashAlgorithm,
+
+    /// ACS state
+    /// Reliable broadcast of every replica's proposal
+    pub rbc: HashMap<Replica, RbcState>,
+    /// Binary agreement on including every replica's proposal
+    pub ba: HashMap<Replica, BaState>,
+    pub acs_done: bool,
+
+    exit_rx: oneshot::Receiver<()>,
+}
+
+impl Context<Box<dyn Transport<WrapperMsg>>, Box<dyn Transport<SyncMsg>>> {
+    pub fn spawn(
+        config:Node,
+        message: u64,
+        byz: bool
+    )->anyhow::Result<oneshot::Sender<()>>{
+        // Setup networking
+        let (consensus_net, sync_net) = transport::replica_transports(&config, Protocol::Acs)?;
+        Context::spawn_with(config, message, byz, consensus_net, sync_net)
+    }
+}
+
+impl<N: Transport<WrapperMsg>, S: Transport<SyncMsg>> Context<N, S> {
+    /// Run replica `config.id` over the given transports for consensus and
+    /// syncer messages.
+    pub fn spawn_with(
+        config: Node,
+        message: u64,
+        byz: bool,
+        net: N,
+        sync: S,
+    )->anyhow::Result<oneshot::Sender<()>>{
+        let key_share = dkg::node::load_share(&config)
+            .map_err(|e| anyhow!("Failed to load the DKG key share, run the dkg protocol first: {}", e))?;
+        let (exit_tx, exit_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let mut c = Context {
+                peers: Peers::new(&config, net),
+                sync: sync,
+                num_nodes: config.num_nodes,
+                myid: config.id,
+                num_faults: config.num_faults,
+                inp_message:message,
+                byz: byz,
+                key_share: key_share,
+                session: config.session.clone(),
+                hash: config.hash,
+                rbc: HashMap::default(),
+                ba: HashMap::default(),
+                acs_done: false,
+                exit_rx: exit_rx,
+            };
+            if let Err(e) = c.run().await {
+                log::error!("Consensus error: {}", e);
+            }
+        });
+        Ok(exit_tx)
+    }
+
+    /// Send `protmsg` to every replica. It is serialized once, with a MAC
+    /// for every peer, and our own copy goes to the local queue.
+    pub async fn broadcast(&mut self, protmsg:ProtMsg){
+        let slot = self.slot(&protmsg);
+        let to: Vec<Replica> = (0..self.num_nodes)
+            // Simulates a crash fault
+            .filter(|replica| !(self.byz && replica%2 == 0))
+            .collect();
+        self.peers.multicast(protmsg, to, slot).await;
+    }
+
+    pub fn add_cancel_handler(&mut self, slot: Slot, canc: CancelHandler<Acknowledgement>){
+        self.peers.add_cancel_handler(slot, canc);
+    }
+
+    /// The instance and round `protmsg` belongs to. A reliable broadcast
+    /// and the agreement on its proposal share an instance.
+    fn slot(&self, protmsg: &ProtMsg) -> Slot {
+        match protmsg {
+            ProtMsg::RbcInit(_) => Slot::new(self.myid, 0),
+            ProtMsg::RbcEcho(inst, _) | ProtMsg::RbcReady(inst, _) => Slot::new(*inst, 0),
+            ProtMsg::BVal(inst, round, _) | ProtMsg::Aux(inst, round, _)
+                | ProtMsg::Coin(inst, round, _) => Slot::new(*inst, round + 1),
+            ProtMsg::Term(inst, _) => Slot::new(*inst, usize::MAX),
+        }
+    }
+
+    pub async fn run(&mut self)-> Result<()>{
+        // Agree on keys with the other replicas before anything else
+        self.peers.ready().await;
+        // The process starts listening to messages in this process.
+        // First, the node sends an alive message
+        let cancel_handler = self.sync.send(0,
+            SyncMsg { sender: self.myid, state: SyncState::ALIVE,value:"".to_string()}
+        ).await;
+        self.add_cancel_handler(Slot::CONTROL, cancel_handler);
+        loop {
+            self.process_pending().await;
+            tokio::select! {
+                // Receive exit handlers
+                exit_val = &mut self.exit_rx => {
+                    exit_val.map_err(anyhow::Error::new)?;
+                    log::info!("Termination signal received by the server. Exiting.");
+                    break
+                },
+                sync_msg = self.sync.recv() =>{
+                    let sync_msg = sync_msg.ok_or_else(||
+                        anyhow!("Networking layer has closed")
+                    )?;
+                    match sync_msg.state {
+                        SyncState::START =>{
+                            log::error!("Consensus Start time: {:?}", SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap()
+                                .as_millis());
+                            self.start_acs().await;
+
+                            let cancel_handler = self.sync.send(0, SyncMsg { sender: self.myid, state: SyncState::STARTED, value:"".to_string()}).await;
+                            self.add_cancel_handler(Slot::CONTROL, cancel_handler);
+                        },
+                        SyncState::STOP =>{
+                            // Code used for internal purposes
+                            log::error!("Consensus Stop time: {:?}", SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap()
+                                .as_millis());
+                            log::info!("Termination signal received by the server. Exiting.");
+                            break
+                        },
+                        _=>{}
+                    }
+                },
+                received = self.peers.receive() => received?,
+            };
+        }
+        let pending = self.peers.cancel_handlers.cancel_all();
+        log::info!("Cancelled {} unacknowledged messages", pending);
+        Ok(())
+    }
+
+    pub async fn terminate(&mut self, res: String) {
+        log::info!("terminating..");
+        log::info!("{} messages are waiting for an acknowledgement", self.peers.cancel_handlers.reap());
+        let cancel_handler = self.sync.send(0,
+            SyncMsg { sender: self.myid, state: SyncState::COMPLETED, value:res}
+        ).await;
+
+        self.add_cancel_handler(Slot::CONTROL, cancel_handler);
+    }
+}
+
+pub fn to_socket_address(
+    ip_str: &str,
+    port: u16,
+) -> std::io::Result<SocketAddr> {
+    // Takes IPv4 and IPv6 addresses and DNS names alike
+    config::resolve_host(ip_str, port)
+}
@@ -0,0 +1,15 @@
+mod context;
+pub use context::*;
+
+mod process;
+
+#[cfg(test)]
+mod sim;
+
+mod rbc;
+pub use rbc::*;
+
+mod ba;
+pub use ba::*;
+
+mod acs;
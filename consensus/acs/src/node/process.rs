@@ -0,0 +1,71 @@
+use std::time::Instant;
+
+use transport::Transport;
+use types::{Offence, Replica, SyncMsg, acs::{ProtMsg, WrapperMsg}};
+use crate::node::{
+    MAX_ROUNDS_AHEAD,
+    context::Context
+};
+impl<N: Transport<WrapperMsg>, S: Transport<SyncMsg>> Context<N, S> {
+    /// Handle our own messages and the verified ones from the network, most
+    /// urgent first.
+    pub(crate) async fn process_pending(&mut self) {
+        while let Some((protmsg, sender)) = self.peers.pop() {
+            self.handle_protmsg(protmsg, sender).await;
+        }
+    }
+
+    /// Whether `protmsg` is about the proposal of a replica and, for the
+    /// agreement on it, about a round not too far ahead of ours. We would
+    /// keep state for anything else, so it counts against its sender.
+    fn in_bounds(&mut self, protmsg: &ProtMsg, sender: Replica) -> bool {
+        let (inst, round) = match protmsg {
+            ProtMsg::RbcInit(_) => return true,
+            ProtMsg::RbcEcho(inst, _) | ProtMsg::RbcReady(inst, _) | ProtMsg::Term(inst, _) => (*inst, None),
+            ProtMsg::BVal(inst, round, _) | ProtMsg::Aux(inst, round, _)
+                | ProtMsg::Coin(inst, round, _) => (*inst, Some(*round)),
+        };
+        if inst >= self.num_nodes {
+            log::warn!("Message for unknown instance {} from node {}", inst, sender);
+            self.peers.reputation.penalize(sender, Offence::Malformed, Instant::now());
+            return false;
+        }
+        let current = self.ba.get(&inst).map_or(0, |ba| ba.round);
+        if round.is_some_and(|round| round > current + MAX_ROUNDS_AHEAD) {
+            log::warn!("Message for round {:?} of instance {} from node {}, which is at round {}", round, inst, sender, current);
+            self.peers.reputation.penalize(sender, Offence::Unexpected, Instant::now());
+            return false;
+        }
+        true
+    }
+
+    async fn handle_protmsg(&mut self, protmsg: ProtMsg, sender: Replica) {
+        if !self.in_bounds(&protmsg, sender) {
+            return;
+        }
+        match protmsg {
+            ProtMsg::RbcInit(value) => {
+                self.handle_rbc_init(value, sender).await;
+            },
+            ProtMsg::RbcEcho(origin, value) => {
+                self.handle_rbc_echo(origin, value, sender).await;
+            },
+            ProtMsg::RbcReady(origin, value) => {
+                self.handle_rbc_ready(origin, value, sender).await;
+            },
+            ProtMsg::BVal(inst, round, value) => {
+                self.handle_bval(inst, round, value, sender).await;
+            },
+            ProtMsg::Aux(inst, round, value) => {
+                self.handle_aux(inst, round, value, sender);
+            },
+            ProtMsg::Coin(inst, round, share) => {
+                self.handle_coin(inst, round, *share, sender);
+            },
+            ProtMsg::Term(inst, value) => {
+                self.handle_term(inst, value, sender).await;
+            }
+        }
+        self.acs_progress().await;
+    }
+}
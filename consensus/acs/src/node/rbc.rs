@@ -0,0 +1,68 @@
+use std::collections::{HashMap, HashSet};
+
+use transport::Transport;
+use types::{Replica, SyncMsg, acs::{ProtMsg, WrapperMsg}};
+
+use super::Context;
+
+/// Bracha's reliable broadcast of one replica's proposal.
+#[derive(Debug, Default)]
+pub struct RbcState {
+    pub echo_sent: bool,
+    pub ready_sent: bool,
+    /// value -> replicas that echoed it
+    pub echoes: HashMap<u64, HashSet<Replica>>,
+    /// value -> replicas that are ready to deliver it
+    pub readys: HashMap<u64, HashSet<Replica>>,
+    pub delivered: Option<u64>,
+}
+
+impl<N: Transport<WrapperMsg>, S: Transport<SyncMsg>> Context<N, S> {
+    pub async fn handle_rbc_init(&mut self, value: u64, origin: Replica) {
+        let rbc = self.rbc.entry(origin).or_default();
+        if rbc.echo_sent {
+            return;
+        }
+        rbc.echo_sent = true;
+        self.broadcast(ProtMsg::RbcEcho(origin, value)).await;
+    }
+
+    pub async fn handle_rbc_echo(&mut self, origin: Replica, value: u64, sender: Replica) {
+        let n_minus_f = self.num_nodes - self.num_faults;
+        let rbc = self.rbc.entry(origin).or_default();
+        // A replica echoes one value, so later echoes of the same sender
+        // count for nothing
+        if rbc.echoes.values().any(|echoers| echoers.contains(&sender)) {
+            return;
+        }
+        let echoes = rbc.echoes.entry(value).or_default();
+        echoes.insert(sender);
+        if echoes.len() >= n_minus_f && !rbc.ready_sent {
+            rbc.ready_sent = true;
+            self.broadcast(ProtMsg::RbcReady(origin, value)).await;
+        }
+    }
+
+    pub async fn handle_rbc_ready(&mut self, origin: Replica, value: u64, sender: Replica) {
+        let f_plus_one = self.num_faults + 1;
+        let two_f_plus_one = 2*self.num_faults + 1;
+        let rbc = self.rbc.entry(origin).or_default();
+        if rbc.readys.values().any(|readys| readys.contains(&sender)) {
+            return;
+        }
+        let readys = rbc.readys.entry(value).or_default();
+        readys.insert(sender);
+        let count = readys.len();
+        // Amplify readys so that every honest replica eventually delivers
+        if count >= f_plus_one && !rbc.ready_sent {
+            rbc.ready_sent = true;
+            self.broadcast(ProtMsg::RbcReady(origin, value)).await;
+        }
+        let rbc = self.rbc.entry(origin).or_default();
+        // The agreement on this proposal picks up the delivery in acs_progress
+        if count >= two_f_plus_one && rbc.delivered.is_none() {
+            rbc.delivered = Some(value);
+            log::info!("Delivered proposal {} of node {}", value, origin);
+        }
+    }
+}
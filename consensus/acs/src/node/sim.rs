@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+
+use config::Node;
+use crypto::vss::{self, KeyShare};
+use sim::{Envelope, SimNetwork, SimSender, SimTransport};
+use tokio::sync::mpsc::unbounded_channel;
+use types::{SyncMsg, SyncState};
+
+use super::Context;
+
+/// Configs for `n` replicas, with the shares of a threshold key dealt to
+/// them as a DKG run would have left them.
+fn configs(n: usize, f: usize) -> Vec<Node> {
+    let (commitment, shares) = vss::deal(vss::random_scalar(), f, n);
+    (0..n).map(|i| {
+        let mut config = Node::new();
+        config.id = i;
+        config.num_nodes = n;
+        config.num_faults = f;
+        config.session = "sim".to_string();
+        for j in 0..n {
+            // Both ends of a channel share its key
+            config.sk_map.insert(j, vec![(i.min(j)*n + i.max(j)) as u8; crypto::SECRET_KEY_SIZE].into());
+        }
+        let key_share = KeyShare { threshold: f, share: shares[i].clone(), commitment: commitment.clone() };
+        config.key_share = serde_json::to_vec(&key_share).unwrap().into();
+        config
+    }).collect()
+}
+
+/// Play the syncer for `n` simulated replicas, of which those in `silent`
+/// never come up, and return the common subset every other one output.
+async fn run(seed: u64, n: usize, f: usize, silent: &[usize]) -> Vec<Option<String>> {
+    let mut net = SimNetwork::new(seed);
+    let (sync_tx, mut sync_rx) = unbounded_channel::<Envelope<SyncMsg>>();
+    let mut starts = Vec::new();
+    let mut exits = Vec::new();
+    for (i, config) in configs(n, f).into_iter().enumerate() {
+        if silent.contains(&i) {
+            continue;
+        }
+        let (start_tx, start_rx) = unbounded_channel();
+        starts.push(start_tx);
+        exits.push(Context::spawn_with(
+            config,
+            10*(i as u64 + 1),
+            false,
+            net.register(i),
+            SimTransport::new(SimSender::new(i, sync_tx.clone()), start_rx),
+        ).unwrap());
+    }
+    let scheduler = net.spawn();
+
+    let up = starts.len();
+    let mut alive = HashSet::new();
+    let mut completed = vec![None; n];
+    while completed.iter().filter(|c| c.is_some()).count() < up {
+        let msg = sync_rx.recv().await.unwrap().open();
+        match msg.state {
+            SyncState::ALIVE => {
+                alive.insert(msg.sender);
+                if alive.len() == up {
+                    for start in &starts {
+                        start.send(SyncMsg { sender: 0, state: SyncState::START, value: "".to_string() }).unwrap();
+                    }
+                }
+            },
+            SyncState::COMPLETED => completed[msg.sender] = Some(msg.value),
+            _ => {},
+        }
+    }
+    for exit in exits {
+        let _ = exit.send(());
+    }
+    scheduler.await.unwrap();
+    completed
+}
+
+#[tokio::test]
+async fn simulated_replicas_output_the_same_subset() {
+    for seed in 0..4 {
+        let outputs = run(seed, 4, 1, &[]).await;
+        assert!(outputs.iter().all(|o| *o == outputs[0]), "seed {}: {:?}", seed, outputs);
+    }
+}
+
+#[tokio::test]
+async fn simulated_replicas_output_the_same_subset_without_a_silent_one() {
+    for seed in 0..4 {
+        let outputs: Vec<String> = run(seed, 4, 1, &[2]).await.into_iter().flatten().collect();
+        assert_eq!(outputs.len(), 3);
+        assert!(outputs.iter().all(|o| *o == outputs[0]), "seed {}: {:?}", seed, outputs);
+        // The silent replica never proposed, and everyone else's proposal is in
+        assert_eq!(outputs[0], "[Some(10), Some(20), None, Some(40)]", "seed {}", seed);
+    }
+}
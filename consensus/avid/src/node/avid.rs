@@ -0,0 +1,147 @@
+use std::{collections::{HashMap, HashSet}, time::Instant};
+
+use crypto::{hash::TaggedHash, merkle::MerkleTree};
+use transport::Transport;
+use types::{Offence, Replica, SyncMsg, avid::{Fragment, ProtMsg, WrapperMsg}};
+
+use super::{Context, encode, decode, data_shards};
+
+/// The state of one replica's erasure coded broadcast.
+#[derive(Debug, Default)]
+pub struct AvidState {
+    pub echo_sent: bool,
+    pub ready_sent: bool,
+    /// root -> fragments echoed by every replica
+    pub echoes: HashMap<TaggedHash, HashMap<Replica, Vec<u8>>>,
+    /// root -> replicas ready to deliver
+    pub readys: HashMap<TaggedHash, HashSet<Replica>>,
+    pub delivered: bool,
+}
+
+impl<N: Transport<WrapperMsg>, S: Transport<SyncMsg>> Context<N, S> {
+    /// Broadcast a payload of the configured size if we are the broadcaster.
+    pub async fn start_avid(&mut self) {
+        if self.myid != self.broadcaster {
+            return;
+        }
+        let payload: Vec<u8> = self.inp_message.to_be_bytes()
+            .iter()
+            .cycle()
+            .take(self.payload)
+            .cloned()
+            .collect();
+        let shards = encode(&payload, self.num_nodes, self.num_faults);
+        let tree = MerkleTree::from_leaves(self.hash, &shards);
+        let root = tree.root();
+        log::info!("Broadcasting {} bytes in {} fragments of {} bytes", payload.len(), shards.len(), shards[0].len());
+        for (index, shard) in shards.into_iter().enumerate() {
+            let fragment = Fragment {
+                shard,
+                proof: tree.gen_proof(index),
+            };
+            self.send(index, ProtMsg::Send(root, fragment)).await;
+        }
+    }
+
+    /// Check that `fragment` is leaf `index` of a tree over all fragments
+    /// with root `root`.
+    fn valid_fragment(&self, root: &TaggedHash, fragment: &Fragment, index: Replica) -> bool {
+        fragment.proof.index == index
+            && fragment.proof.num_leaves == self.num_nodes
+            && fragment.proof.verify(root, &fragment.shard)
+    }
+
+    pub async fn handle_send(&mut self, root: TaggedHash, fragment: Fragment, broadcaster: Replica) {
+        if !self.valid_fragment(&root, &fragment, self.myid) {
+            log::warn!("Invalid fragment from broadcaster {}", broadcaster);
+            self.peers.reputation.penalize(broadcaster, Offence::Malformed, Instant::now());
+            return;
+        }
+        let state = self.avid.entry(broadcaster).or_default();
+        if state.echo_sent {
+            return;
+        }
+        state.echo_sent = true;
+        // Only our own fragment travels further, not the whole payload
+        self.broadcast(ProtMsg::Echo(broadcaster, root, fragment)).await;
+    }
+
+    pub async fn handle_echo(&mut self, broadcaster: Replica, root: TaggedHash, fragment: Fragment, sender: Replica) {
+        if !self.valid_fragment(&root, &fragment, sender) {
+            log::warn!("Invalid echoed fragment from node {}", sender);
+            self.peers.reputation.penalize(sender, Offence::Malformed, Instant::now());
+            return;
+        }
+        let n_minus_f = self.num_nodes - self.num_faults;
+        let state = self.avid.entry(broadcaster).or_default();
+        // A replica echoes one fragment, so later echoes of the same sender,
+        // under whatever root, count for nothing
+        if state.echoes.values().any(|echoes| echoes.contains_key(&sender)) {
+            return;
+        }
+        let echoes = state.echoes.entry(root).or_default();
+        echoes.insert(sender, fragment.shard);
+        if echoes.len() >= n_minus_f && !state.ready_sent {
+            state.ready_sent = true;
+            self.broadcast(ProtMsg::Ready(broadcaster, root)).await;
+        }
+        self.try_deliver(broadcaster, root).await;
+    }
+
+    pub async fn handle_ready(&mut self, broadcaster: Replica, root: TaggedHash, sender: Replica) {
+        let f_plus_one = self.num_faults + 1;
+        let state = self.avid.entry(broadcaster).or_default();
+        if state.readys.values().any(|readys| readys.contains(&sender)) {
+            return;
+        }
+        let readys = state.readys.entry(root).or_default();
+        readys.insert(sender);
+        if readys.len() >= f_plus_one && !state.ready_sent {
+            state.ready_sent = true;
+            self.broadcast(ProtMsg::Ready(broadcaster, root)).await;
+        }
+        self.try_deliver(broadcaster, root).await;
+    }
+
+    /// Deliver once 2f+1 replicas are ready and enough fragments arrived to
+    /// decode the payload.
+    async fn try_deliver(&mut self, broadcaster: Replica, root: TaggedHash) {
+        let two_f_plus_one = 2*self.num_faults + 1;
+        let k = data_shards(self.num_nodes, self.num_faults);
+        let state = self.avid.entry(broadcaster).or_default();
+        if state.delivered {
+            return;
+        }
+        let readys = state.readys.get(&root).map(|r| r.len()).unwrap_or(0);
+        let echoes = match state.echoes.get(&root) {
+            Some(echoes) if echoes.len() >= k => echoes,
+            _ => return,
+        };
+        if readys < two_f_plus_one {
+            return;
+        }
+        state.delivered = true;
+        let mut shards = vec![None; self.num_nodes];
+        for (index, shard) in echoes.iter() {
+            shards[*index] = Some(shard.clone());
+        }
+        let payload = decode(shards, self.num_nodes, self.num_faults);
+        // A faulty broadcaster could have committed to fragments that are not
+        // a codeword; re-encoding detects this, and all honest replicas then
+        // agree on delivering nothing.
+        let res = match payload {
+            Some(payload) if MerkleTree::from_leaves(self.hash, &encode(&payload, self.num_nodes, self.num_faults)).root() == root => {
+                log::info!("Delivered {} bytes from broadcaster {}", payload.len(), broadcaster);
+                self.hash.hash(&payload)
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect()
+            },
+            _ => {
+                log::error!("Broadcaster {} sent fragments that do not decode consistently", broadcaster);
+                "invalid".to_string()
+            }
+        };
+        self.terminate(res).await;
+    }
+}
@@ -0,0 +1,78 @@
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+/// The number of fragments needed to recover a payload, so that f faulty
+/// replicas cannot withhold it and f more cannot lie about it.
+pub fn data_shards(num_nodes: usize, num_faults: usize) -> usize {
+    num_nodes - 2*num_faults
+}
+
+/// Split `payload` into `num_nodes` Reed-Solomon fragments of which any
+/// `data_shards` recover it. The payload length is prepended so that the
+/// padding of the last data fragment can be stripped.
+pub fn encode(payload: &[u8], num_nodes: usize, num_faults: usize) -> Vec<Vec<u8>> {
+    let k = data_shards(num_nodes, num_faults);
+    let mut data = (payload.len() as u64).to_be_bytes().to_vec();
+    data.extend_from_slice(payload);
+    let shard_len = data.len().div_ceil(k);
+    data.resize(shard_len*k, 0);
+
+    let mut shards: Vec<Vec<u8>> = data.chunks(shard_len)
+        .map(|c| c.to_vec())
+        .collect();
+    shards.resize(num_nodes, vec![0; shard_len]);
+    if num_nodes > k {
+        ReedSolomon::new(k, num_nodes - k)
+            .expect("invalid number of fragments")
+            .encode(&mut shards)
+            .expect("fragments have the same length");
+    }
+    shards
+}
+
+/// Recover the payload from any `data_shards` fragments. Returns `None` if
+/// there are too few fragments or they are inconsistent.
+pub fn decode(mut shards: Vec<Option<Vec<u8>>>, num_nodes: usize, num_faults: usize) -> Option<Vec<u8>> {
+    let k = data_shards(num_nodes, num_faults);
+    if num_nodes > k {
+        ReedSolomon::new(k, num_nodes - k)
+            .ok()?
+            .reconstruct_data(&mut shards)
+            .ok()?;
+    }
+    let mut data = Vec::new();
+    for shard in shards.into_iter().take(k) {
+        data.extend(shard?);
+    }
+    if data.len() < 8 {
+        return None;
+    }
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&data[..8]);
+    let len = u64::from_be_bytes(len) as usize;
+    if len > data.len() - 8 {
+        return None;
+    }
+    Some(data[8..8+len].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn avid_decode_from_any_data_shards() {
+        let payload: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let shards = encode(&payload, 7, 2);
+        assert_eq!(shards.len(), 7);
+
+        let mut partial: Vec<Option<Vec<u8>>> = shards.into_iter().map(Some).collect();
+        partial[0] = None;
+        partial[2] = None;
+        partial[3] = None;
+        partial[6] = None;
+        assert_eq!(decode(partial.clone(), 7, 2), Some(payload));
+
+        partial[5] = None;
+        assert_eq!(decode(partial, 7, 2), None);
+    }
+}
@@ -0,0 +1,13 @@
+mod context;
+pub use context::*;
+
+mod process;
+
+mod coding;
+pub use coding::*;
+
+mod avid;
+pub use avid::*;
+
+#[cfg(test)]
+mod sim;
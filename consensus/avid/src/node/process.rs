@@ -0,0 +1,45 @@
+use std::time::Instant;
+
+use transport::Transport;
+use types::{Offence, Replica, SyncMsg, avid::{ProtMsg, WrapperMsg}};
+use crate::node::{
+    context::Context
+};
+impl<N: Transport<WrapperMsg>, S: Transport<SyncMsg>> Context<N, S> {
+    /// Handle our own messages and the verified ones from the network, most
+    /// urgent first.
+    pub(crate) async fn process_pending(&mut self) {
+        while let Some((protmsg, sender)) = self.peers.pop() {
+            self.handle_protmsg(protmsg, sender).await;
+        }
+    }
+
+    async fn handle_protmsg(&mut self, protmsg: ProtMsg, sender: Replica) {
+        // We would keep state for a broadcast by a replica that does not exist
+        if let ProtMsg::Echo(broadcaster, ..) | ProtMsg::Ready(broadcaster, _) = protmsg {
+            if broadcaster >= self.num_nodes {
+                log::warn!("Message for unknown broadcaster {} from node {}", broadcaster, sender);
+                self.peers.reputation.penalize(sender, Offence::Malformed, Instant::now());
+                return;
+            }
+        }
+        // Roots under another hash function commit to no tree of ours
+        let (ProtMsg::Send(root, _) | ProtMsg::Echo(_, root, _) | ProtMsg::Ready(_, root)) = &protmsg;
+        if root.alg != self.hash {
+            log::warn!("Root under {:?} from node {}", root.alg, sender);
+            self.peers.reputation.penalize(sender, Offence::Malformed, Instant::now());
+            return;
+        }
+        match protmsg {
+            ProtMsg::Send(root, fragment) => {
+                self.handle_send(root, fragment, sender).await;
+            },
+            ProtMsg::Echo(broadcaster, root, fragment) => {
+                self.handle_echo(broadcaster, root, fragment, sender).await;
+            },
+            ProtMsg::Ready(broadcaster, root) => {
+                self.handle_ready(broadcaster, root, sender).await;
+            }
+        }
+    }
+}
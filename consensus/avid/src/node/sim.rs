@@ -0,0 +1,103 @@
+use std::collections::HashSet;
+
+use config::Node;
+use crypto::hash::{Algorithms, HashAlgorithm, MacAlgorithm};
+use sim::{Envelope, SimNetwork, SimSender, SimTransport};
+use tokio::sync::mpsc::unbounded_channel;
+use types::{SyncMsg, SyncState};
+
+use super::Context;
+
+const MESSAGE: u64 = 42;
+const PAYLOAD: usize = 1000;
+/// Not the defaults, which every replica must be handed
+const ALGORITHMS: Algorithms = Algorithms { hash: HashAlgorithm::Blake3, mac: MacAlgorithm::Blake3 };
+
+fn configs(n: usize, f: usize) -> Vec<Node> {
+    (0..n).map(|i| {
+        let mut config = Node::new();
+        config.id = i;
+        config.num_nodes = n;
+        config.num_faults = f;
+        config.payload = PAYLOAD;
+        config.hash = ALGORITHMS.hash;
+        config.mac = ALGORITHMS.mac;
+        for j in 0..n {
+            // Both ends of a channel share its key
+            config.sk_map.insert(j, vec![(i.min(j)*n + i.max(j)) as u8; crypto::SECRET_KEY_SIZE].into());
+        }
+        config
+    }).collect()
+}
+
+/// Play the syncer for `n` simulated replicas, of which those in `silent`
+/// never come up, and return the digest of the payload every other one
+/// retrieved from the fragments of replica 0.
+async fn run(seed: u64, n: usize, f: usize, silent: &[usize]) -> Vec<Option<String>> {
+    let mut net = SimNetwork::new(seed);
+    let (sync_tx, mut sync_rx) = unbounded_channel::<Envelope<SyncMsg>>();
+    let mut starts = Vec::new();
+    let mut exits = Vec::new();
+    for (i, config) in configs(n, f).into_iter().enumerate() {
+        if silent.contains(&i) {
+            continue;
+        }
+        let (start_tx, start_rx) = unbounded_channel();
+        starts.push(start_tx);
+        exits.push(Context::spawn_with(
+            config,
+            MESSAGE,
+            false,
+            net.register(i),
+            SimTransport::new(SimSender::new(i, sync_tx.clone()), start_rx),
+        ).unwrap());
+    }
+    let scheduler = net.spawn();
+
+    let up = starts.len();
+    let mut alive = HashSet::new();
+    let mut completed = vec![None; n];
+    while completed.iter().filter(|c| c.is_some()).count() < up {
+        let msg = sync_rx.recv().await.unwrap().open();
+        match msg.state {
+            SyncState::ALIVE => {
+                alive.insert(msg.sender);
+                if alive.len() == up {
+                    for start in &starts {
+                        start.send(SyncMsg { sender: 0, state: SyncState::START, value: "".to_string() }).unwrap();
+                    }
+                }
+            },
+            SyncState::COMPLETED => completed[msg.sender] = Some(msg.value),
+            _ => {},
+        }
+    }
+    for exit in exits {
+        let _ = exit.send(());
+    }
+    scheduler.await.unwrap();
+    completed
+}
+
+/// The digest that replicas report for the payload of the broadcaster.
+fn expected() -> String {
+    let payload: Vec<u8> = MESSAGE.to_be_bytes().iter().cycle().take(PAYLOAD).cloned().collect();
+    ALGORITHMS.hash.hash(&payload).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[tokio::test]
+async fn simulated_replicas_retrieve_the_dispersed_payload() {
+    for seed in 0..4 {
+        let outputs = run(seed, 4, 1, &[]).await;
+        assert!(outputs.iter().all(|o| o.as_deref() == Some(expected().as_str())), "seed {}: {:?}", seed, outputs);
+    }
+}
+
+#[tokio::test]
+async fn simulated_replicas_retrieve_the_dispersed_payload_without_a_silent_one() {
+    for seed in 4..6 {
+        let outputs = run(seed, 4, 1, &[3]).await;
+        assert_eq!(outputs[3], None);
+        assert!(outputs[..3].iter().all(|o| o.as_deref() == Some(expected().as_str())), "seed {}: {:?}", seed, outputs);
+    }
+}
@@ -0,0 +1,216 @@
+use std::{collections::{HashMap, HashSet}, net::SocketAddr, time::{SystemTime, UNIX_EPOCH}};
+
+use anyhow::{Result, anyhow};
+use config::{Node, SecretStore};
+use consensus::Peers;
+use crypto::{hash::{HashAlgorithm, TaggedHash}, vss::{Commitment, Share}};
+use network::{plaintcp::CancelHandler, Acknowledgement};
+use tokio::{sync::{oneshot}, time::{Instant, sleep_until}};
+use transport::{Slot, Transport};
+use types::{Protocol, Replica, SyncMsg, SyncState, dkg::{WrapperMsg, ProtMsg}};
+
+use super::{Fault, Phase, QualState};
+
+pub struct Context<N, S> {
+    /// Networking context
+    pub peers: Peers<WrapperMsg, N>,
+    pub sync: S,
+
+    /// Data context
+    pub num_nodes: usize,
+    pub myid: usize,
+    pub num_faults: usize,
+    /// Worst case network delay in milliseconds, used to time the phases
+    pub delta: u64,
+    /// The hash function of the commitment digests
+    pub hash: HashAlgorithm,
+    pub fault: Fault,
+    /// The keystore our key share goes to
+    pub store: SecretStore,
+
+    /// DKG state
+    pub phase: Phase,
+    pub phase_deadline: Instant,
+    /// Commitment received from every dealer
+    pub commitments: HashMap<Replica, Commitment>,
+    /// Our share from every dealer
+    pub shares: HashMap<Replica, Share>,
+    /// dealer -> commitment digest -> echoing replicas
+    pub echoes: HashMap<Replica, HashMap<TaggedHash, HashSet<Replica>>>,
+    /// dealer -> replicas that complained about it
+    pub complaints: HashMap<Replica, HashSet<Replica>>,
+    /// dealer -> complaints answered with a valid reveal
+    pub answered: HashMap<Replica, HashSet<Replica>>,
+    /// Dealers caught cheating
+    pub disqualified: HashSet<Replica>,
+    /// Dealers we have already complained about
+    pub complained: HashSet<Replica>,
+    /// Our own sharing, kept to answer complaints
+    pub dealt: Option<(Commitment, Vec<Share>)>,
+    /// The agreement on the qualified dealers
+    pub qual: QualState,
+
+    exit_rx: oneshot::Receiver<()>,
+}
+
+impl Context<Box<dyn Transport<WrapperMsg>>, Box<dyn Transport<SyncMsg>>> {
+    pub fn spawn(
+        config:Node,
+        store: SecretStore,
+        byz: bool
+    )->anyhow::Result<oneshot::Sender<()>>{
+        // Setup networking
+        let (consensus_net, sync_net) = transport::replica_transports(&config, Protocol::Dkg)?;
+        let fault = if byz { Fault::BadShares } else { Fault::Honest };
+        Context::spawn_with(config, store, fault, consensus_net, sync_net)
+    }
+}
+
+impl<N: Transport<WrapperMsg>, S: Transport<SyncMsg>> Context<N, S> {
+    /// Run replica `config.id` over the given transports for consensus and
+    /// syncer messages, keeping the key share in `store`.
+    pub fn spawn_with(
+        config: Node,
+        store: SecretStore,
+        fault: Fault,
+        net: N,
+        sync: S,
+    )->anyhow::Result<oneshot::Sender<()>>{
+        // Deals carry secret shares, so they are always encrypted
+        let mut config = config;
+        config.confidential = true;
+        let (exit_tx, exit_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let mut c = Context {
+                peers: Peers::new(&config, net),
+                sync: sync,
+                num_nodes: config.num_nodes,
+                myid: config.id,
+                num_faults: config.num_faults,
+                delta: config.delta,
+                hash: config.hash,
+                fault,
+                store,
+                phase: Phase::Idle,
+                phase_deadline: Instant::now(),
+                commitments: HashMap::default(),
+                shares: HashMap::default(),
+                echoes: HashMap::default(),
+                complaints: HashMap::default(),
+                answered: HashMap::default(),
+                disqualified: HashSet::default(),
+                complained: HashSet::default(),
+                dealt: None,
+                qual: QualState::default(),
+                exit_rx: exit_rx,
+            };
+            if let Err(e) = c.run().await {
+                log::error!("Consensus error: {}", e);
+            }
+        });
+        Ok(exit_tx)
+    }
+
+    /// Send `protmsg` to every replica. It is serialized once, with a MAC
+    /// for every peer, and our own copy goes to the local queue.
+    pub async fn broadcast(&mut self, protmsg:ProtMsg){
+        let slot = self.slot(&protmsg);
+        self.peers.broadcast(protmsg, slot).await;
+    }
+
+    pub fn add_cancel_handler(&mut self, slot: Slot, canc: CancelHandler<Acknowledgement>){
+        self.peers.add_cancel_handler(slot, canc);
+    }
+
+    /// The instance `protmsg` belongs to, named after its dealer. The
+    /// agreement on the qualified dealers is an instance of its own, after
+    /// those of the dealers, with a round for each attempt.
+    fn slot(&self, protmsg: &ProtMsg) -> Slot {
+        match protmsg {
+            ProtMsg::Deal(..) | ProtMsg::Reveal(..) => Slot::new(self.myid, 0),
+            ProtMsg::Echo(dealer, _) | ProtMsg::Complaint(dealer) => Slot::new(*dealer, 0),
+            ProtMsg::Propose(attempt, _) | ProtMsg::QualEcho(attempt, _) => Slot::new(self.num_nodes, *attempt),
+            ProtMsg::QualReady(_) => Slot::new(self.num_nodes, self.num_nodes),
+        }
+    }
+
+    pub async fn send(&mut self,replica:Replica, protmsg:ProtMsg){
+        let slot = self.slot(&protmsg);
+        self.peers.send(replica, protmsg, slot).await;
+    }
+
+    pub async fn run(&mut self)-> Result<()>{
+        // Agree on keys with the other replicas before anything else
+        self.peers.ready().await;
+        // The process starts listening to messages in this process.
+        // First, the node sends an alive message
+        let cancel_handler = self.sync.send(0,
+            SyncMsg { sender: self.myid, state: SyncState::ALIVE,value:"".to_string()}
+        ).await;
+        self.add_cancel_handler(Slot::CONTROL, cancel_handler);
+        loop {
+            self.process_pending().await;
+            tokio::select! {
+                // Receive exit handlers
+                exit_val = &mut self.exit_rx => {
+                    exit_val.map_err(anyhow::Error::new)?;
+                    log::info!("Termination signal received by the server. Exiting.");
+                    break
+                },
+                // Move to the next phase once the current one has timed out
+                _ = sleep_until(self.phase_deadline), if self.phase.is_timed() => {
+                    self.next_phase().await;
+                },
+                sync_msg = self.sync.recv() =>{
+                    let sync_msg = sync_msg.ok_or_else(||
+                        anyhow!("Networking layer has closed")
+                    )?;
+                    match sync_msg.state {
+                        SyncState::START =>{
+                            log::error!("Consensus Start time: {:?}", SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap()
+                                .as_millis());
+                            self.start_dkg().await;
+
+                            let cancel_handler = self.sync.send(0, SyncMsg { sender: self.myid, state: SyncState::STARTED, value:"".to_string()}).await;
+                            self.add_cancel_handler(Slot::CONTROL, cancel_handler);
+                        },
+                        SyncState::STOP =>{
+                            // Code used for internal purposes
+                            log::error!("Consensus Stop time: {:?}", SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap()
+                                .as_millis());
+                            log::info!("Termination signal received by the server. Exiting.");
+                            break
+                        },
+                        _=>{}
+                    }
+                },
+                received = self.peers.receive() => received?,
+            };
+        }
+        let pending = self.peers.cancel_handlers.cancel_all();
+        log::info!("Cancelled {} unacknowledged messages", pending);
+        Ok(())
+    }
+
+    pub async fn terminate(&mut self, res: String) {
+        log::info!("terminating..");
+        log::info!("{} messages are waiting for an acknowledgement", self.peers.cancel_handlers.reap());
+        let cancel_handler = self.sync.send(0,
+            SyncMsg { sender: self.myid, state: SyncState::COMPLETED, value:res}
+        ).await;
+
+        self.add_cancel_handler(Slot::CONTROL, cancel_handler);
+    }
+}
+
+pub fn to_socket_address(
+    ip_str: &str,
+    port: u16,
+) -> std::io::Result<SocketAddr> {
+    // Takes IPv4 and IPv6 addresses and DNS names alike
+    config::resolve_host(ip_str, port)
+}
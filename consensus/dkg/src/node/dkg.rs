@@ -0,0 +1,492 @@
+use std::{collections::{HashMap, HashSet}, time::Duration};
+
+use config::Node;
+use crypto::{hash::{HashAlgorithm, TaggedHash}, vss::{self, Commitment, KeyShare, Scalar, Share}};
+use tokio::time::Instant;
+use transport::Transport;
+use types::{Offence, Replica, SyncMsg, dkg::{ProtMsg, Qualified, WrapperMsg}};
+
+use super::Context;
+
+/// The phases of the (synchronous) joint-Feldman DKG. Every timed phase
+/// lasts a few multiples of `delta`, the worst case network delay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Waiting for the syncer to start the protocol
+    Idle,
+    /// Dealing our sharing and collecting the sharings of everyone else
+    Dealing,
+    /// Collecting complaints and the dealers' answers to them
+    Complaining,
+    /// Agreeing on the qualified dealers, and fetching the shares of the
+    /// ones that never delivered ours
+    Agreeing,
+    /// The key has been generated (or generation failed)
+    Done,
+}
+
+impl Phase {
+    pub fn is_timed(&self) -> bool {
+        matches!(self, Phase::Dealing | Phase::Complaining | Phase::Agreeing)
+    }
+}
+
+/// Network delays an attempt at agreeing on the qualified dealers lasts:
+/// one for the proposal, one for the echoes and one for the readies, and
+/// one more as the replicas start their attempts apart.
+pub const ATTEMPT_DELTAS: u64 = 4;
+/// Network delays to fetch the shares of the agreed dealers that are
+/// missing here, a complaint and its answer.
+pub const FETCH_DELTAS: u64 = 2;
+
+/// How a replica deals its sharing, to test the others against faulty
+/// dealers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    Honest,
+    /// Hands the replicas with an even index shares that do not match
+    /// the commitment
+    BadShares,
+    /// Hands the next replica a sharing of another secret
+    Equivocating,
+}
+
+/// The agreement on the qualified dealers, and on the commitment of each.
+/// Every attempt is a reliable broadcast (Bracha) of the set its proposer
+/// puts forward, and attempts rotate over the replicas until one delivers.
+/// A replica is ready for one set only, whatever the attempt, so no two
+/// sets gather `n - f` readies.
+#[derive(Debug, Default)]
+pub struct QualState {
+    /// The attempt under way, proposed by replica `attempt % n`
+    pub attempt: usize,
+    /// Attempts whose proposal we considered
+    pub echoed: HashSet<usize>,
+    /// The set we are ready to deliver
+    pub ready: Option<Qualified>,
+    /// (attempt, set) -> replicas that echoed it
+    pub echoes: HashMap<(usize, Qualified), HashSet<Replica>>,
+    /// set -> replicas that are ready to deliver it
+    pub readies: HashMap<Qualified, HashSet<Replica>>,
+    pub delivered: Option<Qualified>,
+}
+
+/// The key share a previous DKG run left in the keystore of `config`.
+pub fn load_share(config: &Node) -> anyhow::Result<KeyShare> {
+    if config.key_share.is_empty() {
+        return Err(anyhow::anyhow!("The keystore holds no key share"));
+    }
+    let share: KeyShare = serde_json::from_slice(&config.key_share)?;
+    if !share.is_valid() {
+        return Err(anyhow::anyhow!("The stored key share does not match its commitment"));
+    }
+    Ok(share)
+}
+
+fn commitment_digest(alg: HashAlgorithm, comm: &Commitment) -> TaggedHash {
+    alg.digest(&comm.to_bytes())
+}
+
+impl<N: Transport<WrapperMsg>, S: Transport<SyncMsg>> Context<N, S> {
+    fn set_phase(&mut self, phase: Phase, deltas: u64) {
+        self.phase = phase;
+        self.phase_deadline = Instant::now() + Duration::from_millis(deltas*self.delta);
+    }
+
+    fn proposer(&self, attempt: usize) -> Replica {
+        attempt % self.num_nodes
+    }
+
+    /// Act as a dealer: share a fresh random secret with every replica.
+    pub async fn start_dkg(&mut self) {
+        if self.phase != Phase::Idle {
+            return;
+        }
+        self.set_phase(Phase::Dealing, 2);
+        let (comm, shares) = vss::deal(vss::random_scalar(), self.num_faults, self.num_nodes);
+        for share in shares.iter() {
+            let mut comm = comm.clone();
+            let mut share = share.clone();
+            match self.fault {
+                Fault::BadShares if share.index%2 == 0 => {
+                    share.value += Scalar::one();
+                },
+                Fault::Equivocating if share.index == (self.myid + 1)%self.num_nodes => {
+                    let (other_comm, other_shares) = vss::deal(vss::random_scalar(), self.num_faults, self.num_nodes);
+                    comm = other_comm;
+                    share = other_shares[share.index].clone();
+                },
+                _ => {},
+            }
+            self.send(share.index, ProtMsg::Deal(comm, share)).await;
+        }
+        self.dealt = Some((comm, shares));
+    }
+
+    pub async fn next_phase(&mut self) {
+        match self.phase {
+            Phase::Dealing => {
+                // Complain about every dealer that did not send us a valid share in time
+                for dealer in 0..self.num_nodes {
+                    if !self.shares.contains_key(&dealer) {
+                        self.complain(dealer).await;
+                    }
+                }
+                self.set_phase(Phase::Complaining, 3);
+            },
+            Phase::Complaining => {
+                if self.qual.delivered.is_some() {
+                    // The others agreed while we were still complaining
+                    self.set_phase(Phase::Agreeing, FETCH_DELTAS);
+                    return;
+                }
+                self.set_phase(Phase::Agreeing, ATTEMPT_DELTAS);
+                self.propose_qual().await;
+            },
+            Phase::Agreeing => {
+                if self.qual.delivered.is_none() && self.qual.attempt + 1 < self.num_nodes {
+                    // The proposer failed us, so try the next one
+                    self.qual.attempt += 1;
+                    log::warn!("No agreement on the qualified dealers yet, trying node {}", self.proposer(self.qual.attempt));
+                    self.set_phase(Phase::Agreeing, ATTEMPT_DELTAS);
+                    self.propose_qual().await;
+                    return;
+                }
+                self.phase = Phase::Done;
+                let e = match &self.qual.delivered {
+                    None => anyhow::anyhow!("No agreement on the qualified dealers"),
+                    Some(qual) => anyhow::anyhow!("Qualified dealers {:?} never delivered our share", self.missing(qual)),
+                };
+                log::error!("DKG failed: {}", e);
+                self.terminate("failed".to_string()).await;
+            },
+            _ => {}
+        }
+    }
+
+    async fn complain(&mut self, dealer: Replica) {
+        if !self.complained.insert(dealer) {
+            return;
+        }
+        log::warn!("Complaining about dealer {}", dealer);
+        self.broadcast(ProtMsg::Complaint(dealer)).await;
+    }
+
+    pub async fn handle_deal(&mut self, comm: Commitment, share: Share, dealer: Replica) {
+        if self.phase == Phase::Done || self.commitments.contains_key(&dealer) {
+            return;
+        }
+        log::info!("Received deal from node {}", dealer);
+        let valid = share.index == self.myid
+            && comm.degree() == self.num_faults
+            && comm.verify_share(&share);
+        let digest = commitment_digest(self.hash, &comm);
+        self.commitments.insert(dealer, comm);
+        if valid {
+            self.shares.insert(dealer, share);
+            self.broadcast(ProtMsg::Echo(dealer, digest)).await;
+        }
+        else {
+            log::warn!("Invalid share from dealer {}", dealer);
+            self.peers.reputation.penalize(dealer, Offence::Malformed, std::time::Instant::now());
+            self.complain(dealer).await;
+        }
+    }
+
+    pub fn handle_echo(&mut self, dealer: Replica, digest: TaggedHash, sender: Replica) {
+        if dealer >= self.num_nodes {
+            log::warn!("Node {} echoed unknown dealer {}", sender, dealer);
+            self.peers.reputation.penalize(sender, Offence::Malformed, std::time::Instant::now());
+            return;
+        }
+        let echoes = self.echoes.entry(dealer).or_default();
+        // A replica echoes one commitment of each dealer
+        if echoes.values().any(|echoers| echoers.contains(&sender)) {
+            return;
+        }
+        echoes.entry(digest).or_default().insert(sender);
+    }
+
+    pub async fn handle_complaint(&mut self, dealer: Replica, complainer: Replica) {
+        if dealer >= self.num_nodes {
+            log::warn!("Node {} complained about unknown dealer {}", complainer, dealer);
+            self.peers.reputation.penalize(complainer, Offence::Malformed, std::time::Instant::now());
+            return;
+        }
+        log::info!("Node {} complained about dealer {}", complainer, dealer);
+        if self.phase != Phase::Done {
+            self.complaints
+                .entry(dealer)
+                .or_default()
+                .insert(complainer);
+        }
+        if dealer != self.myid {
+            return;
+        }
+        // Answer the complaint by opening the complainer's share to everyone,
+        // even once done, as a replica that misses our share needs it to
+        // finish
+        let reveal = self.dealt.as_ref()
+            .and_then(|(comm, shares)| shares.get(complainer)
+                .map(|s| ProtMsg::Reveal(comm.clone(), s.clone())));
+        if let Some(reveal) = reveal {
+            self.broadcast(reveal).await;
+        }
+    }
+
+    pub async fn handle_reveal(&mut self, comm: Commitment, share: Share, dealer: Replica) {
+        if self.phase == Phase::Done {
+            return;
+        }
+        if comm.degree() != self.num_faults || !comm.verify_share(&share) {
+            log::warn!("Dealer {} revealed an invalid share", dealer);
+            self.peers.reputation.penalize(dealer, Offence::Malformed, std::time::Instant::now());
+            self.disqualified.insert(dealer);
+            return;
+        }
+        let agreed = self.qual.delivered.as_ref()
+            .and_then(|qual| qual.iter().find(|(d, _)| *d == dealer))
+            .map(|(_, digest)| *digest);
+        match (agreed, self.commitments.get(&dealer)) {
+            // Once the dealers are agreed on, only the agreed commitment
+            // counts, whatever the dealer sent us first
+            (Some(digest), _) if commitment_digest(self.hash, &comm) != digest => {
+                log::warn!("Dealer {} revealed a commitment other than the agreed one", dealer);
+                return;
+            },
+            (Some(_), known) => {
+                if known != Some(&comm) {
+                    self.shares.remove(&dealer);
+                    self.commitments.insert(dealer, comm);
+                }
+            },
+            (None, Some(known)) if *known != comm => {
+                log::warn!("Dealer {} revealed a different commitment", dealer);
+                self.disqualified.insert(dealer);
+                return;
+            },
+            (None, Some(_)) => {},
+            (None, None) => {
+                self.commitments.insert(dealer, comm);
+            }
+        }
+        self.answered
+            .entry(dealer)
+            .or_default()
+            .insert(share.index);
+        if share.index == self.myid {
+            self.shares.insert(dealer, share);
+            self.try_finalize().await;
+        }
+    }
+
+    /// Whether `qual` names distinct replicas, and no more than there are.
+    fn well_formed(&self, qual: &Qualified) -> bool {
+        let distinct: HashSet<&Replica> = qual.iter().map(|(dealer, _)| dealer).collect();
+        distinct.len() == qual.len() && qual.iter().all(|(dealer, _)| *dealer < self.num_nodes)
+    }
+
+    /// Whether `qual` is a set of qualified dealers we can vouch for: more
+    /// than `num_faults` dealers, each with the commitment that more than
+    /// `num_faults` replicas echoed to us, so that a correct replica holds
+    /// it, and none caught cheating.
+    fn acceptable(&self, qual: &Qualified) -> bool {
+        self.well_formed(qual)
+            && qual.len() > self.num_faults
+            && qual.iter().all(|(dealer, digest)| {
+                !self.disqualified.contains(dealer)
+                    && self.echoes.get(dealer)
+                        .and_then(|e| e.get(digest))
+                        .is_some_and(|e| e.len() > self.num_faults)
+            })
+    }
+
+    /// Propose the qualified dealers if the attempt under way is ours: the
+    /// set we are ready for if there is one, as it may be delivered
+    /// somewhere already, or else the dealers that qualify in our view.
+    async fn propose_qual(&mut self) {
+        let attempt = self.qual.attempt;
+        if self.proposer(attempt) != self.myid {
+            return;
+        }
+        let qual = match &self.qual.ready {
+            Some(qual) => qual.clone(),
+            None => self.qualified(),
+        };
+        log::info!("Proposing qualified dealers {:?}", qual);
+        self.broadcast(ProtMsg::Propose(attempt, qual)).await;
+    }
+
+    pub async fn handle_propose(&mut self, attempt: usize, qual: Qualified, sender: Replica) {
+        if attempt >= self.num_nodes || sender != self.proposer(attempt) {
+            log::warn!("Node {} proposed qualified dealers, but does not propose attempt {}", sender, attempt);
+            self.peers.reputation.penalize(sender, Offence::Unexpected, std::time::Instant::now());
+            return;
+        }
+        if self.phase == Phase::Done || !self.qual.echoed.insert(attempt) {
+            return;
+        }
+        if !self.acceptable(&qual) {
+            log::warn!("Not echoing the proposed qualified dealers {:?}", qual);
+            return;
+        }
+        self.broadcast(ProtMsg::QualEcho(attempt, qual)).await;
+    }
+
+    pub async fn handle_qual_echo(&mut self, attempt: usize, qual: Qualified, sender: Replica) {
+        if attempt >= self.num_nodes || !self.well_formed(&qual) {
+            log::warn!("Malformed echo of qualified dealers from node {}", sender);
+            self.peers.reputation.penalize(sender, Offence::Malformed, std::time::Instant::now());
+            return;
+        }
+        // A replica echoes once an attempt, so later echoes of the same
+        // sender count for nothing
+        if self.qual.echoes.iter().any(|((a, _), echoers)| *a == attempt && echoers.contains(&sender)) {
+            return;
+        }
+        let echoers = self.qual.echoes.entry((attempt, qual.clone())).or_default();
+        echoers.insert(sender);
+        if echoers.len() >= self.num_nodes - self.num_faults {
+            self.send_qual_ready(qual).await;
+        }
+    }
+
+    pub async fn handle_qual_ready(&mut self, qual: Qualified, sender: Replica) {
+        if !self.well_formed(&qual) {
+            log::warn!("Malformed ready for qualified dealers from node {}", sender);
+            self.peers.reputation.penalize(sender, Offence::Malformed, std::time::Instant::now());
+            return;
+        }
+        if self.qual.readies.values().any(|readies| readies.contains(&sender)) {
+            return;
+        }
+        let readies = self.qual.readies.entry(qual.clone()).or_default();
+        readies.insert(sender);
+        let count = readies.len();
+        if count > self.num_faults {
+            self.send_qual_ready(qual.clone()).await;
+        }
+        if count >= self.num_nodes - self.num_faults && self.qual.delivered.is_none() {
+            self.deliver_qual(qual).await;
+        }
+    }
+
+    async fn send_qual_ready(&mut self, qual: Qualified) {
+        if self.qual.ready.is_some() {
+            return;
+        }
+        self.qual.ready = Some(qual.clone());
+        self.broadcast(ProtMsg::QualReady(qual)).await;
+    }
+
+    /// The agreed dealers whose share we miss, or whose commitment differs
+    /// from the agreed one here.
+    fn missing(&self, qual: &Qualified) -> Vec<Replica> {
+        qual.iter()
+            .filter(|(dealer, digest)| !self.shares.contains_key(dealer)
+                || self.commitments.get(dealer).map(|comm| commitment_digest(self.hash, comm)) != Some(*digest))
+            .map(|(dealer, _)| *dealer)
+            .collect()
+    }
+
+    /// Every replica delivers the same set. Ask the dealers in it that never
+    /// delivered our share, or dealt us another commitment than the agreed
+    /// one, to reveal our share under the agreed commitment.
+    async fn deliver_qual(&mut self, qual: Qualified) {
+        log::info!("Agreed on qualified dealers {:?}", qual);
+        let missing = self.missing(&qual);
+        self.qual.delivered = Some(qual);
+        if self.phase == Phase::Agreeing {
+            self.set_phase(Phase::Agreeing, FETCH_DELTAS);
+        }
+        for dealer in missing {
+            self.shares.remove(&dealer);
+            self.commitments.remove(&dealer);
+            // We may have complained before, about a share that was missing then
+            self.complained.remove(&dealer);
+            self.complain(dealer).await;
+        }
+        self.try_finalize().await;
+    }
+
+    /// Finish once the set of qualified dealers is agreed on and we have our
+    /// share from each of them, under the agreed commitment.
+    async fn try_finalize(&mut self) {
+        if self.phase == Phase::Done {
+            return;
+        }
+        let qual = match &self.qual.delivered {
+            Some(qual) if self.missing(qual).is_empty() => qual.clone(),
+            _ => return,
+        };
+        self.phase = Phase::Done;
+        let res = match self.finalize(&qual) {
+            Ok(key) => key,
+            Err(e) => {
+                log::error!("DKG failed: {}", e);
+                "failed".to_string()
+            }
+        };
+        self.terminate(res).await;
+    }
+
+    /// The dealers that qualify in our view, with the commitment that
+    /// `n - f` replicas echoed, for us to propose.
+    fn qualified(&mut self) -> Qualified {
+        let mut qual = Vec::new();
+        let no_complaints = HashSet::default();
+        for dealer in 0..self.num_nodes {
+            if self.disqualified.contains(&dealer) {
+                continue;
+            }
+            let digest = self.echoes.get(&dealer)
+                .and_then(|e| e.iter().find(|(_, echoers)| echoers.len() >= self.num_nodes - self.num_faults))
+                .map(|(digest, _)| *digest);
+            let digest = match digest {
+                Some(digest) => digest,
+                None => {
+                    log::warn!("No commitment of dealer {} got enough echoes", dealer);
+                    continue;
+                }
+            };
+            let complaints = self.complaints.get(&dealer).unwrap_or(&no_complaints);
+            let answered = self.answered.get(&dealer).unwrap_or(&no_complaints);
+            if complaints.len() > self.num_faults || !complaints.is_subset(answered) {
+                log::warn!("Disqualifying dealer {} with complaints {:?}", dealer, complaints);
+                self.disqualified.insert(dealer);
+                continue;
+            }
+            qual.push((dealer, digest));
+        }
+        qual
+    }
+
+    /// Combine the sharings of the agreed dealers `qual` into our key share
+    /// and keep it in the keystore. Returns the encoded threshold public key.
+    fn finalize(&self, qual: &Qualified) -> anyhow::Result<String> {
+        let mut value = Scalar::zero();
+        let mut commitment = Commitment(Vec::new());
+        for (dealer, _) in qual {
+            value += self.shares[dealer].value;
+            commitment.add(&self.commitments[dealer]);
+        }
+        let key_share = KeyShare {
+            threshold: self.num_faults,
+            share: Share { index: self.myid, value },
+            commitment,
+        };
+        if !key_share.is_valid() {
+            return Err(anyhow::anyhow!("Combined share does not match the combined commitment"));
+        }
+        self.store.save_key_share(serde_json::to_vec(&key_share)?.into())?;
+
+        let pk: String = key_share.public_key()
+            .compress()
+            .as_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        log::info!("Generated threshold public key {}", pk);
+        Ok(pk)
+    }
+}
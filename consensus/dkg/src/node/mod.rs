@@ -0,0 +1,10 @@
+mod context;
+pub use context::*;
+
+mod process;
+
+#[cfg(test)]
+mod sim;
+
+mod dkg;
+pub use dkg::*;
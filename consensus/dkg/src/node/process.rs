@@ -0,0 +1,55 @@
+use std::time::Instant;
+
+use transport::Transport;
+use types::{Offence, Replica, SyncMsg, dkg::{ProtMsg, WrapperMsg}};
+use crate::node::{
+    context::Context
+};
+impl<N: Transport<WrapperMsg>, S: Transport<SyncMsg>> Context<N, S> {
+    /// Handle our own messages and the verified ones from the network, most
+    /// urgent first.
+    pub(crate) async fn process_pending(&mut self) {
+        while let Some((protmsg, sender)) = self.peers.pop() {
+            self.handle_protmsg(protmsg, sender).await;
+        }
+    }
+
+    async fn handle_protmsg(&mut self, protmsg: ProtMsg, sender: Replica) {
+        // A digest under another hash function matches no commitment
+        let foreign = match &protmsg {
+            ProtMsg::Echo(_, digest) => digest.alg != self.hash,
+            ProtMsg::Propose(_, qual) | ProtMsg::QualEcho(_, qual) | ProtMsg::QualReady(qual) => {
+                qual.iter().any(|(_, digest)| digest.alg != self.hash)
+            },
+            _ => false,
+        };
+        if foreign {
+            log::warn!("Commitment digest under another hash function from node {}", sender);
+            self.peers.reputation.penalize(sender, Offence::Malformed, Instant::now());
+            return;
+        }
+        match protmsg {
+            ProtMsg::Deal(comm, share) => {
+                self.handle_deal(comm, share, sender).await;
+            },
+            ProtMsg::Echo(dealer, digest) => {
+                self.handle_echo(dealer, digest, sender);
+            },
+            ProtMsg::Complaint(dealer) => {
+                self.handle_complaint(dealer, sender).await;
+            },
+            ProtMsg::Reveal(comm, share) => {
+                self.handle_reveal(comm, share, sender).await;
+            },
+            ProtMsg::Propose(attempt, qual) => {
+                self.handle_propose(attempt, qual, sender).await;
+            },
+            ProtMsg::QualEcho(attempt, qual) => {
+                self.handle_qual_echo(attempt, qual, sender).await;
+            },
+            ProtMsg::QualReady(qual) => {
+                self.handle_qual_ready(qual, sender).await;
+            }
+        }
+    }
+}
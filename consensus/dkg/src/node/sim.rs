@@ -0,0 +1,130 @@
+use std::{collections::HashSet, path::Path};
+
+use config::{Keystore, Node, SecretStore};
+use crypto::aead::KdfParams;
+use sim::{Envelope, SimNetwork, SimSender, SimTransport};
+use tokio::sync::mpsc::unbounded_channel;
+use types::{SyncMsg, SyncState};
+use zeroize::Zeroizing;
+
+use super::{Context, Fault, load_share};
+
+const PASSPHRASE: &str = "passphrase";
+
+/// Configs for `n` replicas, each with a keystore of its own in `dir`.
+fn configs(n: usize, f: usize, dir: &Path) -> Vec<Node> {
+    (0..n).map(|i| {
+        let mut config = Node::new();
+        config.id = i;
+        config.num_nodes = n;
+        config.num_faults = f;
+        config.delta = 100;
+        for j in 0..n {
+            // Both ends of a channel share its key
+            config.sk_map.insert(j, vec![(i.min(j)*n + i.max(j)) as u8; crypto::SECRET_KEY_SIZE].into());
+        }
+        let name = format!("keystore-{}.json", i);
+        // Cheap to derive, as the store derives the key again on saving
+        let kdf = KdfParams { m_cost: 64, t_cost: 1, ..KdfParams::generate() };
+        Keystore::seal(&config, PASSPHRASE.as_bytes(), kdf).unwrap().to_file(&dir.join(&name)).unwrap();
+        config.keystore = Some(name);
+        config
+    }).collect()
+}
+
+/// Play the syncer for `n` simulated replicas, dealing as `faults` tells or
+/// crashed if `None`, and return the public key every replica reported
+/// along with the key share it left in its keystore.
+async fn run(seed: u64, f: usize, faults: &[Option<Fault>]) -> Vec<Option<(String, Node)>> {
+    let n = faults.len();
+    let dir = std::env::temp_dir().join(format!("dkg-sim-{}-{}", std::process::id(), seed));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut net = SimNetwork::new(seed);
+    let (sync_tx, mut sync_rx) = unbounded_channel::<Envelope<SyncMsg>>();
+    let mut starts = Vec::new();
+    let mut exits = Vec::new();
+    let configs = configs(n, f, &dir);
+    for (config, fault) in configs.iter().zip(faults) {
+        let fault = match fault {
+            Some(fault) => *fault,
+            None => continue,
+        };
+        let (start_tx, start_rx) = unbounded_channel();
+        starts.push(start_tx);
+        let store = SecretStore::new(config, &dir, Zeroizing::new(PASSPHRASE.to_string())).unwrap();
+        exits.push(Context::spawn_with(
+            config.clone(),
+            store,
+            fault,
+            net.register(config.id),
+            SimTransport::new(SimSender::new(config.id, sync_tx.clone()), start_rx),
+        ).unwrap());
+    }
+    let scheduler = net.spawn();
+
+    let up = starts.len();
+    let mut alive = HashSet::new();
+    let mut completed = vec![None; n];
+    while completed.iter().filter(|c| c.is_some()).count() < up {
+        let msg = sync_rx.recv().await.unwrap().open();
+        match msg.state {
+            SyncState::ALIVE => {
+                alive.insert(msg.sender);
+                if alive.len() == up {
+                    for start in &starts {
+                        start.send(SyncMsg { sender: 0, state: SyncState::START, value: "".to_string() }).unwrap();
+                    }
+                }
+            },
+            SyncState::COMPLETED => completed[msg.sender] = Some(msg.value),
+            _ => {},
+        }
+    }
+    for exit in exits {
+        let _ = exit.send(());
+    }
+    scheduler.await.unwrap();
+    let results = completed.into_iter().zip(configs).map(|(key, mut config)| {
+        key.map(|key| {
+            config.unlock(&dir, PASSPHRASE.as_bytes()).unwrap();
+            (key, config)
+        })
+    }).collect();
+    std::fs::remove_dir_all(&dir).unwrap();
+    results
+}
+
+/// Every replica that ran reported the same key, and keeps a share of it.
+fn assert_agreement(seed: u64, results: &[Option<(String, Node)>]) {
+    let keys: Vec<&String> = results.iter().flatten().map(|(key, _)| key).collect();
+    assert!(keys[0] != "failed" && keys.iter().all(|k| *k == keys[0]), "seed {}: {:?}", seed, keys);
+    let first = load_share(&results.iter().flatten().next().unwrap().1).unwrap();
+    for (_, config) in results.iter().flatten() {
+        let share = load_share(config).unwrap();
+        assert_eq!(share.share.index, config.id);
+        assert_eq!(share.commitment, first.commitment);
+    }
+}
+
+#[tokio::test]
+async fn replicas_agree_on_the_key_despite_a_bad_dealer() {
+    let honest = Some(Fault::Honest);
+    for (seed, last) in [(0, honest), (1, Some(Fault::BadShares)), (2, Some(Fault::BadShares))] {
+        assert_agreement(seed, &run(seed, 1, &[honest, honest, honest, last]).await);
+    }
+}
+
+#[tokio::test]
+async fn replicas_agree_on_the_commitment_of_an_equivocating_dealer() {
+    // Replica 0 holds another sharing of the last dealer than everyone else
+    let honest = Some(Fault::Honest);
+    for seed in 3..5 {
+        assert_agreement(seed, &run(seed, 1, &[honest, honest, honest, Some(Fault::Equivocating)]).await);
+    }
+}
+
+#[tokio::test]
+async fn replicas_agree_without_the_first_proposer() {
+    let honest = Some(Fault::Honest);
+    assert_agreement(5, &run(5, 1, &[None, honest, honest, honest]).await);
+}
@@ -1,21 +1,24 @@
-use std::{collections::HashMap, net::{SocketAddr, SocketAddrV4}, time::{SystemTime, UNIX_EPOCH}};
+use std::{collections::{HashMap, HashSet}, net::SocketAddr, time::{SystemTime, UNIX_EPOCH}};
 
 use anyhow::{Result, anyhow};
 use config::Node;
-use fnv::FnvHashMap;
-use network::{plaintcp::{TcpReceiver, TcpReliableSender, CancelHandler}, Acknowledgement};
-use tokio::sync::{oneshot, mpsc::{unbounded_channel, UnboundedReceiver}};
+use consensus::Peers;
+use crypto::{Algorithm, ed25519, hash::{HashAlgorithm, TaggedHash}};
+use network::{plaintcp::CancelHandler, Acknowledgement};
+use tokio::{sync::oneshot, time::{Instant, sleep_until}};
 // use tokio_util::time::DelayQueue;
-use types::{{WrapperMsg, Replica, ProtMsg}, SyncMsg, SyncState};
+use transport::{Slot, Transport};
+use types::{{WrapperMsg, Replica, ProtMsg}, Protocol, SyncMsg, SyncState};
 
-use super::{Handler, SyncHandler};
+use super::Election;
 
-pub struct Context {
+/// The single instance every message of the protocol belongs to
+const INSTANCE: Slot = Slot { instance: 0, round: 0 };
+
+pub struct Context<N, S> {
     /// Networking context
-    pub net_send: TcpReliableSender<Replica,WrapperMsg,Acknowledgement>,
-    pub net_recv: UnboundedReceiver<WrapperMsg>,
-    pub sync_send:TcpReliableSender<Replica,SyncMsg,Acknowledgement>,
-    pub sync_recv: UnboundedReceiver<SyncMsg>,
+    pub peers: Peers<WrapperMsg, N>,
+    pub sync: S,
 
     /// Data context
     pub num_nodes: usize,
@@ -26,91 +29,59 @@ pub struct Context {
     pub quorum: usize,
     pub echo: bool,
     pub echo_quorum: usize,
-    pub vec_counts: HashMap<Vec<u64>, u64>,
+    /// Replicas that echoed every proposal digest
+    pub vec_counts: HashMap<TaggedHash, HashSet<Replica>>,
+    /// Proposed vectors we know, by digest
+    pub proposals: HashMap<TaggedHash, Vec<u64>>,
+    /// Replicas we asked for a missing proposal
+    pub requested: HashMap<TaggedHash, HashSet<Replica>>,
+    /// The replica we wait on for every missing proposal, and until when
+    pub fetching: HashMap<TaggedHash, (Replica, Instant)>,
+    /// Requests we could not answer yet because the proposal is missing here too
+    pub pending_requests: HashMap<TaggedHash, HashSet<Replica>>,
+    pub delivered: bool,
     pub byz: bool,
+    pub delta: u64,
+    /// The hash function of the proposal digests
+    pub hash: HashAlgorithm,
 
-    /// Secret Key map
-    pub sec_key_map:HashMap<Replica, Vec<u8>>,
-
-
-    /// Cancel Handlers
-    pub cancel_handlers: HashMap<u64,Vec<CancelHandler<Acknowledgement>>>,
     exit_rx: oneshot::Receiver<()>,
 
     // Add your custom fields here
     pub is_leader: bool,
+    /// The leader, once elected
+    pub leader: Option<Replica>,
+    /// The election of the leader, if the replicas have Ed25519 identities
+    pub election: Option<Election>,
+    /// Proposals that arrived before the election closed, by sender
+    pub early_prepares: HashMap<Replica, Vec<u64>>,
 }
 
-impl Context {
+impl Context<Box<dyn Transport<WrapperMsg>>, Box<dyn Transport<SyncMsg>>> {
     pub fn spawn(
         config:Node,
         message: u64,
         byz: bool
     )->anyhow::Result<oneshot::Sender<()>>{
-        let mut consensus_addrs :FnvHashMap<Replica,SocketAddr>= FnvHashMap::default();
-        for (replica,address) in config.net_map.iter(){
-            let address:SocketAddr = address.parse().expect("Unable to parse address");
-            consensus_addrs.insert(*replica, SocketAddr::from(address.clone()));
-        }
-
-        let mut is_leader = false;
-
-        // set leader
-        if config.id == 0 {
-            is_leader = true;
-        }
-
-        let my_port = consensus_addrs.get(&config.id).unwrap();
-        let my_address = to_socket_address("0.0.0.0", my_port.port());
-        let mut syncer_map:FnvHashMap<Replica,SocketAddr> = FnvHashMap::default();
-        syncer_map.insert(0, config.client_addr);
-        
         // Setup networking
-        let (tx_net_to_consensus, rx_net_to_consensus) = unbounded_channel();
-        TcpReceiver::<Acknowledgement, WrapperMsg, _>::spawn(
-            my_address,
-            Handler::new(tx_net_to_consensus),
-        );
-        let syncer_listen_port = config.client_port;
-        let syncer_l_address = to_socket_address("0.0.0.0", syncer_listen_port);
-        // The server must listen to the client's messages on some port that is not being used to listen to other servers
-        let (tx_net_to_client,rx_net_from_client) = unbounded_channel();
-        TcpReceiver::<Acknowledgement,SyncMsg,_>::spawn(
-            syncer_l_address, 
-            SyncHandler::new(tx_net_to_client)
-        );
-        let consensus_net = TcpReliableSender::<Replica,WrapperMsg,Acknowledgement>::with_peers(
-            consensus_addrs.clone()
-        );
-
-        let values = Vec::new();
-        
-        let sync_net = TcpReliableSender::<Replica,SyncMsg,Acknowledgement>::with_peers(syncer_map);
+        let (consensus_net, sync_net) = transport::replica_transports(&config, Protocol::Pbft)?;
+        Context::spawn_with(config, message, byz, consensus_net, sync_net)
+    }
+}
+
+impl<N: Transport<WrapperMsg>, S: Transport<SyncMsg>> Context<N, S> {
+    /// Run replica `config.id` over the given transports for consensus and
+    /// syncer messages.
+    pub fn spawn_with(
+        config: Node,
+        message: u64,
+        byz: bool,
+        net: N,
+        sync: S,
+    )->anyhow::Result<oneshot::Sender<()>>{
         let (exit_tx, exit_rx) = oneshot::channel();
+        let mut c = Context::new(config, message, byz, net, sync, exit_rx)?;
         tokio::spawn(async move {
-            let mut c = Context {
-                net_send:consensus_net,
-                net_recv:rx_net_to_consensus,
-                sync_send: sync_net,
-                sync_recv: rx_net_from_client,
-                num_nodes: config.num_nodes,
-                sec_key_map: HashMap::default(),
-                myid: config.id,
-                num_faults: config.num_faults,
-                cancel_handlers:HashMap::default(),
-                exit_rx: exit_rx,
-                is_leader: is_leader,
-                quorum: 0,
-                inp_message:message,
-                values: values,
-                echo: true,
-                echo_quorum: 0,
-                vec_counts: HashMap::default(),
-                byz: byz,
-            };
-            for (id, sk_data) in config.sk_map.clone() {
-                c.sec_key_map.insert(id, sk_data.clone());
-            }
             //c.invoke_coin.insert(100, Duration::from_millis(sleep_time.try_into().unwrap()));
             if let Err(e) = c.run().await {
                 log::error!("Consensus error: {}", e);
@@ -119,63 +90,102 @@ impl Context {
         Ok(exit_tx)
     }
 
+    /// Build the context of replica `config.id` on top of its transports.
+    pub fn new(
+        config: Node,
+        message: u64,
+        byz: bool,
+        net: N,
+        sync: S,
+        exit_rx: oneshot::Receiver<()>,
+    ) -> Result<Self> {
+        // Elect the leader with the Ed25519 identities of the replicas, or
+        // else fix it to replica 0
+        let election = match config.crypto_alg {
+            Algorithm::ED25519 => {
+                let identity = ed25519::Keypair::decode(&mut config.secret_key_bytes.clone())?;
+                Some(Election::new(identity, config.session.as_bytes().to_vec(), config.pk_map.clone()))
+            },
+            _ => None,
+        };
+        let leader = match election {
+            Some(_) => None,
+            None => Some(0),
+        };
+        Ok(Context {
+            peers: Peers::new(&config, net),
+            sync,
+            num_nodes: config.num_nodes,
+            myid: config.id,
+            num_faults: config.num_faults,
+            exit_rx,
+            is_leader: leader == Some(config.id),
+            leader,
+            election,
+            early_prepares: HashMap::default(),
+            quorum: 0,
+            inp_message:message,
+            values: Vec::new(),
+            echo: true,
+            echo_quorum: 0,
+            vec_counts: HashMap::default(),
+            proposals: HashMap::default(),
+            requested: HashMap::default(),
+            fetching: HashMap::default(),
+            pending_requests: HashMap::default(),
+            delivered: false,
+            byz,
+            delta: config.delta,
+            hash: config.hash,
+        })
+    }
+
+    /// Send `protmsg` to every replica. It is serialized once, with a MAC
+    /// for every peer, and our own copy goes to the local queue.
     pub async fn broadcast(&mut self, protmsg:ProtMsg){
-        let sec_key_map = self.sec_key_map.clone();
-        for (replica,sec_key) in sec_key_map.into_iter() {
-            if self.byz && replica%2 == 0{
-                // Simulates a crash fault
-                continue;
-            }
-            // if replica != self.myid{
-            //     let wrapper_msg = WrapperMsg::new(protmsg.clone(), self.myid, &sec_key.as_slice());
-            //     let cancel_handler:CancelHandler<Acknowledgement> = self.net_send.send(replica, wrapper_msg).await;
-            //     self.add_cancel_handler(cancel_handler);
-            // }
-
-            if self.is_leader || replica != self.myid{
-                let wrapper_msg = WrapperMsg::new(protmsg.clone(), self.myid, &sec_key.as_slice());
-                let cancel_handler:CancelHandler<Acknowledgement> = self.net_send.send(replica, wrapper_msg).await;
-                self.add_cancel_handler(cancel_handler);
-            }
-        }
+        let to: Vec<Replica> = (0..self.num_nodes)
+            // Simulates a crash fault
+            .filter(|replica| !(self.byz && replica%2 == 0))
+            // The leader handles its own proposal like everyone else, and so
+            // does every replica with its input until the leader is known
+            .filter(|replica| *replica != self.myid || self.is_leader || self.leader.is_none())
+            .collect();
+        self.peers.multicast(protmsg, to, INSTANCE).await;
     }
 
-    pub fn add_cancel_handler(&mut self, canc: CancelHandler<Acknowledgement>){
-        self.cancel_handlers
-            .entry(0)
-            .or_default()
-            .push(canc);
+    pub fn add_cancel_handler(&mut self, slot: Slot, canc: CancelHandler<Acknowledgement>){
+        self.peers.add_cancel_handler(slot, canc);
     }
 
-    pub async fn send(&mut self,replica:Replica, wrapper_msg:WrapperMsg){
-        let cancel_handler:CancelHandler<Acknowledgement> = self.net_send.send(replica, wrapper_msg).await;
-        self.add_cancel_handler(cancel_handler);
+    pub async fn send(&mut self,replica:Replica, protmsg:ProtMsg){
+        self.peers.send(replica, protmsg, INSTANCE).await;
     }
 
     pub async fn run(&mut self)-> Result<()>{
+        // Agree on keys with the other replicas before anything else
+        self.peers.ready().await;
         // The process starts listening to messages in this process. 
         // First, the node sends an alive message 
-        let cancel_handler = self.sync_send.send(0,
+        let cancel_handler = self.sync.send(0,
             SyncMsg { sender: self.myid, state: SyncState::ALIVE,value:"".to_string()}
         ).await;
-        self.add_cancel_handler(cancel_handler);
+        self.add_cancel_handler(Slot::CONTROL, cancel_handler);
         loop {
+            self.process_pending().await;
+            let election_deadline = self.election.as_ref()
+                .and_then(|election| election.deadline)
+                .unwrap_or_else(Instant::now);
+            let fetch_deadline = self.fetch_deadline();
             tokio::select! {
+                // Poll in a fixed order so that simulated runs replay exactly
+                biased;
                 // Receive exit handlers
                 exit_val = &mut self.exit_rx => {
                     exit_val.map_err(anyhow::Error::new)?;
                     log::info!("Termination signal received by the server. Exiting.");
                     break
                 },
-                msg = self.net_recv.recv() => {
-                    // Received messages are processed here
-                    log::debug!("Got a consensus message from the network: {:?}", msg);
-                    let msg = msg.ok_or_else(||
-                        anyhow!("Networking layer has closed")
-                    )?;
-                    self.process_msg(msg).await;
-                },
-                sync_msg = self.sync_recv.recv() =>{
+                sync_msg = self.sync.recv() =>{
                     let sync_msg = sync_msg.ok_or_else(||
                         anyhow!("Networking layer has closed")
                     )?;
@@ -188,9 +198,10 @@ impl Context {
                             // Start your protocol from here
                             // Write a function to broadcast a message. We demonstrate an example with a PING function
                             self.start_init().await;
+                            self.start_election().await;
 
-                            let cancel_handler = self.sync_send.send(0, SyncMsg { sender: self.myid, state: SyncState::STARTED, value:"".to_string()}).await;
-                            self.add_cancel_handler(cancel_handler);
+                            let cancel_handler = self.sync.send(0, SyncMsg { sender: self.myid, state: SyncState::STARTED, value:"".to_string()}).await;
+                            self.add_cancel_handler(Slot::CONTROL, cancel_handler);
                         },
                         SyncState::STOP =>{
                             // Code used for internal purposes
@@ -204,25 +215,35 @@ impl Context {
                         _=>{}
                     }
                 },
+                _ = sleep_until(election_deadline), if self.election_pending() => {
+                    self.election_timeout().await;
+                },
+                _ = sleep_until(fetch_deadline.unwrap_or_else(Instant::now)), if fetch_deadline.is_some() => {
+                    self.fetch_timeout().await;
+                },
+                received = self.peers.receive() => received?,
             };
         }
+        let pending = self.peers.cancel_handlers.cancel_all();
+        log::info!("Cancelled {} unacknowledged messages", pending);
         Ok(())
     }
 
     pub async fn terminate(&mut self, res: String) {
         log::info!("terminating..");
-        let cancel_handler = self.sync_send.send(0,
+        log::info!("{} messages are waiting for an acknowledgement", self.peers.cancel_handlers.reap());
+        let cancel_handler = self.sync.send(0,
             SyncMsg { sender: self.myid, state: SyncState::COMPLETED, value:res}
         ).await;
 
-        self.add_cancel_handler(cancel_handler);
+        self.add_cancel_handler(Slot::CONTROL, cancel_handler);
     }
 }
 
 pub fn to_socket_address(
     ip_str: &str,
     port: u16,
-) -> SocketAddr {
-    let addr = SocketAddrV4::new(ip_str.parse().unwrap(), port);
-    addr.into()
+) -> std::io::Result<SocketAddr> {
+    // Takes IPv4 and IPv6 addresses and DNS names alike
+    config::resolve_host(ip_str, port)
 }
\ No newline at end of file
@@ -0,0 +1,370 @@
+use std::time::Duration;
+
+use crypto::ed25519;
+use fnv::{FnvHashMap as HashMap, FnvHashSet as HashSet};
+use tokio::time::Instant;
+use transport::Transport;
+use types::{Offence, ProtMsg, Replica, SyncMsg, Ticket, WrapperMsg, elect};
+
+use super::Context;
+
+/// The protocol runs a single instance, in a single view.
+pub const VIEW: u64 = 0;
+
+/// The leader when the replicas cannot agree on a ticket, as without an
+/// election.
+pub const FALLBACK: Replica = 0;
+
+/// The election of the leader with VRF tickets (`types::Ticket`). Tickets
+/// verify against the identity of whoever drew them, so anyone can pass
+/// them on: a replica relays every ticket that beats the lowest it knew.
+/// After two network delays every correct replica knows the lowest ticket
+/// that any of them saw, unless a faulty replica showed its own to some of
+/// them too late. The replicas therefore agree on the winner as in a
+/// reliable broadcast: each echoes the lowest ticket it knows, is ready to
+/// adopt its holder once `n - f` replicas echoed it or `f + 1` are ready to,
+/// and adopts it once `n - f` replicas are ready to. A replica that saw no
+/// ticket win after two more network delays is ready to adopt `FALLBACK`
+/// instead. Every replica is ready to adopt a single leader, so no two
+/// correct replicas adopt different ones.
+pub struct Election {
+    pub identity: ed25519::Keypair,
+    /// The VRF input besides the view: the session of the run, which is only
+    /// known once the run is set up. A later view would take the output of
+    /// the previous leader's ticket instead
+    pub seed: Vec<u8>,
+    /// The Ed25519 identities of the replicas
+    pub pk_map: HashMap<Replica, Vec<u8>>,
+    /// Every valid ticket we know, by the replica that drew it
+    pub tickets: HashMap<Replica, Ticket>,
+    /// The lowest output we relayed
+    pub lowest: Option<Vec<u8>>,
+    /// When the tickets, or then the echoes, had their time
+    pub deadline: Option<Instant>,
+    /// The tickets had their time to spread
+    pub expired: bool,
+    /// The holder of the lowest ticket we knew once they had, if we echoed
+    pub echoed: Option<Replica>,
+    /// The replicas that echoed the ticket of every holder, one echo each
+    pub echoes: HashMap<Replica, HashSet<Replica>>,
+    /// The replicas ready to adopt every leader, one each
+    pub readies: HashMap<Replica, HashSet<Replica>>,
+    /// The leader we are ready to adopt
+    pub ready: Option<Replica>,
+}
+
+impl Election {
+    pub fn new(identity: ed25519::Keypair, seed: Vec<u8>, pk_map: HashMap<Replica, Vec<u8>>) -> Self {
+        Self {
+            identity,
+            seed,
+            pk_map,
+            tickets: HashMap::default(),
+            lowest: None,
+            deadline: None,
+            expired: false,
+            echoed: None,
+            echoes: HashMap::default(),
+            readies: HashMap::default(),
+            ready: None,
+        }
+    }
+
+    fn verify(&self, ticket: &Ticket) -> bool {
+        ticket.view == VIEW && self.pk_map.get(&ticket.replica)
+            .and_then(|pk| ed25519::PublicKey::decode(pk).ok())
+            .is_some_and(|pk| ticket.verify(&pk, &self.seed))
+    }
+
+    fn voted(votes: &HashMap<Replica, HashSet<Replica>>, sender: Replica) -> bool {
+        votes.values().any(|voters| voters.contains(&sender))
+    }
+}
+
+impl<N: Transport<WrapperMsg>, S: Transport<SyncMsg>> Context<N, S> {
+    /// Draw our ticket and pass it on. Without an election, the leader is
+    /// fixed from the start.
+    pub async fn start_election(&mut self) {
+        let delta = self.delta;
+        let ticket = match self.election.as_mut() {
+            Some(election) if election.deadline.is_none() => {
+                election.deadline = Some(Instant::now() + Duration::from_millis(2*delta));
+                Ticket::draw(self.myid, &election.identity, VIEW, &election.seed)
+            },
+            _ => return,
+        };
+        self.handle_ticket(ticket, self.myid).await;
+    }
+
+    /// Whether the election waits for its tickets or echoes to spread.
+    pub fn election_pending(&self) -> bool {
+        self.leader.is_none()
+            && self.election.as_ref().is_some_and(|e| e.deadline.is_some() && e.ready.is_none())
+    }
+
+    pub async fn election_timeout(&mut self) {
+        let election = match self.election.as_mut() {
+            Some(election) => election,
+            None => return,
+        };
+        election.deadline = None;
+        if !election.expired {
+            election.expired = true;
+            self.echo_lowest().await;
+        } else {
+            log::warn!("No ticket won the election, falling back to node {}", FALLBACK);
+            self.elect_ready(FALLBACK).await;
+        }
+    }
+
+    pub async fn handle_ticket(&mut self, ticket: Ticket, sender: Replica) {
+        let election = match self.election.as_mut() {
+            Some(election) => election,
+            None => {
+                log::warn!("Ticket from node {}, but the leader is fixed", sender);
+                self.peers.reputation.penalize(sender, Offence::Unexpected, std::time::Instant::now());
+                return;
+            }
+        };
+        if !election.verify(&ticket) {
+            log::warn!("Invalid ticket of node {} from node {}", ticket.replica, sender);
+            self.peers.reputation.penalize(sender, Offence::Malformed, std::time::Instant::now());
+            return;
+        }
+        // Tickets that come after we echoed cannot change our echo
+        if election.echoed.is_some() || election.tickets.contains_key(&ticket.replica) {
+            return;
+        }
+        election.tickets.insert(ticket.replica, ticket.clone());
+        if election.lowest.as_ref().is_none_or(|lowest| ticket.output < *lowest) {
+            election.lowest = Some(ticket.output.clone());
+            self.broadcast(ProtMsg::Ticket(ticket)).await;
+        }
+        self.echo_lowest().await;
+    }
+
+    /// Echo the lowest ticket, once the tickets had their time to spread
+    /// and those of `n - f` replicas are in, and give the echoes their time.
+    async fn echo_lowest(&mut self) {
+        let n_minus_f = self.num_nodes - self.num_faults;
+        let delta = self.delta;
+        let ticket = match self.election.as_mut() {
+            Some(e) if e.expired && e.echoed.is_none() && e.tickets.len() >= n_minus_f => {
+                let winner = elect(VIEW, &e.seed, e.tickets.values(), &e.pk_map);
+                let ticket = winner.and_then(|winner| e.tickets.get(&winner)).cloned();
+                e.echoed = ticket.as_ref().map(|ticket| ticket.replica);
+                e.deadline = Some(Instant::now() + Duration::from_millis(2*delta));
+                ticket
+            },
+            _ => None,
+        };
+        if let Some(ticket) = ticket {
+            log::info!("Echoing the ticket of node {} for view {}", ticket.replica, VIEW);
+            self.broadcast(ProtMsg::ElectEcho(ticket)).await;
+        }
+    }
+
+    pub async fn handle_elect_echo(&mut self, ticket: Ticket, sender: Replica) {
+        let n_minus_f = self.num_nodes - self.num_faults;
+        let election = match self.election.as_mut() {
+            Some(election) => election,
+            None => {
+                log::warn!("Election echo from node {}, but the leader is fixed", sender);
+                self.peers.reputation.penalize(sender, Offence::Unexpected, std::time::Instant::now());
+                return;
+            }
+        };
+        if !election.verify(&ticket) {
+            log::warn!("Echo of an invalid ticket of node {} from node {}", ticket.replica, sender);
+            self.peers.reputation.penalize(sender, Offence::Malformed, std::time::Instant::now());
+            return;
+        }
+        // A replica echoes once, so later echoes of the same sender count for nothing
+        if Election::voted(&election.echoes, sender) {
+            return;
+        }
+        let echoers = election.echoes.entry(ticket.replica).or_default();
+        echoers.insert(sender);
+        if echoers.len() >= n_minus_f {
+            self.elect_ready(ticket.replica).await;
+        }
+    }
+
+    pub async fn handle_elect_ready(&mut self, leader: Replica, sender: Replica) {
+        let (num_nodes, num_faults) = (self.num_nodes, self.num_faults);
+        let election = match self.election.as_mut() {
+            Some(election) => election,
+            None => {
+                log::warn!("Election ready from node {}, but the leader is fixed", sender);
+                self.peers.reputation.penalize(sender, Offence::Unexpected, std::time::Instant::now());
+                return;
+            }
+        };
+        if leader >= num_nodes {
+            log::warn!("Node {} is ready to adopt unknown node {}", sender, leader);
+            self.peers.reputation.penalize(sender, Offence::Malformed, std::time::Instant::now());
+            return;
+        }
+        if Election::voted(&election.readies, sender) {
+            return;
+        }
+        let readies = election.readies.entry(leader).or_default();
+        readies.insert(sender);
+        let count = readies.len();
+        // At least one correct replica is ready to adopt it
+        if count > num_faults {
+            self.elect_ready(leader).await;
+        }
+        if count >= num_nodes - num_faults && self.leader.is_none() {
+            log::info!("Elected node {} to lead view {}", leader, VIEW);
+            self.set_leader(leader).await;
+        }
+    }
+
+    /// Tell everyone that we are ready to adopt `leader`, unless we are ready
+    /// to adopt someone already.
+    async fn elect_ready(&mut self, leader: Replica) {
+        match self.election.as_mut() {
+            Some(election) if election.ready.is_none() => {
+                election.ready = Some(leader);
+                election.deadline = None;
+            },
+            _ => return,
+        }
+        self.broadcast(ProtMsg::ElectReady(leader)).await;
+    }
+
+    /// Take the lead if it is ours, and go on with the proposal the leader
+    /// sent before we knew it led.
+    pub async fn set_leader(&mut self, leader: Replica) {
+        self.leader = Some(leader);
+        self.is_leader = leader == self.myid;
+        self.propose().await;
+        let early = std::mem::take(&mut self.early_prepares);
+        for (sender, values) in early {
+            if sender == leader {
+                self.handle_prepare(values, sender).await;
+            } else {
+                log::warn!("Dropping the proposal of node {}, which does not lead", sender);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use config::Node;
+    use crypto::{Algorithm, ed25519, hash::Algorithms};
+    use sim::{SimSender, SimTransport};
+    use tokio::sync::{mpsc::unbounded_channel, oneshot};
+    use transport::{MemTransport, Transport, mesh};
+    use types::{ProtMsg, Replica, SyncMsg, Ticket, WrapperMsg};
+
+    use super::FALLBACK;
+    use crate::node::Context;
+
+    type Honest = Context<MemTransport<WrapperMsg>, SimTransport<SyncMsg>>;
+
+    const SEED: &[u8] = b"sim";
+
+    /// Identities for 4 replicas, of which the last one draws the lowest
+    /// ticket.
+    fn identities() -> Vec<ed25519::Keypair> {
+        loop {
+            let identities: Vec<ed25519::Keypair> = (0..4).map(|_| ed25519::Keypair::generate()).collect();
+            let outputs: Vec<Vec<u8>> = identities.iter().enumerate()
+                .map(|(i, identity)| Ticket::draw(i, identity, super::VIEW, SEED).output)
+                .collect();
+            if outputs[..3].iter().all(|output| *output > outputs[3]) {
+                return identities;
+            }
+        }
+    }
+
+    /// Replicas 0 to 2, and the end of replica 3 with its identity.
+    fn replicas() -> (Vec<Honest>, MemTransport<WrapperMsg>, ed25519::Keypair) {
+        let mut identities = identities();
+        let mut nets = mesh(0..4);
+        let (sync_tx, _) = unbounded_channel();
+        let honest = (0..3).map(|i| {
+            let mut config = Node::new();
+            config.id = i;
+            config.num_nodes = 4;
+            config.num_faults = 1;
+            config.crypto_alg = Algorithm::ED25519;
+            config.session = String::from_utf8(SEED.to_vec()).unwrap();
+            config.secret_key_bytes = identities[i].encode().to_vec().into();
+            for (j, identity) in identities.iter().enumerate() {
+                config.pk_map.insert(j, identity.public().encode().to_vec());
+                config.sk_map.insert(j, vec![(i.min(j)*4 + i.max(j)) as u8; crypto::SECRET_KEY_SIZE].into());
+            }
+            let sync = SimTransport::new(SimSender::new(i, sync_tx.clone()), unbounded_channel().1);
+            let (_, exit_rx) = oneshot::channel();
+            Context::new(config, 0, false, nets.remove(&i).unwrap(), sync, exit_rx).unwrap()
+        }).collect();
+        (honest, nets.remove(&3).unwrap(), identities.pop().unwrap())
+    }
+
+    /// Handle whatever reached `replica`.
+    async fn pump(replica: &mut Honest) {
+        replica.process_pending().await;
+        while let Ok(received) = tokio::time::timeout(Duration::from_millis(20), replica.peers.receive()).await {
+            received.unwrap();
+            replica.process_pending().await;
+        }
+    }
+
+    async fn pump_all(honest: &mut [Honest]) {
+        for _ in 0..4 {
+            for replica in honest.iter_mut() {
+                pump(replica).await;
+            }
+        }
+    }
+
+    /// Send `msg` as the faulty replica 3.
+    async fn send_as_3(net: &mut MemTransport<WrapperMsg>, nonce: &mut u64, to: Replica, msg: ProtMsg) {
+        *nonce += 1;
+        let key = [(to*4 + 3) as u8; crypto::SECRET_KEY_SIZE];
+        net.send(to, WrapperMsg::new(&msg, 3, *nonce, [(to, &key[..])], false, Algorithms::default())).await;
+    }
+
+    #[tokio::test]
+    async fn a_late_low_ticket_does_not_split_the_leader() {
+        let (mut honest, mut byz, identity) = replicas();
+        let mut nonce = 0;
+        for replica in honest.iter_mut() {
+            replica.start_election().await;
+        }
+        pump_all(&mut honest).await;
+
+        // Replica 3 shows its winning ticket to replica 0 alone, which takes
+        // it just in time, while the others close before its relay arrives
+        let ticket = Ticket::draw(3, &identity, super::VIEW, SEED);
+        send_as_3(&mut byz, &mut nonce, 0, ProtMsg::Ticket(ticket.clone())).await;
+        pump(&mut honest[0]).await;
+        for replica in honest.iter_mut() {
+            replica.election_timeout().await;
+        }
+        for to in 0..3 {
+            send_as_3(&mut byz, &mut nonce, to, ProtMsg::ElectEcho(ticket.clone())).await;
+        }
+        pump_all(&mut honest).await;
+        // The echoes split, so nobody leads yet, where replica 0 used to
+        // follow replica 3 and the others someone else
+        assert!(honest.iter().all(|replica| replica.leader.is_none()));
+
+        // No ticket wins, and everyone falls back alike
+        for replica in honest.iter_mut() {
+            replica.election_timeout().await;
+        }
+        for to in 0..3 {
+            send_as_3(&mut byz, &mut nonce, to, ProtMsg::ElectReady(3)).await;
+        }
+        pump_all(&mut honest).await;
+        let leaders: Vec<Option<Replica>> = honest.iter().map(|replica| replica.leader).collect();
+        assert_eq!(leaders, vec![Some(FALLBACK); 3]);
+    }
+}
@@ -1,14 +1,14 @@
 mod context;
 pub use context::*;
 
-mod handler;
-pub use handler::*;
-
-mod sync_handler;
-pub use sync_handler::*;
-
 mod process;
 pub use process::*;
 
+#[cfg(test)]
+mod sim;
+
 mod ping;
-pub use ping::*;
\ No newline at end of file
+pub use ping::*;
+
+mod election;
+pub use election::*;
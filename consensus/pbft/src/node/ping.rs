@@ -1,14 +1,19 @@
-use types::{Msg, ProtMsg};
+use std::time::Duration;
+
+use crypto::hash::TaggedHash;
+use tokio::time::Instant;
+use transport::Transport;
+use types::{Msg, Offence, ProtMsg, Replica, SyncMsg, WrapperMsg};
 
 use super::Context;
 
-impl Context {
+impl<N: Transport<WrapperMsg>, S: Transport<SyncMsg>> Context<N, S> {
     // A function's input parameter needs to be borrowed as mutable only when
     // we intend to modify the variable in the function. Otherwise, it need not be borrowed as mutable.
     // In this example, the mut can (and must) be removed because we are not modifying the Context inside
     // the function. 
     
-    pub async fn start_init(self: &mut Context) {
+    pub async fn start_init(self: &mut Self) {
         let protocol_msg = ProtMsg::Init(
             self.inp_message
         );
@@ -19,72 +24,216 @@ impl Context {
 
     pub async fn handle_init(&mut self, msg:u64, sender:usize){
         // ignore incoming values if quorum reached
-        if self.quorum == (2*self.num_faults) + 1 {
+        if self.values.len() > 2*self.num_faults {
             return;
         }
 
-        // only process init messages at the leader
-        if self.is_leader {
+        // keep the values until the leader is known, since it may be us
+        if self.is_leader || self.leader.is_none() {
             log::info!("Received init message {:?} from node {}", msg, sender);
 
             self.values.push(msg);
             self.quorum = self.values.len();
             log::info!("Total values received: {:?}, Values: {:?}", self.quorum, self.values);
+            self.propose().await;
+        }
+    }
 
-            // if quorum reached start echo stage
-            if self.quorum == (2*self.num_faults) + 1 {
-                self.quorum = 0;
+    /// Propose the values once we lead and a quorum of them is in.
+    pub async fn propose(&mut self) {
+        // if quorum reached start echo stage
+        if self.is_leader && self.quorum == (2*self.num_faults) + 1 {
+            self.quorum = 0;
 
-                log::info!("quorum reached, beginning echo stage...");
-                
-                // broadcast echo msg
-                self.broadcast(ProtMsg::Prepare(Msg {
-                    content: (self.values.clone()), 
-                    origin: (self.myid) 
-                })).await;
-            }
+            log::info!("quorum reached, beginning echo stage...");
+
+            // broadcast echo msg
+            self.broadcast(ProtMsg::Prepare(Msg {
+                content: (self.values.clone()),
+                origin: (self.myid)
+            })).await;
         }
     }
 
     pub async fn handle_prepare(&mut self, values: Vec<u64>, sender_id: usize) {
+        match self.leader {
+            // hold the proposal until the election tells whether its sender leads
+            None => {
+                self.early_prepares.insert(sender_id, values);
+                return;
+            },
+            Some(leader) if leader != sender_id => {
+                log::warn!("Proposal from node {:?}, which does not lead", sender_id);
+                self.peers.reputation.penalize(sender_id, Offence::Unexpected, std::time::Instant::now());
+                return;
+            },
+            Some(_) => (),
+        }
         log::info!("received values vector {:?} from node {:?}", values, sender_id);
+        let digest = self.hash.digest_of(&values);
+        self.store_proposal(digest, values).await;
 
         if !self.is_leader {
             if self.echo {
                 // broadcast echo msg
-                self.broadcast(ProtMsg::Echo(Msg {
-                    content: (values.clone()), 
-                    origin: (self.myid) 
-                })).await;
+                self.broadcast(ProtMsg::Echo(digest)).await;
 
                 self.echo = false;
             }
         }
+        self.check_echoes().await;
     }
 
-    pub async fn handle_echo(&mut self, values: Vec<u64>, sender_id: usize) {
-        log::info!("received values vector {:?} from node {:?}", values, sender_id);
+    pub async fn handle_echo(&mut self, digest: TaggedHash, sender_id: usize) {
+        log::info!("received echo for digest {:?} from node {:?}", digest, sender_id);
+        // a replica echoes once, so later echoes of the same sender count for nothing
+        if self.vec_counts.values().any(|echoers| echoers.contains(&sender_id)) {
+            log::warn!("Repeated echo from node {:?}", sender_id);
+            return;
+        }
         self.echo_quorum += 1;
 
-        let count = self.vec_counts.entry(values.clone()).or_insert(0);
-        *count += 1;
+        let echoers = self.vec_counts.entry(digest).or_default();
+        echoers.insert(sender_id);
+
+        // an honest replica echoed, so someone knows the vector
+        if echoers.len() > self.num_faults {
+            self.request_value(digest).await;
+        }
+        self.check_echoes().await;
+    }
+
+    pub async fn handle_request(&mut self, digest: TaggedHash, sender_id: usize) {
+        log::info!("received request for digest {:?} from node {:?}", digest, sender_id);
+        match self.proposals.get(&digest).cloned() {
+            Some(values) => {
+                self.respond(sender_id, values).await;
+            },
+            None => {
+                // answer once we learn the vector ourselves. A replica waits
+                // for one vector at a time, so a later request replaces its
+                // earlier one and the backlog stays within one per replica
+                self.pending_requests.retain(|_, requesters| {
+                    requesters.remove(&sender_id);
+                    !requesters.is_empty()
+                });
+                self.pending_requests.entry(digest).or_default().insert(sender_id);
+            }
+        }
+    }
+
+    pub async fn handle_response(&mut self, values: Vec<u64>, sender_id: usize) {
+        let digest = self.hash.digest_of(&values);
+        if self.proposals.contains_key(&digest) {
+            return;
+        }
+        // a late answer to a request that timed out is as good as any
+        if self.requested.get(&digest).is_some_and(|asked| asked.contains(&sender_id)) {
+            log::info!("received values vector {:?} for digest {:?} from node {:?}", values, digest, sender_id);
+            self.store_proposal(digest, values).await;
+            self.check_echoes().await;
+            return;
+        }
+        // the vector we wait on from the sender does not hash to its digest
+        let mut waited: Vec<TaggedHash> = self.fetching.iter()
+            .filter(|(_, (target, _))| *target == sender_id)
+            .map(|(digest, _)| *digest)
+            .collect();
+        if waited.is_empty() {
+            log::warn!("Unrequested vector from node {:?}", sender_id);
+            self.peers.reputation.penalize(sender_id, Offence::Unexpected, std::time::Instant::now());
+            return;
+        }
+        log::warn!("Node {:?} answered with a vector of another digest", sender_id);
+        self.peers.reputation.penalize(sender_id, Offence::Malformed, std::time::Instant::now());
+        waited.sort_unstable_by_key(|digest| digest.value);
+        for digest in waited {
+            self.fetching.remove(&digest);
+            self.request_value(digest).await;
+        }
+    }
+
+    async fn store_proposal(&mut self, digest: TaggedHash, values: Vec<u64>) {
+        if self.proposals.contains_key(&digest) {
+            return;
+        }
+        self.proposals.insert(digest, values.clone());
+        self.fetching.remove(&digest);
+        if let Some(requesters) = self.pending_requests.remove(&digest) {
+            for replica in requesters {
+                self.respond(replica, values.clone()).await;
+            }
+        }
+    }
+
+    async fn respond(&mut self, replica: Replica, values: Vec<u64>) {
+        self.send(replica, ProtMsg::Response(Msg {
+            content: values,
+            origin: self.myid,
+        })).await;
+    }
+
+    /// Ask the next replica that should know the vector with `digest` for
+    /// it: the leader first, then its echoers, one at a time. Nothing happens
+    /// while a request for it is still out, or once we have the vector.
+    async fn request_value(&mut self, digest: TaggedHash) {
+        if self.proposals.contains_key(&digest) || self.fetching.contains_key(&digest) {
+            return;
+        }
+        let mut echoers: Vec<Replica> = self.vec_counts.get(&digest)
+            .map(|echoers| echoers.iter().copied().collect())
+            .unwrap_or_default();
+        echoers.sort_unstable();
+        let asked = self.requested.entry(digest).or_default();
+        let target = self.leader.into_iter()
+            .chain(echoers)
+            .find(|replica| *replica != self.myid && !asked.contains(replica));
+        // everyone we could ask was asked, so wait for the next echoer
+        let target = match target {
+            Some(target) => target,
+            None => return,
+        };
+        asked.insert(target);
+        self.fetching.insert(digest, (target, Instant::now() + Duration::from_millis(2*self.delta)));
+        log::info!("requesting vector for digest {:?} from node {:?}", digest, target);
+        self.send(target, ProtMsg::Request(digest)).await;
+    }
+
+    /// When the next request for a missing vector times out.
+    pub fn fetch_deadline(&self) -> Option<Instant> {
+        self.fetching.values().map(|(_, deadline)| *deadline).min()
+    }
 
-        let f_plus_one_responses = (self.num_faults + 1) as u64;
+    /// Ask someone else for the vectors whose requests timed out.
+    pub async fn fetch_timeout(&mut self) {
+        let now = Instant::now();
+        let mut expired: Vec<TaggedHash> = self.fetching.iter()
+            .filter(|(_, (_, deadline))| *deadline <= now)
+            .map(|(digest, _)| *digest)
+            .collect();
+        expired.sort_unstable_by_key(|digest| digest.value);
+        for digest in expired {
+            if let Some((target, _)) = self.fetching.remove(&digest) {
+                log::warn!("Node {:?} did not send the vector for digest {:?}", target, digest);
+            }
+            self.request_value(digest).await;
+        }
+    }
+
+    async fn check_echoes(&mut self) {
+        let f_plus_one_responses = self.num_faults + 1;
 
         let result = self.vec_counts.iter()
-            .find(|&(_, &count) | count == f_plus_one_responses)
-            .map(|(vec, _)| vec.clone());
+            .find(|&(_, echoers)| echoers.len() >= f_plus_one_responses)
+            .map(|(digest, _)| *digest);
 
         match result {
-            Some(vec) => {
-                log::info!("Found vector: {:?}", vec);
+            Some(digest) => {
+                log::info!("Found digest: {:?}", digest);
 
                 if self.echo {
                     // broadcast echo msg
-                    self.broadcast(ProtMsg::Echo(Msg {
-                        content: (vec.clone()), 
-                        origin: (self.myid) 
-                    })).await;
+                    self.broadcast(ProtMsg::Echo(digest)).await;
     
                     self.echo = false;
                 }
@@ -92,41 +241,155 @@ impl Context {
             None => (),
         }
 
+        if self.delivered {
+            return;
+        }
+
         // check if enough responses received for delivery
-        let n_minus_f_responses = (self.num_nodes - self.num_faults) as u64;
+        let n_minus_f_responses = self.num_nodes - self.num_faults;
 
         let delivery_res = self.vec_counts.iter()
-            .find(|&(_, &count) | count == n_minus_f_responses)
-            .map(|(vec2, _)| vec2.clone());
+            .find(|&(_, echoers)| echoers.len() >= n_minus_f_responses)
+            .map(|(digest, _)| *digest);
 
-        match delivery_res {
-            Some(vec2) => {
-                log::info!("Delivering vector: {:?}", vec2);
+        // wait for the vector itself if only its digest is known
+        let vec2 = match delivery_res.and_then(|digest| self.proposals.get(&digest)) {
+            Some(vec2) => vec2.clone(),
+            None => return,
+        };
+        self.delivered = true;
+        log::info!("Delivering vector: {:?}", vec2);
 
-                let mut vec3 = vec2.clone();
+        let mut vec3 = vec2.clone();
 
-                // get the median value
-                vec3.sort_unstable();
+        // get the median value
+        vec3.sort_unstable();
 
-                let result: String;
-    
-                let mid = vec3.len() / 2;
-                if vec3.len() % 2 == 0 {
-                    let mid_val = (vec3[mid - 1] as f64 + vec3[mid] as f64) / 2.0;
-                    result = format!("{:?}", mid_val);
-                } else {
-                    // If odd, return the middle element
-                    result = format!("{:?}", vec3[mid]);
-                }
+        let result: String;
 
-                self.terminate(result).await;
-            },
-            None => (),
+        let mid = vec3.len() / 2;
+        if vec3.len() % 2 == 0 {
+            let mid_val = (vec3[mid - 1] as f64 + vec3[mid] as f64) / 2.0;
+            result = format!("{:?}", mid_val);
+        } else {
+            // If odd, return the middle element
+            result = format!("{:?}", vec3[mid]);
+        }
+
+        self.terminate(result).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::Node;
+    use crypto::hash::{HashAlgorithm, TaggedHash};
+    use futures::FutureExt;
+    use fnv::FnvHashMap;
+    use tokio::{sync::oneshot, time::Instant};
+    use transport::{MemTransport, Transport, mesh};
+    use types::{ProtMsg, Replica, SyncMsg, WrapperMsg, Wrapped};
+
+    use crate::node::Context;
+
+    type Replica1 = Context<MemTransport<WrapperMsg>, MemTransport<SyncMsg>>;
+
+    /// Replica 1 of 4, led by replica 0, and the ends of the other replicas.
+    fn replica_1() -> (Replica1, FnvHashMap<Replica, MemTransport<WrapperMsg>>) {
+        let mut config = Node::new();
+        config.id = 1;
+        config.num_nodes = 4;
+        config.num_faults = 1;
+        for j in 0..4 {
+            config.sk_map.insert(j, vec![j as u8; crypto::SECRET_KEY_SIZE].into());
+        }
+        let mut nets = mesh(0..4);
+        let net = nets.remove(&1).unwrap();
+        let sync = mesh([0]).remove(&0).unwrap();
+        let (_, exit_rx) = oneshot::channel();
+        (Context::new(config, 20, false, net, sync, exit_rx).unwrap(), nets)
+    }
+
+    /// The digests replica 1 asked `net` for so far.
+    fn requests(net: &mut MemTransport<WrapperMsg>) -> Vec<TaggedHash> {
+        let mut digests = Vec::new();
+        while let Some(Some(msg)) = net.recv().now_or_never() {
+            if let Ok(ProtMsg::Request(digest)) = msg.protmsg() {
+                digests.push(digest);
+            }
         }
+        digests
+    }
+
+    #[tokio::test]
+    async fn a_replica_echoes_one_digest_once() {
+        let (mut ctx, _nets) = replica_1();
+        let (digest, other) = (HashAlgorithm::default().digest_of(&vec![10u64, 20, 30]), HashAlgorithm::default().digest_of(&vec![10u64]));
+        ctx.handle_echo(digest, 2).await;
+        ctx.handle_echo(digest, 2).await;
+        ctx.handle_echo(other, 2).await;
+        assert_eq!(ctx.vec_counts[&digest].len(), 1);
+        assert!(!ctx.vec_counts.contains_key(&other));
+        ctx.handle_echo(digest, 3).await;
+        assert_eq!(ctx.vec_counts[&digest].len(), 2);
+        assert_eq!(ctx.echo_quorum, 2);
+    }
+
+    #[tokio::test]
+    async fn a_digest_under_another_hash_function_is_rejected() {
+        let (mut ctx, _nets) = replica_1();
+        let digest = HashAlgorithm::Blake3.digest_of(&vec![10u64, 20, 30]);
+        ctx.peers.scheduler.push(ProtMsg::Echo(digest), 2);
+        ctx.peers.scheduler.push(ProtMsg::Request(digest), 3);
+        ctx.process_pending().await;
+        assert!(ctx.vec_counts.is_empty());
+        assert!(ctx.pending_requests.is_empty());
+        assert_eq!(ctx.peers.reputation.scores().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_missing_vector_is_fetched_from_one_replica_at_a_time() {
+        let (mut ctx, mut nets) = replica_1();
+        let values = vec![10u64, 20, 30];
+        let digest = HashAlgorithm::default().digest_of(&values);
+        // f + 1 echoes without the proposal: only the leader is asked
+        ctx.handle_echo(digest, 2).await;
+        ctx.handle_echo(digest, 3).await;
+        assert_eq!(requests(nets.get_mut(&0).unwrap()), vec![digest]);
+        assert!(requests(nets.get_mut(&2).unwrap()).is_empty());
+        assert!(requests(nets.get_mut(&3).unwrap()).is_empty());
+
+        // The leader stays silent, so the first echoer is asked next
+        ctx.fetching.get_mut(&digest).unwrap().1 = Instant::now();
+        ctx.fetch_timeout().await;
+        assert_eq!(requests(nets.get_mut(&2).unwrap()), vec![digest]);
+        assert!(requests(nets.get_mut(&3).unwrap()).is_empty());
+
+        ctx.handle_response(values.clone(), 2).await;
+        assert_eq!(ctx.proposals.get(&digest), Some(&values));
+        assert!(ctx.fetching.is_empty());
+        assert!(ctx.peers.reputation.scores().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_response_of_another_digest_is_rejected() {
+        let (mut ctx, mut nets) = replica_1();
+        let values = vec![10u64, 20, 30];
+        let digest = HashAlgorithm::default().digest_of(&values);
+        ctx.handle_echo(digest, 2).await;
+        ctx.handle_echo(digest, 3).await;
+        assert_eq!(requests(nets.get_mut(&0).unwrap()), vec![digest]);
+
+        // The leader answers with another vector, and the next echoer is asked
+        ctx.handle_response(vec![10, 20, 31], 0).await;
+        assert!(ctx.proposals.is_empty());
+        assert_eq!(ctx.peers.reputation.scores().len(), 1);
+        assert_eq!(ctx.peers.reputation.scores()[0].0, 0);
+        assert_eq!(requests(nets.get_mut(&2).unwrap()), vec![digest]);
 
-        // if self.echo_quorum == self.num_nodes - self.num_faults {
-        //     // deliver values
-        //     log::info!("Delivering value vector {:?}", values);
-        // }
+        // Nobody asked replica 3 for anything
+        ctx.handle_response(values, 3).await;
+        assert!(ctx.proposals.is_empty());
+        assert_eq!(ctx.peers.reputation.scores().len(), 2);
     }
-}
\ No newline at end of file
+}
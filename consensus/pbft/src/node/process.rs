@@ -1,45 +1,53 @@
-use std::{sync::Arc};
+use std::time::Instant;
 
-use crypto::hash::{verf_mac};
-use types::{ProtMsg, WrapperMsg};
+use transport::Transport;
+use types::{Offence, Replica, ProtMsg, SyncMsg, WrapperMsg};
 use crate::node::{
     context::Context
 };
-impl Context{
-    // This function verifies the Message Authentication Code (MAC) of a sent message
-    // A node cannot impersonate as another node because of MACs
-    pub fn check_proposal(&self,wrapper_msg: Arc<WrapperMsg>) -> bool {
-        // validate MAC
-        let byte_val = bincode::serialize(&wrapper_msg.protmsg).expect("Failed to serialize object");
-        let sec_key = match self.sec_key_map.get(&wrapper_msg.clone().sender) {
-            Some(val) => {val},
-            None => {panic!("Secret key not available, this shouldn't happen")},
-        };
-        if !verf_mac(&byte_val,&sec_key.as_slice(),&wrapper_msg.mac){
-            log::warn!("MAC Verification failed.");
-            return false;
+impl<N: Transport<WrapperMsg>, S: Transport<SyncMsg>> Context<N, S> {
+    /// Handle our own messages and the verified ones from the network, most
+    /// urgent first.
+    pub(crate) async fn process_pending(&mut self) {
+        while let Some((protmsg, sender)) = self.peers.pop() {
+            self.handle_protmsg(protmsg, sender).await;
         }
-        true
     }
-    
-    pub(crate) async fn process_msg(&mut self, wrapper_msg: WrapperMsg){
-        log::debug!("Received protocol msg: {:?}",wrapper_msg);
-        let msg = Arc::new(wrapper_msg.clone());
-        if self.check_proposal(msg){
-            match wrapper_msg.clone().protmsg {
-                ProtMsg::Init(main_msg) => {
-                    self.handle_init(main_msg, wrapper_msg.sender).await;
-                },
-                ProtMsg::Prepare(msg) => {
-                    self.handle_prepare(msg.content, msg.origin).await;
-                },
-                ProtMsg::Echo(msg) => {
-                    self.handle_echo(msg.content, msg.origin).await;
-                }
+
+    async fn handle_protmsg(&mut self, protmsg: ProtMsg, sender: Replica) {
+        // A digest under another hash function never matches a proposal
+        if let ProtMsg::Echo(digest) | ProtMsg::Request(digest) = &protmsg {
+            if digest.alg != self.hash {
+                log::warn!("Digest under {:?} from node {}", digest.alg, sender);
+                self.peers.reputation.penalize(sender, Offence::Malformed, Instant::now());
+                return;
             }
         }
-        else {
-            log::warn!("MAC Verification failed for message {:?}",wrapper_msg.protmsg);
+        match protmsg {
+            ProtMsg::Init(main_msg) => {
+                self.handle_init(main_msg, sender).await;
+            },
+            ProtMsg::Prepare(msg) => {
+                self.handle_prepare(msg.content, sender).await;
+            },
+            ProtMsg::Echo(digest) => {
+                self.handle_echo(digest, sender).await;
+            },
+            ProtMsg::Request(digest) => {
+                self.handle_request(digest, sender).await;
+            },
+            ProtMsg::Response(msg) => {
+                self.handle_response(msg.content, sender).await;
+            },
+            ProtMsg::Ticket(ticket) => {
+                self.handle_ticket(ticket, sender).await;
+            },
+            ProtMsg::ElectEcho(ticket) => {
+                self.handle_elect_echo(ticket, sender).await;
+            },
+            ProtMsg::ElectReady(leader) => {
+                self.handle_elect_ready(leader, sender).await;
+            }
         }
     }
-}
\ No newline at end of file
+}
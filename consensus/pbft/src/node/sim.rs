@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+
+use config::Node;
+use crypto::{Algorithm, ed25519};
+use sim::{Envelope, SimNetwork, SimSender, SimTransport};
+use tokio::sync::mpsc::unbounded_channel;
+use types::{Replica, SyncMsg, SyncState};
+
+use super::Context;
+
+/// Configs for `n` replicas, which elect their leader if `elect`.
+fn configs(n: usize, f: usize, elect: bool) -> Vec<Node> {
+    let identities: Vec<_> = (0..n).map(|_| ed25519::Keypair::generate()).collect();
+    (0..n).map(|i| {
+        let mut config = Node::new();
+        config.id = i;
+        config.num_nodes = n;
+        config.num_faults = f;
+        for j in 0..n {
+            // Both ends of a channel share its key
+            config.sk_map.insert(j, vec![(i.min(j)*n + i.max(j)) as u8; crypto::SECRET_KEY_SIZE].into());
+        }
+        if elect {
+            config.crypto_alg = Algorithm::ED25519;
+            config.delta = 100;
+            config.session = "sim".to_string();
+            config.secret_key_bytes = identities[i].encode().to_vec().into();
+            for (j, identity) in identities.iter().enumerate() {
+                config.pk_map.insert(j, identity.public().encode().to_vec());
+            }
+        }
+        config
+    }).collect()
+}
+
+/// Play the syncer for `n` simulated replicas and return what every
+/// replica delivered along with the delivery order of the network.
+async fn run(seed: u64, n: usize, f: usize, elect: bool) -> (Vec<String>, Vec<(Replica, Replica)>) {
+    let mut net = SimNetwork::new(seed);
+    let (sync_tx, mut sync_rx) = unbounded_channel::<Envelope<SyncMsg>>();
+    let mut starts = Vec::new();
+    let mut exits = Vec::new();
+    for (i, config) in configs(n, f, elect).into_iter().enumerate() {
+        let (start_tx, start_rx) = unbounded_channel();
+        starts.push(start_tx);
+        exits.push(Context::spawn_with(
+            config,
+            10*(i as u64 + 1),
+            false,
+            net.register(i),
+            SimTransport::new(SimSender::new(i, sync_tx.clone()), start_rx),
+        ).unwrap());
+    }
+    let scheduler = net.spawn();
+
+    let mut alive = HashSet::new();
+    let mut completed = vec![None; n];
+    while completed.iter().any(|c| c.is_none()) {
+        let msg = sync_rx.recv().await.unwrap().open();
+        match msg.state {
+            SyncState::ALIVE => {
+                alive.insert(msg.sender);
+                if alive.len() == n {
+                    for start in &starts {
+                        start.send(SyncMsg { sender: 0, state: SyncState::START, value: "".to_string() }).unwrap();
+                    }
+                }
+            },
+            SyncState::COMPLETED => completed[msg.sender] = Some(msg.value),
+            _ => {},
+        }
+    }
+    for exit in exits {
+        let _ = exit.send(());
+    }
+    let trace = scheduler.await.unwrap();
+    (completed.into_iter().map(Option::unwrap).collect(), trace)
+}
+
+#[tokio::test]
+async fn simulated_replicas_agree() {
+    for seed in 0..8 {
+        let (values, trace) = run(seed, 4, 1, false).await;
+        assert!(values.iter().all(|v| *v == values[0]), "seed {}: {:?}", seed, values);
+        assert_eq!(run(seed, 4, 1, false).await.1, trace, "seed {} is not reproducible", seed);
+    }
+}
+
+// The election waits on a timer, so its runs are not reproducible
+#[tokio::test]
+async fn simulated_replicas_elect_a_leader_and_agree() {
+    for seed in 0..4 {
+        let (values, _) = run(seed, 4, 1, true).await;
+        assert!(values.iter().all(|v| *v == values[0]), "seed {}: {:?}", seed, values);
+    }
+}
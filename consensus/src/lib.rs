@@ -0,0 +1,10 @@
+//! Building blocks shared by the consensus protocols.
+
+mod scheduler;
+pub use scheduler::*;
+
+mod verifier;
+pub use verifier::*;
+
+mod peers;
+pub use peers::*;
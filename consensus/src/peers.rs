@@ -0,0 +1,348 @@
+use std::{collections::{HashMap, VecDeque}, time::Instant};
+
+use anyhow::{Result, anyhow};
+use config::Node;
+use crypto::hash::Algorithms;
+use futures::FutureExt;
+use network::{plaintcp::CancelHandler, Acknowledgement, Message};
+use transport::{KeyWatch, PendingAcks, Slot, Transport};
+use types::{Nonces, Offence, Prioritized, Replica, Reputation, Wrapped};
+
+use crate::{Checked, Scheduler, Verifier, SCHEDULER_BATCH};
+
+/// What every protocol does with its messages, whatever they are: MAC them
+/// for their recipients on the way out, and on the way in check their MACs
+/// on the verifiers, drop replays, keep score of misbehaving peers and
+/// queue the rest by priority. A protocol's `Context` only dispatches the
+/// protocol messages `W` wraps.
+pub struct Peers<W: Wrapped, N> {
+    /// Consensus transport
+    pub net: N,
+    pub myid: Replica,
+    pub num_nodes: usize,
+
+    /// Secret Key map
+    pub sec_key_map: HashMap<Replica, Vec<u8>>,
+    /// Keys a peer just rotated out, still accepted for its messages in flight
+    pub prev_key_map: HashMap<Replica, Vec<u8>>,
+    /// Keys derived by the transport, if it derives them
+    pub key_watch: Option<KeyWatch>,
+    /// Nonces of our messages and of the ones we accepted
+    pub nonces: Nonces,
+    /// Misbehaviour scores of the other replicas
+    pub reputation: Reputation,
+    /// Whether the transport vouches for the sender of every message, so
+    /// that a message that fails its checks can be blamed on its sender
+    pub authenticated: bool,
+    /// Messages to ourselves, which skip the network
+    pub local: VecDeque<W::Msg>,
+    /// Verified messages from the network, waiting to be handled by priority
+    pub scheduler: Scheduler<W::Msg>,
+    /// Tasks verifying the MACs of messages from the network
+    pub verifier: Verifier<W>,
+    /// Encrypt the payloads of our messages for their recipients
+    pub confidential: bool,
+    /// The hash function and MAC our messages are sealed with
+    pub algorithms: Algorithms,
+
+    /// Cancel handlers of the messages not acknowledged yet
+    pub cancel_handlers: PendingAcks,
+}
+
+impl<W, N> Peers<W, N>
+where
+    W: Wrapped + Message,
+    W::Msg: Prioritized + Send,
+    N: Transport<W>,
+{
+    /// The peers of replica `config.id`, reached over `net`.
+    pub fn new(config: &Node, net: N) -> Self {
+        let key_watch = net.key_watch();
+        let authenticated = net.authenticates_senders();
+        Self {
+            net,
+            myid: config.id,
+            num_nodes: config.num_nodes,
+            sec_key_map: config.sk_map.iter().map(|(id, sk)| (*id, sk.to_vec())).collect(),
+            prev_key_map: HashMap::default(),
+            key_watch,
+            nonces: Nonces::new(),
+            reputation: Reputation::new(config.num_nodes),
+            authenticated,
+            local: VecDeque::new(),
+            scheduler: Scheduler::new(&config.priorities),
+            verifier: Verifier::new(config.id, config.verifiers, config.confidential, config.algorithms()),
+            confidential: config.confidential,
+            algorithms: config.algorithms(),
+            cancel_handlers: PendingAcks::new(),
+        }
+    }
+
+    /// Wait until the transport can carry protocol messages, e.g. until
+    /// keys are agreed with the other replicas.
+    pub async fn ready(&mut self) {
+        self.net.ready().await;
+        self.refresh_keys();
+    }
+
+    /// Pick up keys the transport derived since the last call.
+    pub fn refresh_keys(&mut self) {
+        if let Some(ring) = self.key_watch.as_mut().and_then(KeyWatch::fresh) {
+            self.sec_key_map = ring.current;
+            self.prev_key_map = ring.previous;
+        }
+    }
+
+    pub fn add_cancel_handler(&mut self, slot: Slot, canc: CancelHandler<Acknowledgement>) {
+        self.cancel_handlers.add(slot, canc);
+    }
+
+    /// Send `protmsg` to every replica in `to`. It is serialized once, with
+    /// a MAC for every peer, and our own copy goes to the local queue. The
+    /// cancel handlers are kept under `slot`.
+    pub async fn multicast(&mut self, protmsg: W::Msg, to: impl IntoIterator<Item = Replica>, slot: Slot) {
+        let mut peers = Vec::new();
+        let mut to_self = false;
+        for replica in to {
+            if replica == self.myid {
+                to_self = true;
+            } else if self.sec_key_map.contains_key(&replica) {
+                peers.push(replica);
+            } else {
+                log::warn!("No key shared with replica {}, not sending to it", replica);
+            }
+        }
+        if !peers.is_empty() {
+            let nonce = self.nonces.issue();
+            let keys = peers.iter().map(|replica| (*replica, self.sec_key_map[replica].as_slice()));
+            let wrapper_msg = W::wrap(&protmsg, self.myid, nonce, keys, self.confidential, self.algorithms);
+            for cancel_handler in self.net.broadcast(wrapper_msg, peers).await {
+                self.add_cancel_handler(slot, cancel_handler);
+            }
+        }
+        if to_self {
+            self.local.push_back(protmsg);
+        }
+    }
+
+    /// Send `protmsg` to every replica we share a key with, ourselves
+    /// included.
+    pub async fn broadcast(&mut self, protmsg: W::Msg, slot: Slot) {
+        let mut all: Vec<Replica> = self.sec_key_map.keys().copied().collect();
+        all.sort_unstable();
+        self.multicast(protmsg, all, slot).await;
+    }
+
+    pub async fn send(&mut self, replica: Replica, protmsg: W::Msg, slot: Slot) {
+        self.multicast(protmsg, [replica], slot).await;
+    }
+
+    // Charge the sender of a message with `offence`, if the message is
+    // known to be from it. Otherwise anyone could get it banned by sending
+    // garbage in its name
+    fn blame(&mut self, sender: Replica, offence: Offence, now: Instant) {
+        if self.authenticated {
+            self.reputation.penalize(sender, offence, now);
+        }
+    }
+
+    // Drop a message before it goes to the verifiers if its sender is
+    // banned or throttled, if it is not meant for us or if we share no key
+    // with its sender. Returns the keys to check its MAC with
+    fn precheck(&mut self, wrapper_msg: &W) -> Option<(Vec<u8>, Option<Vec<u8>>)> {
+        let sender = wrapper_msg.sender();
+        let now = Instant::now();
+        // Only an authenticated sender is rate limited before its MAC is
+        // checked, so that nobody eats up the allowance of another
+        let admitted = if self.authenticated {
+            self.reputation.admit(sender, now)
+        } else {
+            !self.reputation.is_banned(sender, now)
+        };
+        if !admitted {
+            log::debug!("Dropping a message from throttled replica {}", sender);
+            return None;
+        }
+        if !wrapper_msg.is_for(self.myid) {
+            log::warn!("Message from {} is not addressed to us", sender);
+            self.blame(sender, Offence::Malformed, now);
+            return None;
+        }
+        let sec_key = match self.sec_key_map.get(&sender) {
+            Some(val) => {val.clone()},
+            None => {
+                log::warn!("No key shared with replica {}", sender);
+                self.blame(sender, Offence::Unexpected, now);
+                return None;
+            },
+        };
+        Some((sec_key, self.prev_key_map.get(&sender).cloned()))
+    }
+
+    // This function checks a message whose Message Authentication Code (MAC)
+    // the verifiers are done with.
+    // A node cannot impersonate as another node because of MACs, and cannot
+    // replay a message because of the nonces. A bad MAC or a replay only
+    // costs the sender reputation if the transport authenticated it, as
+    // anyone can forge the one or capture the other
+    fn check_proposal(&mut self, checked: &Checked<W>) -> bool {
+        let sender = checked.msg.sender();
+        let now = Instant::now();
+        if !checked.valid {
+            log::warn!("MAC Verification failed.");
+            self.blame(sender, Offence::InvalidMac, now);
+            return false;
+        }
+        if !self.nonces.accept(sender, checked.msg.nonce()) {
+            log::warn!("Dropping a replayed message from {}", sender);
+            self.blame(sender, Offence::Unexpected, now);
+            return false;
+        }
+        // The MAC vouches for the sender now
+        if !self.authenticated && !self.reputation.admit(sender, now) {
+            log::debug!("Dropping a message from throttled replica {}", sender);
+            return false;
+        }
+        true
+    }
+
+    /// Hand a message from the network to the verifiers.
+    fn process_msg(&mut self, wrapper_msg: W) {
+        log::debug!("Received protocol msg: {:?}", wrapper_msg);
+        match self.precheck(&wrapper_msg) {
+            Some((key, previous)) => self.verifier.submit(wrapper_msg, key, previous),
+            None => log::warn!("Dropped message from {}", wrapper_msg.sender()),
+        }
+    }
+
+    /// Take a message the verifiers are done with, and queue it by priority
+    /// if it checks out.
+    fn process_checked(&mut self, checked: Checked<W>) {
+        if !self.check_proposal(&checked) {
+            log::warn!("Dropped message from {}", checked.msg.sender());
+            return;
+        }
+        let wrapper_msg = checked.msg;
+        match wrapper_msg.protmsg() {
+            Ok(protmsg) => self.scheduler.push(protmsg, wrapper_msg.sender()),
+            Err(e) => {
+                log::warn!("Undecodable message from {}: {}", wrapper_msg.sender(), e);
+                self.reputation.penalize(wrapper_msg.sender(), Offence::Malformed, Instant::now());
+            }
+        }
+    }
+
+    /// Wait for messages from the network and hand them to the verifiers,
+    /// or for verified ones and queue them by priority. Fails once the
+    /// transport or the verifiers stop.
+    pub async fn receive(&mut self) -> Result<()> {
+        tokio::select! {
+            // Poll in a fixed order so that simulated runs replay exactly
+            biased;
+            // Verified messages go first, so that the verifiers drain
+            checked = self.verifier.recv() => {
+                let checked = checked.ok_or_else(||
+                    anyhow!("The verifiers have stopped")
+                )?;
+                self.process_checked(checked);
+                // Take whatever else was verified, so that urgent
+                // messages overtake the ones that arrived before them
+                for _ in 1..SCHEDULER_BATCH {
+                    match self.verifier.recv().now_or_never() {
+                        Some(Some(checked)) => self.process_checked(checked),
+                        _ => break,
+                    }
+                }
+            },
+            msg = self.net.recv(), if self.verifier.has_room() => {
+                // Received messages are processed here
+                log::debug!("Got a consensus message from the network: {:?}", msg);
+                let msg = msg.ok_or_else(||
+                    anyhow!("Networking layer has closed")
+                )?;
+                self.refresh_keys();
+                self.process_msg(msg);
+                // Hand whatever else has arrived to the verifiers too
+                while self.verifier.has_room() {
+                    match self.net.recv().now_or_never() {
+                        Some(Some(msg)) => {
+                            self.refresh_keys();
+                            self.process_msg(msg);
+                        },
+                        _ => break,
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// The next message to handle and its sender: our own messages first,
+    /// then the verified ones, most urgent first.
+    pub fn pop(&mut self) -> Option<(W::Msg, Replica)> {
+        match self.local.pop_front() {
+            Some(protmsg) => Some((protmsg, self.myid)),
+            None => self.scheduler.pop(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use network::Acknowledgement;
+    use transport::{mesh, MemTransport};
+    use types::{ProtMsg, WrapperMsg, BAN_THRESHOLD};
+
+    use super::*;
+
+    /// A mesh whose links vouch for their senders, as TLS does.
+    struct Vouched(MemTransport<WrapperMsg>);
+
+    #[async_trait]
+    impl Transport<WrapperMsg> for Vouched {
+        async fn send(&mut self, to: Replica, msg: WrapperMsg) -> CancelHandler<Acknowledgement> {
+            self.0.send(to, msg).await
+        }
+
+        async fn recv(&mut self) -> Option<WrapperMsg> {
+            self.0.recv().await
+        }
+
+        fn authenticates_senders(&self) -> bool {
+            true
+        }
+    }
+
+    // Replica 1 sends replica 0 messages with a bad MAC, and returns the
+    // scores replica 0 keeps
+    async fn bad_macs<N: Transport<WrapperMsg>>(net: N, mut sender: MemTransport<WrapperMsg>) -> Vec<(Replica, u64)> {
+        let mut config = Node::new();
+        config.num_nodes = 3;
+        for j in 0..3 {
+            config.sk_map.insert(j, vec![j as u8; crypto::SECRET_KEY_SIZE].into());
+        }
+        let mut peers = Peers::new(&config, net);
+        for i in 0..20 {
+            let msg = WrapperMsg::new(&ProtMsg::Init(i), 1, i, [(0, &[9u8; 32][..])], false, Algorithms::default());
+            sender.send(0, msg).await;
+        }
+        while let Ok(received) = tokio::time::timeout(std::time::Duration::from_millis(100), peers.receive()).await {
+            received.unwrap();
+        }
+        assert!(peers.pop().is_none());
+        peers.reputation.scores()
+    }
+
+    #[tokio::test]
+    async fn forged_messages_cost_nothing_unless_the_transport_vouches() {
+        // Anyone could have sent these in replica 1's name
+        let mut nets = mesh([0, 1]);
+        let (net, sender) = (nets.remove(&0).unwrap(), nets.remove(&1).unwrap());
+        assert!(bad_macs(net, sender).await.is_empty());
+
+        let mut nets = mesh([0, 1]);
+        let (net, sender) = (nets.remove(&0).unwrap(), nets.remove(&1).unwrap());
+        assert_eq!(bad_macs(Vouched(net), sender).await, vec![(1, BAN_THRESHOLD)]);
+    }
+}
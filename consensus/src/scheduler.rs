@@ -0,0 +1,128 @@
+use std::{cmp::Reverse, collections::{BTreeMap, HashMap}};
+
+use priority_queue::PriorityQueue;
+use types::{Prioritized, Priority, Replica};
+
+/// The most messages taken from the network before the waiting ones are
+/// handled.
+pub const SCHEDULER_BATCH: usize = 256;
+/// How many messages may arrive after a message before it is handled
+/// ahead of more urgent ones, so that no class starves.
+pub const SCHEDULER_MAX_AGE: u64 = 4 * SCHEDULER_BATCH as u64;
+
+/// Received messages waiting to be handled, most urgent first and in order
+/// of arrival within a class, except for messages that waited longer than
+/// `SCHEDULER_MAX_AGE`, which go first. The class of a message is the one
+/// its kind is configured with, or its default otherwise.
+#[derive(Debug)]
+pub struct Scheduler<M> {
+    queue: PriorityQueue<u64, (Priority, Reverse<u64>)>,
+    // By arrival, with their senders
+    waiting: BTreeMap<u64, (M, Replica)>,
+    next: u64,
+    classes: HashMap<String, Priority>,
+}
+
+impl<M: Prioritized> Scheduler<M> {
+    /// `classes` overrides the class of some kinds of messages. Kinds that
+    /// `M` does not have are ignored, with a warning.
+    pub fn new<'a>(classes: impl IntoIterator<Item = (&'a String, &'a Priority)>) -> Self {
+        let classes = classes.into_iter()
+            .filter(|(kind, _)| {
+                let known = M::KINDS.contains(&kind.as_str());
+                if !known {
+                    log::warn!("Ignoring the priority of unknown message kind {}", kind);
+                }
+                known
+            })
+            .map(|(kind, priority)| (kind.clone(), *priority))
+            .collect();
+        Self {
+            queue: PriorityQueue::new(),
+            waiting: BTreeMap::new(),
+            next: 0,
+            classes,
+        }
+    }
+
+    pub fn priority(&self, msg: &M) -> Priority {
+        self.classes.get(msg.kind())
+            .copied()
+            .unwrap_or_else(|| msg.priority())
+    }
+
+    /// Queue `msg`, received from `sender`.
+    pub fn push(&mut self, msg: M, sender: Replica) {
+        let seq = self.next;
+        self.next += 1;
+        self.queue.push(seq, (self.priority(&msg), Reverse(seq)));
+        self.waiting.insert(seq, (msg, sender));
+    }
+
+    /// The most urgent message that has waited longest, and its sender, or
+    /// the oldest message if it is overdue.
+    pub fn pop(&mut self) -> Option<(M, Replica)> {
+        let oldest = *self.waiting.keys().next()?;
+        let seq = if self.next - oldest > SCHEDULER_MAX_AGE {
+            self.queue.remove(&oldest);
+            oldest
+        } else {
+            self.queue.pop()?.0
+        };
+        self.waiting.remove(&seq)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::hash::{EMPTY_HASH, HashAlgorithm, Tagged};
+    use types::ProtMsg;
+
+    #[test]
+    fn urgent_messages_overtake() {
+        let classes: HashMap<String, Priority> = vec![("Init".to_string(), Priority::Bulk)].into_iter().collect();
+        let mut scheduler = Scheduler::<ProtMsg>::new(&classes);
+        let msgs = [
+            ProtMsg::Init(1),
+            ProtMsg::Response(types::Msg { content: vec![], origin: 0 }),
+            ProtMsg::Echo(Tagged { alg: HashAlgorithm::default(), value: EMPTY_HASH }),
+            ProtMsg::Request(Tagged { alg: HashAlgorithm::default(), value: EMPTY_HASH }),
+            ProtMsg::Echo(Tagged { alg: HashAlgorithm::default(), value: EMPTY_HASH }),
+        ];
+        for (sender, msg) in msgs.iter().enumerate() {
+            scheduler.push(msg.clone(), sender);
+        }
+        assert_eq!(scheduler.len(), 5);
+        let order: Vec<Replica> = std::iter::from_fn(|| scheduler.pop())
+            .map(|(_, sender)| sender)
+            .collect();
+        // Both echoes in order, then the init, which is configured as bulk,
+        // the response and the request in order of arrival
+        assert_eq!(order, vec![2, 4, 0, 1, 3]);
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn bulk_messages_do_not_starve() {
+        let mut scheduler = Scheduler::<ProtMsg>::new(&HashMap::<String, Priority>::default());
+        scheduler.push(ProtMsg::Response(types::Msg { content: vec![], origin: 0 }), 0);
+        // Votes keep arriving faster than they are handled
+        let mut popped = Vec::new();
+        for _ in 0..SCHEDULER_MAX_AGE {
+            scheduler.push(ProtMsg::Echo(Tagged { alg: HashAlgorithm::default(), value: EMPTY_HASH }), 1);
+            scheduler.push(ProtMsg::Echo(Tagged { alg: HashAlgorithm::default(), value: EMPTY_HASH }), 1);
+            popped.push(scheduler.pop().unwrap().1);
+        }
+        let at = popped.iter().position(|sender| *sender == 0).expect("the response starved");
+        assert!(at as u64 <= SCHEDULER_MAX_AGE / 2);
+    }
+}
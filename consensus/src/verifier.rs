@@ -0,0 +1,178 @@
+use crypto::hash::Algorithms;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use types::{Authenticated, Replica};
+
+/// The most messages a verifier takes at once.
+pub const VERIFIER_BATCH: usize = 64;
+/// The most messages waiting to be verified. The replica stops taking
+/// messages from the network beyond this, so that a backlog stays in the
+/// transport's bounded queues.
+pub const VERIFIER_DEPTH: usize = 1024;
+
+/// A message to verify, with the key its sender shares with us and the key
+/// the sender just rotated out, if any.
+struct Job<M> {
+    msg: M,
+    key: Vec<u8>,
+    previous: Option<Vec<u8>>,
+}
+
+/// A message the verifiers are done with.
+#[derive(Debug)]
+pub struct Checked<M> {
+    pub msg: M,
+    /// Whether its MAC for us verified, and its payload decrypted if it
+    /// was encrypted
+    pub valid: bool,
+}
+
+/// A pool of tasks that verify the MACs of received messages off the
+/// consensus task. Messages are spread over the verifiers by sender, so
+/// the messages of a sender come out in the order they went in.
+#[derive(Debug)]
+pub struct Verifier<M> {
+    myid: Replica,
+    size: usize,
+    confidential: bool,
+    algs: Algorithms,
+    workers: Vec<UnboundedSender<Job<M>>>,
+    done_tx: UnboundedSender<Checked<M>>,
+    done_rx: UnboundedReceiver<Checked<M>>,
+    in_flight: usize,
+}
+
+impl<M: Authenticated> Verifier<M> {
+    /// A pool of `size` verifiers for the messages to `myid`, or of one per
+    /// core if `size` is 0. The verifiers are started on the first message.
+    /// If `confidential`, payloads in the clear are rejected. MACs are
+    /// checked under `algs`.
+    pub fn new(myid: Replica, size: usize, confidential: bool, algs: Algorithms) -> Self {
+        let size = match size {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let (done_tx, done_rx) = unbounded_channel();
+        Self {
+            myid,
+            size,
+            confidential,
+            algs,
+            workers: Vec::new(),
+            done_tx,
+            done_rx,
+            in_flight: 0,
+        }
+    }
+
+    /// Whether there is room for another message.
+    pub fn has_room(&self) -> bool {
+        self.in_flight < VERIFIER_DEPTH
+    }
+
+    /// Verify `msg` with `key`, or with `previous` if `key` fails.
+    pub fn submit(&mut self, msg: M, key: Vec<u8>, previous: Option<Vec<u8>>) {
+        if self.workers.is_empty() {
+            for _ in 0..self.size {
+                let (tx, rx) = unbounded_channel();
+                tokio::spawn(verify(self.myid, self.confidential, self.algs, rx, self.done_tx.clone()));
+                self.workers.push(tx);
+            }
+        }
+        let worker = &self.workers[msg.sender() % self.size];
+        if worker.send(Job { msg, key, previous }).is_ok() {
+            self.in_flight += 1;
+        }
+    }
+
+    /// The next message the verifiers are done with. Never resolves while
+    /// no message is being verified.
+    pub async fn recv(&mut self) -> Option<Checked<M>> {
+        let checked = self.done_rx.recv().await?;
+        self.in_flight -= 1;
+        Some(checked)
+    }
+}
+
+async fn verify<M: Authenticated>(
+    myid: Replica,
+    confidential: bool,
+    algs: Algorithms,
+    mut rx: UnboundedReceiver<Job<M>>,
+    done: UnboundedSender<Checked<M>>,
+) {
+    while let Some(job) = rx.recv().await {
+        let mut batch = vec![job];
+        while batch.len() < VERIFIER_BATCH {
+            match rx.try_recv() {
+                Ok(job) => batch.push(job),
+                Err(_) => break,
+            }
+        }
+        let valid = {
+            let keyed: Vec<(&M, &[u8])> = batch.iter()
+                .map(|job| (&job.msg, job.key.as_slice()))
+                .collect();
+            M::verify_batch(myid, &keyed, algs)
+        };
+        for (mut job, valid) in batch.into_iter().zip(valid) {
+            // The payload is decrypted with the key its MAC verified with
+            let key = if valid {
+                Some(&job.key)
+            } else {
+                job.previous.as_ref().filter(|prev| job.msg.verify(myid, prev, algs))
+            };
+            let valid = match key {
+                Some(key) => job.msg.decrypt(myid, key, confidential),
+                None => false,
+            };
+            if done.send(Checked { msg: job.msg, valid }).is_err() {
+                return;
+            }
+        }
+        // Let the other tasks on this thread run between batches
+        tokio::task::yield_now().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::{ProtMsg, Wrapped, WrapperMsg};
+
+    #[tokio::test]
+    async fn verified_in_order_per_sender() {
+        let keys = [[1u8; 32], [2u8; 32]];
+        let mut verifier = Verifier::<WrapperMsg>::new(0, 2, false, Algorithms::default());
+        let mut sent = Vec::new();
+        for i in 0..20u64 {
+            let sender = (i % 2) as Replica;
+            let forged = i == 7;
+            let key = if forged { [3u8; 32] } else { keys[sender] };
+            // Some are encrypted, and come out decrypted
+            let msg = WrapperMsg::new(&ProtMsg::Init(i), sender, i, [(0, &key[..])], i % 3 == 0, Algorithms::default());
+            // Replica 1 rotated its key, the old one is still accepted
+            let (key, previous) = match sender {
+                1 => (vec![9u8; 32], Some(keys[1].to_vec())),
+                _ => (keys[0].to_vec(), None),
+            };
+            verifier.submit(msg, key, previous);
+            sent.push((sender, i, !forged));
+        }
+        let mut got = Vec::new();
+        while got.len() < sent.len() {
+            let checked = verifier.recv().await.unwrap();
+            if checked.valid {
+                assert!(matches!(checked.msg.protmsg(), Ok(ProtMsg::Init(i)) if i == checked.msg.nonce));
+            }
+            got.push((checked.msg.sender, checked.msg.nonce, checked.valid));
+        }
+        for sender in 0..2 {
+            let of = |msgs: &Vec<(Replica, u64, bool)>| msgs.iter()
+                .filter(|(s, _, _)| *s == sender)
+                .cloned()
+                .collect::<Vec<_>>();
+            assert_eq!(of(&got), of(&sent));
+        }
+        assert!(verifier.has_room());
+    }
+}
@@ -0,0 +1,115 @@
+//! Authenticated encryption with ChaCha20-Poly1305, and keys derived from
+//! passphrases with Argon2id.
+
+use argon2::{Argon2, Params};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce, aead::{Aead, Payload}};
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use zeroize::Zeroizing;
+
+use crate::{error::{DecodingError, DecryptionError}, hash::MacAlgorithm};
+
+pub const KEY_SIZE: usize = 32;
+pub const NONCE_SIZE: usize = 12;
+/// The bytes a ciphertext is longer than its plaintext
+pub const TAG_SIZE: usize = 16;
+
+/// A fresh random key, zeroed once dropped.
+pub fn random_key() -> Zeroizing<[u8; KEY_SIZE]> {
+    let mut key = Zeroizing::new([0u8; KEY_SIZE]);
+    rand::thread_rng().fill_bytes(&mut key[..]);
+    key
+}
+
+/// A key for `label` derived from `key`, so that a key is never used both
+/// to MAC and to encrypt.
+pub fn derive_key(key: &[u8], label: &[u8]) -> Zeroizing<[u8; KEY_SIZE]> {
+    Zeroizing::new(MacAlgorithm::HmacSha256.mac(label, key))
+}
+
+/// A fresh random nonce. With random nonces, a key should seal well under
+/// 2^32 messages.
+pub fn random_nonce() -> [u8; NONCE_SIZE] {
+    let mut nonce = [0u8; NONCE_SIZE];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    nonce
+}
+
+/// Encrypt `plaintext` under `key`, authenticating `aad` along with it. A
+/// nonce must never be used twice with the same key.
+pub fn seal(key: &[u8; KEY_SIZE], nonce: &[u8; NONCE_SIZE], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(Nonce::from_slice(nonce), Payload { msg: plaintext, aad })
+        .expect("ChaCha20-Poly1305 encrypts messages of any size we send")
+}
+
+/// Decrypt a ciphertext from `seal`. Fails if it or `aad` were tampered
+/// with, or if it was sealed under another key or nonce.
+pub fn open(key: &[u8; KEY_SIZE], nonce: &[u8; NONCE_SIZE], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, DecryptionError> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+        .map_err(|_| DecryptionError::new("wrong key, or the ciphertext was tampered with"))
+}
+
+/// The cost of deriving a key from a passphrase with Argon2id, kept with
+/// whatever the key encrypts.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct KdfParams {
+    pub salt: Vec<u8>,
+    /// Memory in KiB
+    pub m_cost: u32,
+    /// Passes over the memory
+    pub t_cost: u32,
+    /// Lanes
+    pub p_cost: u32,
+}
+
+impl KdfParams {
+    /// The recommended costs, with a fresh salt: 19 MiB of memory and two
+    /// passes, a fraction of a second on a server.
+    pub fn generate() -> KdfParams {
+        let mut salt = vec![0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        KdfParams {
+            salt,
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+
+    /// Derive the key of `passphrase`. The key is zeroed once dropped.
+    pub fn derive_key(&self, passphrase: &[u8]) -> Result<Zeroizing<[u8; KEY_SIZE]>, DecodingError> {
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(KEY_SIZE))
+            .map_err(|e| DecodingError::new(format!("Argon2 parameters: {}", e)))?;
+        let mut key = Zeroizing::new([0u8; KEY_SIZE]);
+        Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+            .hash_password_into(passphrase, &self.salt, &mut key[..])
+            .map_err(|e| DecodingError::new(format!("Argon2: {}", e)))?;
+        Ok(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_messages_only_open_unchanged() {
+        let kdf = KdfParams { m_cost: 64, t_cost: 1, ..KdfParams::generate() };
+        let key = kdf.derive_key(b"correct horse").unwrap();
+        assert_eq!(*kdf.derive_key(b"correct horse").unwrap(), *key);
+        let nonce = random_nonce();
+        let sealed = seal(&key, &nonce, b"replica-0", b"secret");
+        assert_eq!(sealed.len(), b"secret".len() + TAG_SIZE);
+        assert_eq!(open(&key, &nonce, b"replica-0", &sealed).unwrap(), b"secret");
+
+        let wrong = kdf.derive_key(b"battery staple").unwrap();
+        assert!(open(&wrong, &nonce, b"replica-0", &sealed).is_err());
+        assert!(open(&key, &nonce, b"replica-1", &sealed).is_err());
+        assert!(open(&key, &random_nonce(), b"replica-0", &sealed).is_err());
+        let mut tampered = sealed.clone();
+        tampered[0] ^= 1;
+        assert!(open(&key, &nonce, b"replica-0", &tampered).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+//! A threshold common coin from a DKG key share.
+//!
+//! For a coin named `name`, replica `i` publishes `h^{x_i}` where
+//! `h = H(name)` and `x_i` is its key share, together with a Chaum-Pedersen
+//! proof that the same exponent is behind its public share `g^{x_i}`. Any
+//! `t+1` valid coin shares interpolate to `h^x`, whose hash is the coin.
+
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::traits::Identity;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha512};
+
+use crate::hash::{Hash, HashAlgorithm};
+use crate::vss::{Commitment, KeyShare, RistrettoPoint, Scalar, lagrange_coefficient, random_scalar};
+
+/// A proof that `log_g(Y) == log_h(S)`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DleqProof {
+    pub c: Scalar,
+    pub z: Scalar,
+}
+
+/// Replica `index`'s share of a coin.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CoinShare {
+    pub index: usize,
+    pub value: RistrettoPoint,
+    pub proof: DleqProof,
+}
+
+/// The base point `h` of the coin named `name`.
+pub fn coin_base(name: &[u8]) -> RistrettoPoint {
+    RistrettoPoint::hash_from_bytes::<Sha512>(name)
+}
+
+fn challenge(points: &[&RistrettoPoint]) -> Scalar {
+    let mut hasher = Sha512::new();
+    for p in points {
+        hasher.update(p.compress().as_bytes());
+    }
+    Scalar::from_hash(hasher)
+}
+
+/// Compute our share of the coin named `name`.
+pub fn coin_share(key: &KeyShare, name: &[u8]) -> CoinShare {
+    let g = RISTRETTO_BASEPOINT_POINT;
+    let h = coin_base(name);
+    let x = key.share.value;
+    let y = x * g;
+    let s = x * h;
+    let k = random_scalar();
+    let a = k * g;
+    let b = k * h;
+    let c = challenge(&[&h, &y, &s, &a, &b]);
+    CoinShare {
+        index: key.share.index,
+        value: s,
+        proof: DleqProof { c, z: k - c * x },
+    }
+}
+
+/// Check a coin share against the public share of its sender, which is
+/// derived from the combined DKG commitment.
+pub fn verify_coin_share(commitment: &Commitment, name: &[u8], share: &CoinShare) -> bool {
+    let g = RISTRETTO_BASEPOINT_POINT;
+    let h = coin_base(name);
+    let y = commitment.public_share(share.index);
+    let DleqProof { c, z } = share.proof;
+    let a = z * g + c * y;
+    let b = z * h + c * share.value;
+    challenge(&[&h, &y, &share.value, &a, &b]) == c
+}
+
+/// Interpolate `h^x` from at least `t+1` valid coin shares with distinct
+/// indices and hash it into the coin value with `alg`.
+pub fn combine_coin(alg: HashAlgorithm, shares: &[CoinShare]) -> Hash {
+    let indices: Vec<usize> = shares.iter().map(|s| s.index).collect();
+    let point = shares.iter()
+        .fold(RistrettoPoint::identity(), |acc, s| {
+            acc + lagrange_coefficient(s.index, &indices) * s.value
+        });
+    alg.hash(point.compress().as_bytes())
+}
+
+/// Interpret a coin value as a single bit.
+pub fn coin_bit(coin: &Hash) -> bool {
+    coin[0] & 1 == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vss::{deal, Share};
+
+    fn key_shares(t: usize, n: usize) -> Vec<KeyShare> {
+        let (commitment, shares) = deal(random_scalar(), t, n);
+        shares.into_iter()
+            .map(|share| KeyShare { threshold: t, share, commitment: commitment.clone() })
+            .collect()
+    }
+
+    #[test]
+    fn coin_shares_verify_and_agree() {
+        let keys = key_shares(1, 4);
+        let name = b"ba-0-round-3";
+        let shares: Vec<CoinShare> = keys.iter()
+            .map(|k| coin_share(k, name))
+            .collect();
+        assert!(shares.iter().all(|s| verify_coin_share(&keys[0].commitment, name, s)));
+        assert_eq!(combine_coin(HashAlgorithm::default(), &shares[0..2]), combine_coin(HashAlgorithm::default(), &shares[2..4]));
+        assert_ne!(combine_coin(HashAlgorithm::default(), &shares[0..2]), combine_coin(HashAlgorithm::default(), &[
+            coin_share(&keys[0], b"other"),
+            coin_share(&keys[1], b"other"),
+        ]));
+    }
+
+    #[test]
+    fn coin_rejects_bad_share() {
+        let keys = key_shares(1, 4);
+        let name = b"coin";
+        let mut share = coin_share(&keys[1], name);
+        share.index = 2;
+        assert!(!verify_coin_share(&keys[0].commitment, name, &share));
+
+        let forged = KeyShare {
+            share: Share { index: 1, value: random_scalar() },
+            ..keys[1].clone()
+        };
+        assert!(!verify_coin_share(&keys[0].commitment, name, &coin_share(&forged, name)));
+    }
+}
@@ -4,6 +4,8 @@ use std::str::FromStr;
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum Algorithm {
     NOPKI,
+    /// Pairwise MAC keys are derived from Ed25519 identities, see `kex`
+    ED25519,
 }
 
 impl FromStr for Algorithm {
@@ -12,7 +14,7 @@ impl FromStr for Algorithm {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             // "RSA" => Ok(Algorithm::RSA),
-            // "ED25519" => Ok(Algorithm::ED25519),
+            "ED25519" => Ok(Algorithm::ED25519),
             // "SECP256K1" => Ok(Algorithm::SECP256K1),
             "NOPKI" => Ok(Algorithm::NOPKI),
             _ => Err("no match"),
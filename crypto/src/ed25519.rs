@@ -20,10 +20,17 @@
 
 //! Ed25519 keys.
 
+use curve25519_dalek::{
+    constants::ED25519_BASEPOINT_POINT,
+    edwards::{CompressedEdwardsY, EdwardsPoint},
+    scalar::Scalar,
+    traits::{IsIdentity, VartimeMultiscalarMul},
+};
 use ed25519_dalek::{Signer, Verifier, self as ed25519};
 use rand::RngCore;
+use sha2::{Digest, Sha512};
 use std::convert::TryFrom;
-use super::error::DecodingError;
+use super::error::{BatchError, DecodingError};
 use zeroize::Zeroize;
 use core::fmt;
 
@@ -135,6 +142,86 @@ impl PublicKey {
     }
 }
 
+/// Verify many Ed25519 signatures at once, each given as a (public key,
+/// message, signature) triple. A batch that verifies costs about half as
+/// much as verifying its signatures one by one. If the batch fails, every
+/// signature is verified alone to find the bad ones.
+///
+/// As with every batch verifier of Ed25519, a signature forged with a
+/// small-order component may pass in a batch and fail alone; honest
+/// signers never produce one.
+pub fn verify_batch(batch: &[(&PublicKey, &[u8], &[u8])]) -> Result<(), BatchError> {
+    if batch.len() > 1 && batch_equation(batch) {
+        return Ok(())
+    }
+    let invalid: Vec<usize> = batch.iter()
+        .enumerate()
+        .filter(|(_, (pk, msg, sig))| !pk.verify(msg, sig))
+        .map(|(i, _)| i)
+        .collect();
+    if invalid.is_empty() {
+        Ok(())
+    } else {
+        Err(BatchError { invalid })
+    }
+}
+
+/// Check the random linear combination of the verification equations of
+/// `batch`, i.e. that `sum z_i ([s_i]B - R_i - [k_i]A_i)` is the identity for
+/// random 128-bit `z_i`. False if any signature is malformed.
+fn batch_equation(batch: &[(&PublicKey, &[u8], &[u8])]) -> bool {
+    let mut rng = rand::thread_rng();
+    let mut b = Scalar::zero();
+    let mut scalars = Vec::with_capacity(2 * batch.len() + 1);
+    let mut points = Vec::with_capacity(2 * batch.len() + 1);
+    for (pk, msg, sig) in batch {
+        if sig.len() != ed25519::SIGNATURE_LENGTH {
+            return false
+        }
+        let mut r_bytes = [0u8; 32];
+        let mut s_bytes = [0u8; 32];
+        r_bytes.copy_from_slice(&sig[..32]);
+        s_bytes.copy_from_slice(&sig[32..]);
+        // The same checks as for a single signature: `s` reduced, and `R`
+        // compared in its encoding
+        let s = match scalar(s_bytes) {
+            Some(s) => s,
+            None => return false
+        };
+        let r = match CompressedEdwardsY(r_bytes).decompress() {
+            Some(r) if r.compress().as_bytes() == &r_bytes => r,
+            _ => return false
+        };
+        let a = match CompressedEdwardsY(pk.encode()).decompress() {
+            Some(a) => a,
+            None => return false
+        };
+        let k = Scalar::from_hash(Sha512::new()
+            .chain(r_bytes)
+            .chain(pk.0.as_bytes())
+            .chain(msg));
+        let mut z = [0u8; 32];
+        rng.fill_bytes(&mut z[..16]);
+        let z = Scalar::from_bits(z);
+        b += z * s;
+        scalars.push(-z);
+        points.push(r);
+        scalars.push(-(z * k));
+        points.push(a);
+    }
+    scalars.push(b);
+    points.push(ED25519_BASEPOINT_POINT);
+    EdwardsPoint::vartime_multiscalar_mul(scalars, points).is_identity()
+}
+
+/// Parse the `s` half of a signature the way `ed25519-dalek` does.
+fn scalar(bytes: [u8; 32]) -> Option<Scalar> {
+    if bytes[31] & 240 == 0 {
+        return Some(Scalar::from_bits(bytes))
+    }
+    Scalar::from_canonical_bytes(bytes)
+}
+
 /// An Ed25519 secret key.
 pub struct SecretKey(ed25519::SecretKey);
 
@@ -233,4 +320,26 @@ mod tests {
         let invalid_msg = "h3ll0 w0rld".as_bytes();
         assert!(!pk.verify(invalid_msg, &sig));
     }
+
+    #[test]
+    fn ed25519_batch_signature() {
+        let kps: Vec<Keypair> = (0..8).map(|_| Keypair::generate()).collect();
+        let pks: Vec<PublicKey> = kps.iter().map(Keypair::public).collect();
+        let msgs: Vec<Vec<u8>> = (0..8u8).map(|i| vec![i; 1 + i as usize]).collect();
+        let mut sigs: Vec<Vec<u8>> = kps.iter().zip(&msgs).map(|(kp, msg)| kp.sign(msg)).collect();
+        fn batch<'a>(pks: &'a [PublicKey], msgs: &'a [Vec<u8>], sigs: &'a [Vec<u8>]) -> Vec<(&'a PublicKey, &'a [u8], &'a [u8])> {
+            pks.iter().zip(msgs).zip(sigs)
+                .map(|((pk, msg), sig)| (pk, msg.as_slice(), sig.as_slice()))
+                .collect()
+        }
+        assert!(batch_equation(&batch(&pks, &msgs, &sigs)));
+        assert!(verify_batch(&batch(&pks, &msgs, &sigs)).is_ok());
+        assert!(verify_batch(&[]).is_ok());
+
+        sigs[2][3] ^= 1;
+        sigs[5] = kps[4].sign(&msgs[5]);
+        sigs[7].truncate(10);
+        assert!(!batch_equation(&batch(&pks, &msgs, &sigs[..7])));
+        assert_eq!(verify_batch(&batch(&pks, &msgs, &sigs)), Err(BatchError { invalid: vec![2, 5, 7] }));
+    }
 }
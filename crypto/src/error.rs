@@ -82,3 +82,38 @@ impl Error for SigningError {
     }
 }
 
+
+/// The signatures of a batch that failed to verify.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchError {
+    /// The positions of the bad signatures in the batch, in ascending order
+    pub invalid: Vec<usize>
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Batch verification error: {} bad signature(s) at {:?}", self.invalid.len(), self.invalid)
+    }
+}
+
+impl Error for BatchError {}
+
+/// An error during decryption of a ciphertext.
+#[derive(Debug)]
+pub struct DecryptionError {
+    msg: String
+}
+
+impl DecryptionError {
+    pub(crate) fn new<S: ToString>(msg: S) -> Self {
+        Self { msg: msg.to_string() }
+    }
+}
+
+impl fmt::Display for DecryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Decryption error: {}", self.msg)
+    }
+}
+
+impl Error for DecryptionError {}
@@ -1,5 +1,13 @@
-use sha2::{Digest, Sha256};
-use serde::Serialize;
+//! Hashes and MACs, under the [`Algorithms`] a deployment picks in its
+//! configs. Nothing here keeps a choice of its own: whoever hashes or MACs
+//! names the algorithm. Every algorithm has 32-byte outputs, so that a
+//! `Hash` is the same under all of them.
+
+use std::{convert::TryFrom, str::FromStr};
+
+use blake2::{VarBlake2b, digest::{Update, VariableOutput}};
+use sha2::{Digest, Sha256, Sha512Trunc256};
+use serde::{Serialize, Deserialize};
 use hmac::{Hmac,Mac,NewMac};
 
 pub const HASH_SIZE:usize = 32;
@@ -9,46 +17,226 @@ pub type Hash = [u8; HASH_SIZE];
 pub const EMPTY_HASH:Hash = [0 as u8; 32];
 
 type HmacSha256 = Hmac<Sha256>;
+type HmacSha512_256 = Hmac<Sha512Trunc256>;
+
+/// The hash function of digests, Merkle trees and the inputs of MACs.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Sha512_256,
+    /// BLAKE2b with 256-bit output
+    Blake2b,
+    Blake3,
+}
+
+/// The MAC that authenticates messages under the pairwise keys.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MacAlgorithm {
+    #[default]
+    HmacSha256,
+    HmacSha512_256,
+    /// Keyed BLAKE2b with 256-bit output; keys longer than 64 bytes are
+    /// hashed first
+    Blake2b,
+    /// Keyed BLAKE3, the fastest; keys that are not 32 bytes long are
+    /// hashed first
+    Blake3,
+}
+
+/// The hash function and the MAC of a deployment. Replicas only understand
+/// each other if they use the same ones.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Algorithms {
+    pub hash: HashAlgorithm,
+    pub mac: MacAlgorithm,
+}
+
+/// A hash or MAC with the algorithm that produced it.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Tagged<A> {
+    pub alg: A,
+    pub value: Hash,
+}
+
+/// A digest tagged with its hash function.
+pub type TaggedHash = Tagged<HashAlgorithm>;
+/// A MAC tagged with its algorithm.
+pub type TaggedMac = Tagged<MacAlgorithm>;
+
+impl HashAlgorithm {
+    const ALL: [HashAlgorithm; 4] = [Self::Sha256, Self::Sha512_256, Self::Blake2b, Self::Blake3];
+
+    /// Hash the concatenation of `parts`.
+    pub fn hash_parts(&self, parts: &[&[u8]]) -> Hash {
+        match self {
+            Self::Sha256 => {
+                let mut h = Sha256::new();
+                parts.iter().for_each(|p| Digest::update(&mut h, p));
+                h.finalize().into()
+            },
+            Self::Sha512_256 => {
+                let mut h = Sha512Trunc256::new();
+                parts.iter().for_each(|p| Digest::update(&mut h, p));
+                h.finalize().into()
+            },
+            Self::Blake2b => {
+                let mut h = VarBlake2b::new(HASH_SIZE).expect("BLAKE2b has 32-byte outputs");
+                parts.iter().for_each(|p| h.update(p));
+                let mut hash = EMPTY_HASH;
+                h.finalize_variable(|out| hash.copy_from_slice(out));
+                hash
+            },
+            Self::Blake3 => {
+                let mut h = blake3::Hasher::new();
+                parts.iter().for_each(|p| { h.update(p); });
+                h.finalize().into()
+            },
+        }
+    }
+
+    pub fn hash(&self, bytes: &[u8]) -> Hash {
+        self.hash_parts(&[bytes])
+    }
+
+    /// Hash `bytes`, tagged with this hash function, as digests are on the
+    /// wire.
+    pub fn digest(&self, bytes: &[u8]) -> TaggedHash {
+        Tagged { alg: *self, value: self.hash(bytes) }
+    }
+
+    /// The tagged digest of `obj` serialized.
+    pub fn digest_of(&self, obj: &impl Serialize) -> TaggedHash {
+        self.digest(&bincode::serialize(obj).expect("Failed to serialize the input of a digest"))
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = &'static str;
+
+    /// Parse the name the algorithm has in configs, e.g. `Blake3`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL.iter()
+            .find(|alg| format!("{:?}", alg) == s)
+            .copied()
+            .ok_or("no match")
+    }
+}
+
+impl MacAlgorithm {
+    const ALL: [MacAlgorithm; 4] = [Self::HmacSha256, Self::HmacSha512_256, Self::Blake2b, Self::Blake3];
+
+    pub fn mac(&self, bytes: &[u8], secret_key: &[u8]) -> Hash {
+        match self {
+            Self::HmacSha256 => {
+                let mut mac = HmacSha256::new_varkey(secret_key)
+                    .expect("HMAC can take secret key of any size");
+                mac.update(bytes);
+                mac.finalize().into_bytes().into()
+            },
+            Self::HmacSha512_256 => {
+                let mut mac = HmacSha512_256::new_varkey(secret_key)
+                    .expect("HMAC can take secret key of any size");
+                mac.update(bytes);
+                mac.finalize().into_bytes().into()
+            },
+            Self::Blake2b => {
+                let long_key;
+                let key = if secret_key.len() > 64 {
+                    long_key = HashAlgorithm::Blake2b.hash(secret_key);
+                    &long_key[..]
+                } else {
+                    secret_key
+                };
+                let mut h = VarBlake2b::new_keyed(key, HASH_SIZE);
+                h.update(bytes);
+                let mut mac = EMPTY_HASH;
+                h.finalize_variable(|out| mac.copy_from_slice(out));
+                mac
+            },
+            Self::Blake3 => {
+                let key = <[u8; 32]>::try_from(secret_key)
+                    .unwrap_or_else(|_| HashAlgorithm::Blake3.hash(secret_key));
+                blake3::keyed_hash(&key, bytes).into()
+            },
+        }
+    }
+
+    /// Check `mac_v` in constant time.
+    pub fn verify(&self, bytes: &[u8], secret_key: &[u8], mac_v: &[u8]) -> bool {
+        let mac = self.mac(bytes, secret_key);
+        mac.len() == mac_v.len()
+            && mac.iter().zip(mac_v).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+
+    /// MAC `bytes`, tagged with this algorithm.
+    pub fn tagged(&self, bytes: &[u8], secret_key: &[u8]) -> TaggedMac {
+        Tagged { alg: *self, value: self.mac(bytes, secret_key) }
+    }
+
+    /// Check a tagged MAC. Only MACs of this algorithm are accepted, so that
+    /// a peer cannot pick a weaker one.
+    pub fn verify_tagged(&self, bytes: &[u8], secret_key: &[u8], mac_v: &TaggedMac) -> bool {
+        mac_v.alg == *self && self.verify(bytes, secret_key, &mac_v.value)
+    }
+}
+
+impl FromStr for MacAlgorithm {
+    type Err = &'static str;
+
+    /// Parse the name the algorithm has in configs, e.g. `HmacSha256`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL.iter()
+            .find(|alg| format!("{:?}", alg) == s)
+            .copied()
+            .ok_or("no match")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-pub fn do_hash(bytes: &[u8]) -> Hash {
-    let hash = Sha256::digest(bytes);
-    return hash.into();
-} 
-
-pub fn do_hash_merkle(bytes: &[u8])-> Hash{
-    let mut sha256 = Sha256::new();
-    sha256.update(&[0x00]);
-    sha256.update(bytes);
-    sha256.clone().finalize().into()
-}
-
-pub fn ser_and_hash(obj: &impl Serialize) -> Hash {
-    let serialized_bytes = bincode::serialize(obj).unwrap();
-    return do_hash(&serialized_bytes);
-}
-
-pub fn do_mac(bytes: &[u8], secret_key:&[u8])-> Hash{
-    let mut mac = HmacSha256::new_varkey(secret_key)
-        .expect("HMAC can take secret key of any size");
-    mac.update(bytes);
-    let result = mac.finalize();
-    // is an array copy necessary?
-    result.into_bytes().into()
-}
-
-pub fn verf_mac(bytes: &[u8], secret_key:&[u8], mac_v:&[u8])-> bool{
-    let mut mac = HmacSha256::new_varkey(secret_key)
-        .expect("HMAC can take secret key of any size");
-    
-    mac.update(bytes);
-
-    let err_c = mac.verify(mac_v);
-    match err_c {
-        Ok(_)=>{
-            true
-        },
-        Err(_)=>{
-            false
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn every_algorithm_matches_its_test_vector() {
+        let expected = [
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            "53048e2681941ef99b2e29b76b4c7dabe4c2d0c634fc6d46e0e2f13107e7af23",
+            "bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319",
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+        ];
+        for (alg, expected) in HashAlgorithm::ALL.iter().zip(expected) {
+            assert_eq!(hex(&alg.hash(b"abc")), expected, "{:?}", alg);
+            assert_eq!(alg.hash_parts(&[b"a", b"", b"bc"]), alg.hash(b"abc"));
+        }
+
+        for alg in MacAlgorithm::ALL {
+            for key in [vec![7u8; 32], vec![7u8; 100]] {
+                let mac = alg.mac(b"abc", &key);
+                assert!(alg.verify(b"abc", &key, &mac), "{:?}", alg);
+                assert!(!alg.verify(b"abd", &key, &mac), "{:?}", alg);
+                assert!(!alg.verify(b"abc", &[8u8; 32], &mac), "{:?}", alg);
+                assert!(!alg.verify(b"abc", &key, &mac[1..]), "{:?}", alg);
+            }
         }
+        let mac = MacAlgorithm::Blake3.tagged(b"abc", &[7u8; 32]);
+        assert!(MacAlgorithm::Blake3.verify_tagged(b"abc", &[7u8; 32], &mac));
+        // A MAC of another algorithm does not pass, even if it is right
+        let other = MacAlgorithm::HmacSha256.tagged(b"abc", &[7u8; 32]);
+        assert!(!MacAlgorithm::Blake3.verify_tagged(b"abc", &[7u8; 32], &other));
+        assert_ne!(HashAlgorithm::Sha256.digest(b"abc"), HashAlgorithm::Blake3.digest(b"abc"));
+
+        assert_eq!("Sha512_256".parse(), Ok(HashAlgorithm::Sha512_256));
+        assert_eq!("Blake3".parse(), Ok(MacAlgorithm::Blake3));
+        assert!("SHA-1".parse::<HashAlgorithm>().is_err());
+        // RFC 4231, test case 2
+        assert_eq!(
+            hex(&MacAlgorithm::HmacSha256.mac(b"what do ya want for nothing?", b"Jefe")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        );
     }
-}
\ No newline at end of file
+}
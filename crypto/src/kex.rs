@@ -0,0 +1,131 @@
+//! Authenticated key exchange for pairwise MAC keys.
+//!
+//! Every replica holds a long-term Ed25519 identity. To agree on a MAC key
+//! with a peer, each side picks an ephemeral X25519 secret and sends the peer
+//! a [`Hello`] carrying its public half, signed with its identity key. Once a
+//! replica has its own ephemeral and the peer's verified `Hello` for the same
+//! epoch, both derive the same key from the Diffie-Hellman secret. Rotating a
+//! key is running the exchange again for the next epoch.
+
+use curve25519_dalek::{constants::X25519_BASEPOINT, montgomery::MontgomeryPoint, scalar::Scalar};
+use hmac::{Hmac, Mac, NewMac};
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use sha2::Sha256;
+use zeroize::Zeroize;
+
+use crate::{ed25519, SECRET_KEY_SIZE};
+
+const KEX_CONTEXT: &[u8] = b"libchatter pairwise mac key";
+
+/// The public half of an ephemeral key, sent from `from` to `to`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Hello {
+    pub from: usize,
+    pub to: usize,
+    pub epoch: u64,
+    pub ephemeral: [u8; 32],
+    pub signature: Vec<u8>,
+}
+
+impl Hello {
+    fn signed_bytes(from: usize, to: usize, epoch: u64, ephemeral: &[u8; 32]) -> Vec<u8> {
+        bincode::serialize(&(KEX_CONTEXT, from, to, epoch, ephemeral))
+            .expect("Failed to serialize the key exchange message")
+    }
+
+    /// Check that `from` signed this message with its identity `pk`.
+    pub fn verify(&self, pk: &ed25519::PublicKey) -> bool {
+        let bytes = Self::signed_bytes(self.from, self.to, self.epoch, &self.ephemeral);
+        pk.verify(&bytes, &self.signature)
+    }
+}
+
+/// An ephemeral X25519 secret, used for a single exchange.
+pub struct Ephemeral {
+    secret: [u8; 32],
+    public: MontgomeryPoint,
+}
+
+impl Ephemeral {
+    pub fn generate() -> Self {
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        secret[0] &= 248;
+        secret[31] &= 127;
+        secret[31] |= 64;
+        let public = X25519_BASEPOINT * Scalar::from_bits(secret);
+        Self { secret, public }
+    }
+
+    /// The `Hello` announcing this ephemeral key to `to`, signed by `identity`.
+    pub fn hello(&self, identity: &ed25519::Keypair, from: usize, to: usize, epoch: u64) -> Hello {
+        let ephemeral = self.public.to_bytes();
+        let signature = identity.sign(&Hello::signed_bytes(from, to, epoch, &ephemeral));
+        Hello { from, to, epoch, ephemeral, signature }
+    }
+
+    /// The MAC key shared with the sender of `theirs`, which must already be
+    /// verified. `None` if the peer's point is degenerate.
+    pub fn derive(self, me: usize, theirs: &Hello) -> Option<[u8; SECRET_KEY_SIZE]> {
+        let mut shared = (MontgomeryPoint(theirs.ephemeral) * Scalar::from_bits(self.secret)).to_bytes();
+        if shared == [0u8; 32] {
+            return None;
+        }
+        // Both sides order the transcript by replica id
+        let mine = self.public.to_bytes();
+        let (low, high) = if me < theirs.from {
+            ((me, mine), (theirs.from, theirs.ephemeral))
+        } else {
+            ((theirs.from, theirs.ephemeral), (me, mine))
+        };
+        let mut mac = Hmac::<Sha256>::new_varkey(&shared)
+            .expect("HMAC can take secret key of any size");
+        shared.zeroize();
+        mac.update(KEX_CONTEXT);
+        mac.update(&bincode::serialize(&(low, high, theirs.epoch))
+            .expect("Failed to serialize the key exchange transcript"));
+        Some(mac.finalize().into_bytes().into())
+    }
+}
+
+impl Drop for Ephemeral {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_sides_derive_the_same_key() {
+        let (alice, bob) = (ed25519::Keypair::generate(), ed25519::Keypair::generate());
+        let (a, b) = (Ephemeral::generate(), Ephemeral::generate());
+        let to_bob = a.hello(&alice, 0, 1, 3);
+        let to_alice = b.hello(&bob, 1, 0, 3);
+        assert!(to_bob.verify(&alice.public()));
+        assert!(to_alice.verify(&bob.public()));
+        let ka = a.derive(0, &to_alice).unwrap();
+        let kb = b.derive(1, &to_bob).unwrap();
+        assert_eq!(ka, kb);
+
+        // A fresh exchange gives a fresh key
+        let (a, b) = (Ephemeral::generate(), Ephemeral::generate());
+        let next = a.derive(0, &b.hello(&bob, 1, 0, 4)).unwrap();
+        assert_ne!(ka, next);
+    }
+
+    #[test]
+    fn tampered_hello_is_rejected() {
+        let alice = ed25519::Keypair::generate();
+        let mut hello = Ephemeral::generate().hello(&alice, 0, 1, 0);
+        hello.epoch = 1;
+        assert!(!hello.verify(&alice.public()));
+        let mut hello = Ephemeral::generate().hello(&alice, 0, 1, 0);
+        hello.to = 2;
+        assert!(!hello.verify(&alice.public()));
+        assert!(!hello.verify(&ed25519::Keypair::generate().public()));
+    }
+}
@@ -17,4 +17,10 @@ pub use gen::*;
 pub mod ed25519;
 pub mod error;
 pub mod rsa;
-pub mod secp256k1;
\ No newline at end of file
+pub mod secp256k1;
+pub mod vss;
+pub mod coin;
+pub mod merkle;
+pub mod kex;
+pub mod aead;
+pub mod vrf;
\ No newline at end of file
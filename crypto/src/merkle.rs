@@ -0,0 +1,189 @@
+//! Merkle trees and inclusion proofs.
+//!
+//! Leaves are hashed with a `0x00` prefix and inner nodes with a `0x01`
+//! prefix, so that an inner node can never be passed off as a leaf. A node
+//! without a sibling is promoted to the next level unchanged rather than
+//! paired with itself, which would let two different leaf lists share a
+//! root. Roots are tagged with the hash function of the tree, which proofs
+//! are then checked with.
+
+use serde::{Serialize, Deserialize};
+
+use crate::hash::{Hash, EMPTY_HASH, HashAlgorithm, Tagged, TaggedHash};
+
+const LEAF_PREFIX: u8 = 0x00;
+const INNER_PREFIX: u8 = 0x01;
+
+/// Hash a leaf.
+pub fn hash_leaf(alg: HashAlgorithm, leaf: &[u8]) -> Hash {
+    alg.hash_parts(&[&[LEAF_PREFIX], leaf])
+}
+
+/// Hash two children into their parent.
+pub fn hash_inner(alg: HashAlgorithm, left: &Hash, right: &Hash) -> Hash {
+    alg.hash_parts(&[&[INNER_PREFIX], left, right])
+}
+
+/// A Merkle tree that keeps every level, from the leaf hashes up to the root.
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    alg: HashAlgorithm,
+    levels: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    /// Build a tree over the given leaves with the hash function `alg`.
+    pub fn from_leaves<T: AsRef<[u8]>>(alg: HashAlgorithm, leaves: &[T]) -> MerkleTree {
+        let mut levels = vec![leaves.iter()
+            .map(|l| hash_leaf(alg, l.as_ref()))
+            .collect::<Vec<Hash>>()];
+        while levels.last().unwrap().len() > 1 {
+            let next = levels.last().unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_inner(alg, left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+        MerkleTree { alg, levels }
+    }
+
+    /// The root of the tree; `EMPTY_HASH` if there are no leaves.
+    pub fn root(&self) -> TaggedHash {
+        let value = self.levels.last()
+            .and_then(|l| l.first())
+            .cloned()
+            .unwrap_or(EMPTY_HASH);
+        Tagged { alg: self.alg, value }
+    }
+
+    pub fn num_leaves(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    /// Prove that leaf `index` is part of the tree.
+    pub fn gen_proof(&self, index: usize) -> MerkleProof {
+        assert!(index < self.num_leaves(), "leaf index out of range");
+        let mut siblings = Vec::with_capacity(self.levels.len());
+        let mut idx = index;
+        for level in &self.levels[..self.levels.len()-1] {
+            if let Some(sibling) = level.get(idx ^ 1) {
+                siblings.push(*sibling);
+            }
+            idx /= 2;
+        }
+        MerkleProof {
+            index,
+            num_leaves: self.num_leaves(),
+            siblings,
+        }
+    }
+}
+
+/// An inclusion proof of one leaf in a tree with `num_leaves` leaves.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub index: usize,
+    pub num_leaves: usize,
+    pub siblings: Vec<Hash>,
+}
+
+impl MerkleProof {
+    /// Check that `leaf` is at position `index` of the tree with root `root`,
+    /// under the hash function `root` is tagged with. Callers must also check
+    /// that `num_leaves` is the size they expect, and that they hash with
+    /// that function.
+    pub fn verify(&self, root: &TaggedHash, leaf: &[u8]) -> bool {
+        if self.index >= self.num_leaves {
+            return false;
+        }
+        let alg = root.alg;
+        let mut acc = hash_leaf(alg, leaf);
+        let mut idx = self.index;
+        let mut width = self.num_leaves;
+        let mut siblings = self.siblings.iter();
+        while width > 1 {
+            if !idx.is_multiple_of(2) || idx+1 < width {
+                let sibling = match siblings.next() {
+                    Some(s) => s,
+                    None => return false,
+                };
+                acc = if idx.is_multiple_of(2) { hash_inner(alg, &acc, sibling) } else { hash_inner(alg, sibling, &acc) };
+            }
+            idx /= 2;
+            width = width.div_ceil(2);
+        }
+        siblings.next().is_none() && acc == root.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: usize) -> Vec<Vec<u8>> {
+        (0..n).map(|i| format!("leaf-{}", i).into_bytes()).collect()
+    }
+
+    #[test]
+    fn merkle_proofs_verify() {
+        for n in 1..20 {
+            let data = leaves(n);
+            let tree = MerkleTree::from_leaves(HashAlgorithm::default(), &data);
+            let root = tree.root();
+            for (i, leaf) in data.iter().enumerate() {
+                let proof = tree.gen_proof(i);
+                assert!(proof.verify(&root, leaf));
+                assert!(!proof.verify(&root, b"not a leaf"));
+
+                let bytes = bincode::serialize(&proof).unwrap();
+                let decoded: MerkleProof = bincode::deserialize(&bytes).unwrap();
+                assert_eq!(decoded, proof);
+            }
+        }
+    }
+
+    #[test]
+    fn merkle_rejects_moved_and_truncated_proofs() {
+        let data = leaves(5);
+        let tree = MerkleTree::from_leaves(HashAlgorithm::default(), &data);
+        let root = tree.root();
+
+        let mut moved = tree.gen_proof(1);
+        moved.index = 0;
+        assert!(!moved.verify(&root, &data[1]));
+
+        let mut truncated = tree.gen_proof(2);
+        truncated.siblings.pop();
+        assert!(!truncated.verify(&root, &data[2]));
+
+        let mut resized = tree.gen_proof(4);
+        resized.num_leaves = 8;
+        assert!(!resized.verify(&root, &data[4]));
+
+        // A root passed off as one of another hash function
+        let retagged = Tagged { alg: HashAlgorithm::Blake3, ..root };
+        assert!(!tree.gen_proof(3).verify(&retagged, &data[3]));
+    }
+
+    #[test]
+    fn merkle_odd_leaves_are_not_duplicated() {
+        let data = leaves(3);
+        let mut padded = data.clone();
+        padded.push(data[2].clone());
+        assert_ne!(MerkleTree::from_leaves(HashAlgorithm::default(), &data).root(), MerkleTree::from_leaves(HashAlgorithm::default(), &padded).root());
+    }
+
+    #[test]
+    fn merkle_leaf_is_not_inner_node() {
+        let data = leaves(2);
+        let tree = MerkleTree::from_leaves(HashAlgorithm::default(), &data);
+        let mut inner = Vec::new();
+        inner.extend_from_slice(&hash_leaf(HashAlgorithm::default(), &data[0]));
+        inner.extend_from_slice(&hash_leaf(HashAlgorithm::default(), &data[1]));
+        assert_ne!(MerkleTree::from_leaves(HashAlgorithm::default(), &[inner]).root(), tree.root());
+    }
+}
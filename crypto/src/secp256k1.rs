@@ -24,7 +24,7 @@ use asn1_der::typed::{DerDecodable, Sequence};
 use rand::RngCore;
 use sha2::{Digest as ShaDigestTrait, Sha256};
 use secp256k1::{Message, Signature};
-use super::error::{DecodingError, SigningError};
+use super::error::{BatchError, DecodingError, SigningError};
 use zeroize::Zeroize;
 use core::fmt;
 
@@ -197,6 +197,23 @@ impl PublicKey {
     }
 }
 
+/// Verify many Secp256k1 signatures at once, each given as a (public key,
+/// message, signature) triple. ECDSA has no batch equation, so this is the
+/// same work as verifying them one by one; it is here so that callers can
+/// verify a batch of either kind of signature alike.
+pub fn verify_batch(batch: &[(&PublicKey, &[u8], &[u8])]) -> Result<(), BatchError> {
+    let invalid: Vec<usize> = batch.iter()
+        .enumerate()
+        .filter(|(_, (pk, msg, sig))| !pk.verify(msg, sig))
+        .map(|(i, _)| i)
+        .collect();
+    if invalid.is_empty() {
+        Ok(())
+    } else {
+        Err(BatchError { invalid })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -210,4 +227,19 @@ mod tests {
         assert_eq!(sk1.0.serialize(), sk2.0.serialize());
         assert_eq!(sk_bytes, [0; 32]);
     }
+
+    #[test]
+    fn secp256k1_batch_signature() {
+        let kps: Vec<Keypair> = (0..4).map(|_| Keypair::generate()).collect();
+        let msgs: Vec<Vec<u8>> = (0..4u8).map(|i| vec![i; 8]).collect();
+        let mut sigs: Vec<Vec<u8>> = kps.iter().zip(&msgs)
+            .map(|(kp, msg)| kp.secret().sign(msg).unwrap())
+            .collect();
+        sigs[1] = kps[0].secret().sign(&msgs[1]).unwrap();
+        let batch: Vec<(&PublicKey, &[u8], &[u8])> = kps.iter().zip(&msgs).zip(&sigs)
+            .map(|((kp, msg), sig)| (kp.public(), msg.as_slice(), sig.as_slice()))
+            .collect();
+        assert_eq!(verify_batch(&batch), Err(BatchError { invalid: vec![1] }));
+        assert!(verify_batch(&[batch[0], batch[2], batch[3]]).is_ok());
+    }
 }
@@ -0,0 +1,160 @@
+//! A verifiable random function on Ed25519 keys:
+//! ECVRF-EDWARDS25519-SHA512-TAI of [RFC 9381].
+//!
+//! The holder of a secret key maps an input to a pseudorandom output, which
+//! nobody can predict without the key, and proves with a proof that anyone
+//! can check against the public key that the output is the only one for
+//! that input.
+//!
+//! [RFC 9381]: https://www.rfc-editor.org/rfc/rfc9381
+
+use curve25519_dalek::{
+    constants::ED25519_BASEPOINT_POINT,
+    edwards::{CompressedEdwardsY, EdwardsPoint},
+    scalar::Scalar,
+};
+use sha2::{Digest, Sha512};
+use std::convert::TryInto;
+
+use crate::ed25519;
+
+/// The bytes of a proof: the point Gamma, the challenge and the response.
+pub const PROOF_SIZE: usize = 80;
+/// The bytes of an output
+pub const OUTPUT_SIZE: usize = 64;
+
+const SUITE: u8 = 0x03;
+const CHALLENGE_SIZE: usize = 16;
+
+pub type Proof = [u8; PROOF_SIZE];
+pub type Output = [u8; OUTPUT_SIZE];
+
+/// The secret scalar and nonce key of an Ed25519 secret key, as in RFC 8032.
+fn expand(sk: &ed25519::SecretKey) -> (Scalar, [u8; 32]) {
+    let h = Sha512::digest(sk.as_ref());
+    let mut scalar: [u8; 32] = h[..32].try_into().expect("SHA-512 has 64-byte outputs");
+    scalar[0] &= 248;
+    scalar[31] &= 127;
+    scalar[31] |= 64;
+    let nonce_key = h[32..].try_into().expect("SHA-512 has 64-byte outputs");
+    (Scalar::from_bits(scalar), nonce_key)
+}
+
+/// Hash `alpha` onto the curve by try-and-increment.
+fn encode_to_curve(pk: &[u8; 32], alpha: &[u8]) -> EdwardsPoint {
+    (0..=u8::MAX)
+        .find_map(|ctr| {
+            let h = Sha512::new()
+                .chain([SUITE, 0x01])
+                .chain(pk)
+                .chain(alpha)
+                .chain([ctr, 0x00])
+                .finalize();
+            let bytes: [u8; 32] = h[..32].try_into().expect("SHA-512 has 64-byte outputs");
+            CompressedEdwardsY(bytes).decompress()
+        })
+        .expect("a point is found with overwhelming probability")
+        .mul_by_cofactor()
+}
+
+fn challenge(points: [&EdwardsPoint; 5]) -> [u8; CHALLENGE_SIZE] {
+    let mut h = Sha512::new().chain([SUITE, 0x02]);
+    for point in points.iter() {
+        h.update(point.compress().as_bytes());
+    }
+    let c = h.chain([0x00]).finalize();
+    c[..CHALLENGE_SIZE].try_into().expect("SHA-512 has 64-byte outputs")
+}
+
+fn challenge_scalar(c: &[u8; CHALLENGE_SIZE]) -> Scalar {
+    let mut bytes = [0u8; 32];
+    bytes[..CHALLENGE_SIZE].copy_from_slice(c);
+    Scalar::from_bits(bytes)
+}
+
+/// Evaluate the VRF of `keypair` on `alpha`, with the proof of the output.
+pub fn prove(keypair: &ed25519::Keypair, alpha: &[u8]) -> (Output, Proof) {
+    let (x, nonce_key) = expand(&keypair.secret());
+    let pk = keypair.public().encode();
+    let h = encode_to_curve(&pk, alpha);
+    let gamma = x * h;
+    let k = Scalar::from_hash(Sha512::new()
+        .chain(nonce_key)
+        .chain(h.compress().as_bytes()));
+    let y = CompressedEdwardsY(pk).decompress().expect("the public key of a keypair is a point");
+    let c = challenge([&y, &h, &gamma, &(k * ED25519_BASEPOINT_POINT), &(k * h)]);
+    let s = k + challenge_scalar(&c) * x;
+
+    let mut proof = [0u8; PROOF_SIZE];
+    proof[..32].copy_from_slice(gamma.compress().as_bytes());
+    proof[32..48].copy_from_slice(&c);
+    proof[48..].copy_from_slice(s.as_bytes());
+    (output(&gamma), proof)
+}
+
+fn output(gamma: &EdwardsPoint) -> Output {
+    let beta = Sha512::new()
+        .chain([SUITE, 0x03])
+        .chain(gamma.mul_by_cofactor().compress().as_bytes())
+        .chain([0x00])
+        .finalize();
+    beta.into()
+}
+
+/// The output of the VRF of `pk` on `alpha` that `proof` proves. None if
+/// the proof is not valid, or the key is weak.
+pub fn verify(pk: &ed25519::PublicKey, alpha: &[u8], proof: &[u8]) -> Option<Output> {
+    if proof.len() != PROOF_SIZE {
+        return None;
+    }
+    let pk = pk.encode();
+    let y = CompressedEdwardsY(pk).decompress().filter(|y| !y.is_small_order())?;
+    let gamma = CompressedEdwardsY(proof[..32].try_into().ok()?).decompress()?;
+    let c: [u8; CHALLENGE_SIZE] = proof[32..48].try_into().ok()?;
+    let s = Scalar::from_canonical_bytes(proof[48..].try_into().ok()?)?;
+
+    let h = encode_to_curve(&pk, alpha);
+    let c_scalar = challenge_scalar(&c);
+    let u = EdwardsPoint::vartime_double_scalar_mul_basepoint(&-c_scalar, &y, &s);
+    let v = s * h - c_scalar * gamma;
+    if challenge([&y, &h, &gamma, &u, &v]) != c {
+        return None;
+    }
+    Some(output(&gamma))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    // RFC 9381, Appendix B.3, Example 16
+    #[test]
+    fn matches_the_rfc_test_vector() {
+        let mut sk = unhex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60");
+        let keypair = ed25519::Keypair::from(ed25519::SecretKey::from_bytes(&mut sk).unwrap());
+        assert_eq!(keypair.public().encode().to_vec(), unhex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"));
+        let (output, proof) = prove(&keypair, b"");
+        assert_eq!(proof.to_vec(), unhex("8657106690b5526245a92b003bb079ccd1a92130477671f6fc01ad16f26f723f26f8a57ccaed74ee1b190bed1f479d9727d2d0f9b005a6e456a35d4fb0daab1268a1b0db10836d9826a528ca76567805"));
+        assert_eq!(output.to_vec(), unhex("90cf1df3b703cce59e2a35b925d411164068269d7b2d29f3301c03dd757876ff66b71dda49d2de59d03450451af026798e8f81cd2e333de5cdf4f3e140fdd8ae"));
+        assert_eq!(verify(&keypair.public(), b"", &proof), Some(output));
+    }
+
+    #[test]
+    fn only_the_proven_output_verifies() {
+        let keypair = ed25519::Keypair::generate();
+        let (output, proof) = prove(&keypair, b"view 1");
+        assert_eq!(verify(&keypair.public(), b"view 1", &proof), Some(output));
+        assert_eq!(verify(&keypair.public(), b"view 2", &proof), None);
+        assert_eq!(verify(&ed25519::Keypair::generate().public(), b"view 1", &proof), None);
+        for i in [0, 40, 70] {
+            let mut forged = proof;
+            forged[i] ^= 1;
+            assert_eq!(verify(&keypair.public(), b"view 1", &forged), None);
+        }
+        assert_eq!(verify(&keypair.public(), b"view 1", &proof[1..]), None);
+    }
+}
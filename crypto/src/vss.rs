@@ -0,0 +1,242 @@
+//! Feldman verifiable secret sharing over the Ristretto group.
+//!
+//! A dealer samples a random polynomial `p` of degree `t` with `p(0)` as the
+//! secret, sends `p(i+1)` privately to replica `i` and publishes the
+//! commitments `g^{a_k}` to every coefficient `a_k`. Any replica can then
+//! check its share against the public commitments, and any `t+1` valid
+//! shares reconstruct the secret.
+
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::traits::Identity;
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use zeroize::Zeroize;
+
+pub use curve25519_dalek::ristretto::RistrettoPoint;
+pub use curve25519_dalek::scalar::Scalar;
+
+/// Sample a uniformly random scalar.
+///
+/// `curve25519-dalek` expects `rand_core` 0.5 while this crate uses
+/// `rand` 0.8, so we reduce 64 random bytes instead of calling
+/// `Scalar::random`.
+pub fn random_scalar() -> Scalar {
+    let mut bytes = [0u8; 64];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let s = Scalar::from_bytes_mod_order_wide(&bytes);
+    bytes.zeroize();
+    s
+}
+
+/// The evaluation point of a replica; replica `i` holds `p(i+1)` since
+/// `p(0)` is the secret.
+pub fn eval_point(index: usize) -> Scalar {
+    Scalar::from(index as u64 + 1)
+}
+
+/// A secret polynomial, known only to the dealer.
+pub struct Polynomial {
+    coeffs: Vec<Scalar>,
+}
+
+impl Polynomial {
+    /// Generate a random polynomial of degree `degree` with `secret` as the
+    /// constant term.
+    pub fn random(secret: Scalar, degree: usize) -> Polynomial {
+        let mut coeffs = Vec::with_capacity(degree + 1);
+        coeffs.push(secret);
+        for _ in 0..degree {
+            coeffs.push(random_scalar());
+        }
+        Polynomial { coeffs }
+    }
+
+    /// Evaluate the polynomial at `x` using Horner's rule.
+    pub fn evaluate(&self, x: &Scalar) -> Scalar {
+        let mut res = Scalar::zero();
+        for c in self.coeffs.iter().rev() {
+            res = res * x + c;
+        }
+        res
+    }
+
+    /// The share of replica `index`.
+    pub fn share(&self, index: usize) -> Share {
+        Share {
+            index,
+            value: self.evaluate(&eval_point(index)),
+        }
+    }
+
+    /// Commit to every coefficient of the polynomial.
+    pub fn commit(&self) -> Commitment {
+        Commitment(self.coeffs.iter()
+            .map(|c| c * RISTRETTO_BASEPOINT_POINT)
+            .collect())
+    }
+}
+
+impl Drop for Polynomial {
+    fn drop(&mut self) {
+        self.coeffs.zeroize();
+    }
+}
+
+/// The share of a secret held by replica `index`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Share {
+    pub index: usize,
+    pub value: Scalar,
+}
+
+/// Public commitments `g^{a_0}, ..., g^{a_t}` to a sharing polynomial.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Commitment(pub Vec<RistrettoPoint>);
+
+impl Commitment {
+    /// The degree of the committed polynomial.
+    pub fn degree(&self) -> usize {
+        self.0.len().saturating_sub(1)
+    }
+
+    /// The commitment `g^{p(0)}` to the secret itself.
+    pub fn public_key(&self) -> RistrettoPoint {
+        self.0.first()
+            .cloned()
+            .unwrap_or_else(RistrettoPoint::identity)
+    }
+
+    /// Compute `g^{p(index+1)}` from the coefficient commitments.
+    pub fn public_share(&self, index: usize) -> RistrettoPoint {
+        let x = eval_point(index);
+        let mut res = RistrettoPoint::identity();
+        for c in self.0.iter().rev() {
+            res = res * x + c;
+        }
+        res
+    }
+
+    /// Check that `share` lies on the committed polynomial.
+    pub fn verify_share(&self, share: &Share) -> bool {
+        share.value * RISTRETTO_BASEPOINT_POINT == self.public_share(share.index)
+    }
+
+    /// Add another commitment coefficient-wise, committing to the sum of the
+    /// two polynomials.
+    pub fn add(&mut self, other: &Commitment) {
+        if other.0.len() > self.0.len() {
+            self.0.resize(other.0.len(), RistrettoPoint::identity());
+        }
+        for (c, o) in self.0.iter_mut().zip(other.0.iter()) {
+            *c += o;
+        }
+    }
+
+    /// Serialize the commitment into compressed points, e.g. for hashing.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0.iter()
+            .flat_map(|c| c.compress().to_bytes().to_vec())
+            .collect()
+    }
+}
+
+/// A replica's share of a jointly generated threshold key, along with the
+/// commitment to the combined polynomial so that every other replica's
+/// public share can be recomputed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyShare {
+    pub threshold: usize,
+    pub share: Share,
+    pub commitment: Commitment,
+}
+
+impl KeyShare {
+    /// The threshold public key `g^x`.
+    pub fn public_key(&self) -> RistrettoPoint {
+        self.commitment.public_key()
+    }
+
+    /// Check the share against the combined commitment.
+    pub fn is_valid(&self) -> bool {
+        self.commitment.degree() == self.threshold
+            && self.commitment.verify_share(&self.share)
+    }
+}
+
+/// Deal a fresh sharing of `secret` among `num_nodes` replicas such that any
+/// `threshold+1` of them can reconstruct it.
+pub fn deal(secret: Scalar, threshold: usize, num_nodes: usize) -> (Commitment, Vec<Share>) {
+    let poly = Polynomial::random(secret, threshold);
+    let shares = (0..num_nodes)
+        .map(|i| poly.share(i))
+        .collect();
+    (poly.commit(), shares)
+}
+
+/// The Lagrange coefficient of `index` for interpolating at 0 over the
+/// evaluation points of `indices`.
+pub fn lagrange_coefficient(index: usize, indices: &[usize]) -> Scalar {
+    let xi = eval_point(index);
+    let mut num = Scalar::one();
+    let mut den = Scalar::one();
+    for j in indices {
+        if *j == index {
+            continue;
+        }
+        let xj = eval_point(*j);
+        num *= xj;
+        den *= xj - xi;
+    }
+    num * den.invert()
+}
+
+/// Reconstruct the secret from at least `threshold+1` distinct shares.
+pub fn reconstruct(shares: &[Share]) -> Scalar {
+    let indices: Vec<usize> = shares.iter().map(|s| s.index).collect();
+    shares.iter()
+        .map(|s| lagrange_coefficient(s.index, &indices) * s.value)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vss_shares_verify_and_reconstruct() {
+        let secret = random_scalar();
+        let (comm, shares) = deal(secret, 2, 7);
+        assert_eq!(comm.degree(), 2);
+        assert!(shares.iter().all(|s| comm.verify_share(s)));
+        assert_eq!(comm.public_key(), secret * RISTRETTO_BASEPOINT_POINT);
+
+        assert_eq!(reconstruct(&shares[0..3]), secret);
+        assert_eq!(reconstruct(&shares[4..7]), secret);
+        assert_ne!(reconstruct(&shares[0..2]), secret);
+    }
+
+    #[test]
+    fn vss_rejects_bad_share() {
+        let (comm, shares) = deal(random_scalar(), 1, 4);
+        let mut bad = shares[1].clone();
+        bad.value += Scalar::one();
+        assert!(!comm.verify_share(&bad));
+
+        let mut moved = shares[1].clone();
+        moved.index = 2;
+        assert!(!comm.verify_share(&moved));
+    }
+
+    #[test]
+    fn vss_commitments_add() {
+        let (s1, s2) = (random_scalar(), random_scalar());
+        let (mut c1, sh1) = deal(s1, 1, 4);
+        let (c2, sh2) = deal(s2, 1, 4);
+        c1.add(&c2);
+        let summed: Vec<Share> = sh1.iter().zip(sh2.iter())
+            .map(|(a, b)| Share { index: a.index, value: a.value + b.value })
+            .collect();
+        assert!(summed.iter().all(|s| c1.verify_share(s)));
+        assert_eq!(reconstruct(&summed[1..3]), s1 + s2);
+    }
+}
@@ -1,5 +1,2 @@
 pub mod syncer;
-pub use syncer::*;
-
-pub mod sync_handler;
-pub use sync_handler::*;
\ No newline at end of file
+pub use syncer::*;
\ No newline at end of file
@@ -7,10 +7,32 @@ use config::Node;
 use fnv::FnvHashMap;
 use node::Syncer;
 use signal_hook::{iterator::Signals, consts::{SIGINT, SIGTERM}};
-use std::{net::{SocketAddr, SocketAddrV4}};
+use std::net::SocketAddr;
+use zeroize::Zeroizing;
 
-#[tokio::main]
-async fn main() -> Result<()> {
+/// The passphrase of the keystore, from `config::PASSPHRASE_VAR` or else
+/// from the terminal.
+fn passphrase(from_env: Option<Zeroizing<String>>) -> Result<Zeroizing<String>> {
+    if let Some(passphrase) = from_env {
+        return Ok(passphrase);
+    }
+    rpassword::prompt_password("Keystore passphrase: ")
+        .map(Zeroizing::new)
+        .map_err(|e| anyhow!("Failed to read the keystore passphrase: {}", e))
+}
+
+fn main() -> Result<()> {
+    // Take the passphrase out of the environment before the runtime starts
+    // its threads, as changing the environment races with them
+    let from_env = std::env::var(config::PASSPHRASE_VAR).ok().map(Zeroizing::new);
+    std::env::remove_var(config::PASSPHRASE_VAR);
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(from_env))
+}
+
+async fn run(passphrase_from_env: Option<Zeroizing<String>>) -> Result<()> {
     log::error!("{}", std::env::current_dir().unwrap().display());
     let yaml = load_yaml!("cli.yml");
     let m = App::from_yaml(yaml).get_matches();
@@ -51,6 +73,14 @@ async fn main() -> Result<()> {
 
     simple_logger::SimpleLogger::new().with_utc_timestamps().init().unwrap();
     log::set_max_level(log::LevelFilter::Info);
+    let dir = conf_file.parent().unwrap_or_else(|| std::path::Path::new("."));
+    let passphrase = match config.keystore {
+        Some(_) => Some(passphrase(passphrase_from_env)?),
+        None => None,
+    };
+    if let Some(passphrase) = &passphrase {
+        config.unlock(dir, passphrase.as_bytes())?;
+    }
     config
         .validate()
         .expect("The decoded config is not valid");
@@ -58,7 +88,11 @@ async fn main() -> Result<()> {
     if let Some(f) = m.value_of("ip") {
         let f_str = f.to_string();
         log::info!("Logging the file f {}",f_str);
-        config.update_config(util::io::file_to_ips(f.to_string()));
+        config.update_config(util::io::file_to_ips(f.to_string()))?;
+    }
+
+    if let Some(session) = m.value_of("session") {
+        config.session = session.to_string();
     }
 
     let config = config;
@@ -69,6 +103,18 @@ async fn main() -> Result<()> {
         "pbft" => {
             exit_tx = pbft::node::Context::spawn(config, input_value.parse().unwrap(), node_normal).unwrap();
         },
+        "dkg" => {
+            // The key share goes to the keystore, with the other secrets
+            let passphrase = passphrase.ok_or_else(|| anyhow!("The dkg keeps its key share in the keystore, and the config has none"))?;
+            let store = config::SecretStore::new(&config, dir, passphrase)?;
+            exit_tx = dkg::node::Context::spawn(config, store, node_normal).unwrap();
+        },
+        "acs" => {
+            exit_tx = acs::node::Context::spawn(config, input_value.parse().unwrap(), node_normal).unwrap();
+        },
+        "avid" => {
+            exit_tx = avid::node::Context::spawn(config, input_value.parse().unwrap(), node_normal).unwrap();
+        },
         "sync" => {
             let f_str = syncer_file.to_string();
             log::info!("Syncer file {}", f_str);
@@ -81,7 +127,12 @@ async fn main() -> Result<()> {
                 idx += 1;
             }
 
-            exit_tx = Syncer::spawn(net_map, config.client_addr.clone()).unwrap();
+            let tls = if config.tls {
+                Some(transport::TlsIdentity::from_node(&config))
+            } else {
+                None
+            };
+            exit_tx = Syncer::spawn(net_map, config.client_addr.clone(), tls).unwrap();
         },
         _ =>{
             log::error!("Matching VSS not provided {}, cancelling execution",vss_type);
@@ -109,7 +160,7 @@ async fn main() -> Result<()> {
 pub fn to_socket_address(
     ip_str: &str,
     port: u16,
-) -> SocketAddr {
-    let addr = SocketAddrV4::new(ip_str.parse().unwrap(), port);
-    addr.into()
+) -> std::io::Result<SocketAddr> {
+    // Takes IPv4 and IPv6 addresses and DNS names alike
+    config::resolve_host(ip_str, port)
 }
\ No newline at end of file
@@ -1,13 +1,12 @@
-use std::{collections::{HashSet, HashMap}, net::{SocketAddr,SocketAddrV4}, time::{SystemTime, UNIX_EPOCH, Duration}};
+use std::{collections::{HashSet, HashMap}, time::{SystemTime, UNIX_EPOCH, Duration}};
 
 use anyhow::{Result, anyhow};
 use fnv::FnvHashMap;
-use network::{plaintcp::{TcpReceiver, TcpReliableSender, CancelHandler}, Acknowledgement};
-use tokio::sync::{oneshot, mpsc::{unbounded_channel, UnboundedReceiver}};
+use network::{plaintcp::CancelHandler, Acknowledgement};
+use tokio::sync::oneshot;
+use transport::{PendingAcks, Slot, TlsIdentity, Transport};
 use types::{Replica, SyncMsg, SyncState};
 
-use crate::SyncHandler;
-
 pub struct Syncer{
     pub num_nodes: usize,
     pub start_time: u128,
@@ -17,36 +16,28 @@ pub struct Syncer{
     pub alive: HashSet<Replica>,
     pub timings:HashMap<Replica,u128>,
     pub values: HashMap<Replica,String>,
-    pub cli_addr: SocketAddr,
-    pub rx_net: UnboundedReceiver<SyncMsg>,
-    pub net_send: TcpReliableSender<Replica,SyncMsg,Acknowledgement>,
+    pub cli_addr: String,
+    pub net: Box<dyn Transport<SyncMsg>>,
     exit_rx: oneshot::Receiver<()>,
-    /// Cancel Handlers
-    pub cancel_handlers: Vec<CancelHandler<Acknowledgement>>,
+    /// Cancel handlers of the messages not acknowledged yet
+    pub cancel_handlers: PendingAcks,
 }
 
 impl Syncer{
     pub fn spawn(
         net_map: FnvHashMap<Replica,String>,
-        cli_addr:SocketAddr,
+        cli_addr:String,
+        tls: Option<TlsIdentity>,
     )-> anyhow::Result<oneshot::Sender<()>>{
         let (exit_tx, exit_rx) = oneshot::channel();
-        let (tx_net_to_server, rx_net_to_server) = unbounded_channel();
-        let cli_addr_sock = cli_addr.port();
-        let new_sock_address = SocketAddrV4::new("0.0.0.0".parse().unwrap(), cli_addr_sock);
-        
-        TcpReceiver::<Acknowledgement, SyncMsg, _>::spawn(
-            std::net::SocketAddr::V4(new_sock_address),
-            SyncHandler::new(tx_net_to_server),
-        );
-
-        let mut server_addrs :FnvHashMap<Replica,SocketAddr>= FnvHashMap::default();
-        for (replica,address) in net_map.iter(){
-            let address:SocketAddr = address.parse().expect("Unable to parse address");
-            server_addrs.insert(*replica, SocketAddr::from(address.clone()));
-        }
+        // Listen on every interface of the family of our own address
+        let listen = config::wildcard(&cli_addr)?;
 
-        let net_send = TcpReliableSender::<Replica,SyncMsg,Acknowledgement>::with_peers(server_addrs);
+        let net = transport::syncer_transport(
+            listen,
+            net_map.clone(),
+            tls,
+        )?;
         
         tokio::spawn(async move{
             let mut syncer = Syncer{
@@ -59,10 +50,9 @@ impl Syncer{
                 values:HashMap::default(),
                 timings:HashMap::default(),
                 cli_addr:cli_addr,
-                rx_net:rx_net_to_server,
-                net_send:net_send,
+                net:net,
                 exit_rx:exit_rx,
-                cancel_handlers:Vec::new()
+                cancel_handlers:PendingAcks::new()
             };
             
             if let Err(e) = syncer.run().await {
@@ -74,7 +64,7 @@ impl Syncer{
 
     pub async fn broadcast(&mut self, sync_msg:SyncMsg){
         for replica in 0..self.num_nodes {
-            let cancel_handler:CancelHandler<Acknowledgement> = self.net_send.send(replica, sync_msg.clone()).await;
+            let cancel_handler:CancelHandler<Acknowledgement> = self.net.send(replica, sync_msg.clone()).await;
             self.add_cancel_handler(cancel_handler);    
         }
     }
@@ -88,7 +78,7 @@ impl Syncer{
                     log::info!("Termination signal received by the server. Exiting.");
                     break
                 },
-                msg = self.rx_net.recv() => {
+                msg = self.net.recv() => {
                     // Received a protocol message
                     // Received a protocol message
                     log::debug!("Got a message from the server: {:?}", msg);
@@ -147,11 +137,12 @@ impl Syncer{
                 },
             }
         }
+        let pending = self.cancel_handlers.cancel_all();
+        log::info!("Cancelled {} unacknowledged messages", pending);
         Ok(())
     }
 
     pub fn add_cancel_handler(&mut self, canc: CancelHandler<Acknowledgement>){
-        self.cancel_handlers
-            .push(canc);
+        self.cancel_handlers.add(Slot::CONTROL, canc);
     }
 }
\ No newline at end of file
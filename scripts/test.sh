@@ -6,10 +6,14 @@ vals=(27000 27100 27200 27300)
 rand=$(shuf -i 1000-150000000 -n 1)
 TESTDIR=${TESTDIR:="testdata/hyb_4"}
 TYPE=${TYPE:="release"}
+PROTOCOL=${PROTOCOL:="pbft"}
 
-# Run the syncer now
+# Run the syncer now, with its own identity if the configs were generated
+# with one
+SYNCER_CONFIG=$TESTDIR/nodes-syncer.json
+[ -f $SYNCER_CONFIG ] || SYNCER_CONFIG=$TESTDIR/nodes-0.json
 ./target/$TYPE/node \
-    --config $TESTDIR/nodes-0.json \
+    --config $SYNCER_CONFIG \
     --ip ip_file \
     --protocol sync \
     --input 100 \
@@ -40,7 +44,7 @@ for((i=0;i<3;i++)); do
 ./target/$TYPE/node \
     --config $TESTDIR/nodes-$i.json \
     --ip ip_file \
-    --protocol pbft \
+    --protocol $PROTOCOL \
     --input ${vals[$i]} \
     --syncer $1 \
     --byzantine false > logs/$i.log &
@@ -50,7 +54,7 @@ for((i=3;i<4;i++)); do
 ./target/$TYPE/node \
     --config $TESTDIR/nodes-$i.json \
     --ip ip_file \
-    --protocol pbft \
+    --protocol $PROTOCOL \
     --input ${vals[$i]} \
     --syncer $1 \
     --byzantine true > logs/$i.log &
@@ -0,0 +1,209 @@
+//! A deterministic in-process network for running several replicas inside
+//! one tokio runtime.
+//!
+//! Every message sent through a [`SimSender`] is handed to the scheduler of
+//! its [`SimNetwork`] instead of a socket. The scheduler waits until the
+//! replicas stop reacting to the last delivery, then picks the next pending
+//! message at random from a generator seeded with the network's seed.
+//! Pending messages are ordered by sender, receiver and their position on
+//! that link, so the order in which a replica addresses different peers (a
+//! `HashMap` walk in most protocols) does not matter. On a current-thread
+//! runtime (the default of `#[tokio::test]`), with no timers or real I/O
+//! involved, the same seed therefore replays the same interleaving of
+//! deliveries.
+
+use std::collections::{BTreeMap, HashMap};
+
+use async_trait::async_trait;
+use network::{plaintcp::CancelHandler, Acknowledgement, Message};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use tokio::{sync::{mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender}, oneshot}, task::JoinHandle};
+use transport::Transport;
+use types::Replica;
+
+/// A message in flight from one replica to another.
+#[derive(Debug)]
+pub struct Envelope<M> {
+    pub from: Replica,
+    pub to: Replica,
+    pub msg: M,
+    ack: oneshot::Sender<Acknowledgement>,
+}
+
+impl<M> Envelope<M> {
+    /// Acknowledge the message to its sender and return its contents.
+    pub fn open(self) -> M {
+        let _ = self.ack.send(Acknowledgement::Pong);
+        self.msg
+    }
+}
+
+/// The sending half given to one replica of the simulation.
+#[derive(Debug, Clone)]
+pub struct SimSender<M> {
+    id: Replica,
+    tx: UnboundedSender<Envelope<M>>,
+}
+
+impl<M> SimSender<M> {
+    /// A sender for replica `id` whose messages all end up in `tx`.
+    pub fn new(id: Replica, tx: UnboundedSender<Envelope<M>>) -> Self {
+        Self { id, tx }
+    }
+
+    pub async fn send(&mut self, to: Replica, msg: M) -> CancelHandler<Acknowledgement> {
+        let (ack, ack_rx) = oneshot::channel();
+        let envelope = Envelope { from: self.id, to, msg, ack };
+        if self.tx.send(envelope).is_err() {
+            log::warn!("Simulated network stopped, dropping message from {} to {}", self.id, to);
+        }
+        ack_rx
+    }
+}
+
+/// A replica's end of the simulated network.
+pub struct SimTransport<M> {
+    sender: SimSender<M>,
+    inbox: UnboundedReceiver<M>,
+}
+
+impl<M> SimTransport<M> {
+    pub fn new(sender: SimSender<M>, inbox: UnboundedReceiver<M>) -> Self {
+        Self { sender, inbox }
+    }
+}
+
+#[async_trait]
+impl<M: Message> Transport<M> for SimTransport<M> {
+    async fn send(&mut self, to: Replica, msg: M) -> CancelHandler<Acknowledgement> {
+        self.sender.send(to, msg).await
+    }
+
+    async fn recv(&mut self) -> Option<M> {
+        self.inbox.recv().await
+    }
+}
+
+/// A simulated mesh between registered replicas.
+pub struct SimNetwork<M> {
+    rng: ChaCha8Rng,
+    tx: UnboundedSender<Envelope<M>>,
+    rx: UnboundedReceiver<Envelope<M>>,
+    inboxes: HashMap<Replica, UnboundedSender<M>>,
+}
+
+impl<M: Send + 'static> SimNetwork<M> {
+    pub fn new(seed: u64) -> Self {
+        let (tx, rx) = unbounded_channel();
+        Self {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            tx,
+            rx,
+            inboxes: HashMap::default(),
+        }
+    }
+
+    /// Attach replica `id`.
+    pub fn register(&mut self, id: Replica) -> SimTransport<M> {
+        let (inbox_tx, inbox_rx) = unbounded_channel();
+        self.inboxes.insert(id, inbox_tx);
+        SimTransport::new(SimSender::new(id, self.tx.clone()), inbox_rx)
+    }
+
+    /// Start the scheduler. Replicas must be registered before. The task
+    /// ends once every sender is dropped and all messages are delivered,
+    /// and returns the (from, to) pairs in delivery order.
+    pub fn spawn(self) -> JoinHandle<Vec<(Replica, Replica)>> {
+        let SimNetwork { mut rng, tx, mut rx, inboxes } = self;
+        drop(tx);
+        tokio::spawn(async move {
+            // (from, to, position on the link) -> message
+            let mut pending = BTreeMap::new();
+            let mut link_seq: HashMap<(Replica, Replica), u64> = HashMap::default();
+            let mut enqueue = |pending: &mut BTreeMap<_, _>, envelope: Envelope<M>| {
+                let seq = link_seq.entry((envelope.from, envelope.to)).or_insert(0);
+                *seq += 1;
+                pending.insert((envelope.from, envelope.to, *seq), envelope);
+            };
+            let mut trace = Vec::new();
+            loop {
+                // Let the replicas finish reacting to the previous delivery
+                loop {
+                    tokio::task::yield_now().await;
+                    let before = pending.len();
+                    while let Ok(envelope) = rx.try_recv() {
+                        enqueue(&mut pending, envelope);
+                    }
+                    if pending.len() == before {
+                        break;
+                    }
+                }
+                if pending.is_empty() {
+                    match rx.recv().await {
+                        Some(envelope) => enqueue(&mut pending, envelope),
+                        None => break,
+                    }
+                    continue;
+                }
+                let key = *pending.keys().nth(rng.gen_range(0..pending.len())).unwrap();
+                let envelope: Envelope<M> = pending.remove(&key).unwrap();
+                let (from, to) = (envelope.from, envelope.to);
+                trace.push((from, to));
+                match inboxes.get(&to) {
+                    Some(inbox) => {
+                        // Like a crashed peer, a dropped inbox never acknowledges
+                        let _ = inbox.send(envelope.open());
+                    },
+                    None => log::warn!("Message from {} to unknown replica {}", from, to),
+                }
+            }
+            trace
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every replica broadcasts its id, then passes the id of the next
+    /// replica on whenever it receives its own, three times.
+    async fn run(seed: u64) -> Vec<(Replica, Replica)> {
+        let n = 4;
+        let mut net = SimNetwork::<usize>::new(seed);
+        let mut nodes = Vec::new();
+        for i in 0..n {
+            nodes.push(net.register(i));
+        }
+        let sched = net.spawn();
+        for (i, mut node) in nodes.into_iter().enumerate() {
+            tokio::spawn(async move {
+                node.broadcast(i, (0..n).collect()).await;
+                let mut hops = 3;
+                while let Some(msg) = node.recv().await {
+                    if msg == i && hops > 0 {
+                        hops -= 1;
+                        node.send((i + 1) % n, (i + 1) % n).await;
+                    }
+                    if hops == 0 {
+                        break;
+                    }
+                }
+            });
+        }
+        sched.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn same_seed_same_schedule() {
+        let trace = run(7).await;
+        assert_eq!(trace, run(7).await);
+        assert!(!trace.is_empty());
+        let mut differs = false;
+        for seed in 0..16 {
+            differs |= run(seed).await != trace;
+        }
+        assert!(differs);
+    }
+}
@@ -1,138 +1,39 @@
 // A tool that builds config files for all the nodes and the clients for the
 // protocol.
 
-use crypto::{secp256k1::{SecretKey}};
-use config::{Node, Client};
+use crypto::{ed25519, secp256k1::{SecretKey}};
+use config::{Node, Client, Secret};
 use clap::{load_yaml, App};
 use rand::Rng;
 use types::Replica;
-use crypto::Algorithm;
-use std::{error::Error, io::{BufWriter, Write}, fs::File};
+use crypto::{Algorithm, hash::{HashAlgorithm, MacAlgorithm}};
+use std::{error::Error, io::{BufWriter, Write}, fs::File, path::Path};
 use util::io::*;
 use fnv::FnvHashMap as HashMap;
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+use zeroize::Zeroizing;
+
+/// A self-signed root that signs the certificates of every node.
+fn new_root_cert() -> Result<Certificate, rcgen::RcgenError> {
+    let mut params = CertificateParams::new(vec![]);
+    params.distinguished_name.push(DnType::OrganizationName, "Libchatter Test");
+    params.distinguished_name.push(DnType::CommonName, "Root");
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    Certificate::from_params(params)
+}
 
-// fn new_root_cert() -> Result<(X509, PKey<Private>), ErrorStack> {
-//     let rsa = Rsa::generate(2048)?;
-//     let privkey = PKey::from_rsa(rsa)?;
-
-//     let mut x509_name = X509NameBuilder::new()?;
-//     x509_name.append_entry_by_text("C", "US")?;
-//     x509_name.append_entry_by_text("ST", "IN")?;
-//     x509_name.append_entry_by_text("O", "Libchatter Test")?;
-//     x509_name.append_entry_by_text("CN", "Root")?;
-//     let x509_name = x509_name.build();
-
-//     let mut cert_builder = X509::builder()?;
-//     cert_builder.set_version(2)?;
-//     let serial_number = {
-//         let mut serial = BigNum::new()?;
-//         serial.rand(159, MsbOption::MAYBE_ZERO, false)?;
-//         serial.to_asn1_integer()?
-//     };
-//     cert_builder.set_serial_number(&serial_number)?;
-//     cert_builder.set_subject_name(&x509_name)?;
-//     cert_builder.set_issuer_name(&x509_name)?;
-//     cert_builder.set_pubkey(&privkey)?;
-//     let not_before = Asn1Time::days_from_now(0)?;
-//     cert_builder.set_not_before(&not_before)?;
-//     let not_after = Asn1Time::days_from_now(365)?;
-//     cert_builder.set_not_after(&not_after)?;
-
-//     cert_builder.append_extension(BasicConstraints::new().critical().ca().build()?)?;
-//     cert_builder.append_extension(
-//         KeyUsage::new()
-//             .critical()
-//             .key_cert_sign()
-//             .crl_sign()
-//             .build()?,
-//     )?;
-
-//     let subject_key_identifier =
-//         SubjectKeyIdentifier::new().build(&cert_builder.x509v3_context(None, None))?;
-//     cert_builder.append_extension(subject_key_identifier)?;
-
-//     cert_builder.sign(&privkey, MessageDigest::sha256())?;
-//     let cert = cert_builder.build();
-
-//     Ok((cert, privkey))
-// }
-
-// /// Make a X509 request with the given private key
-// fn mk_request(privkey: &PKey<Private>) -> Result<X509Req, ErrorStack> {
-//     let mut req_builder = X509ReqBuilder::new()?;
-//     req_builder.set_pubkey(&privkey)?;
-
-//     let mut x509_name = X509NameBuilder::new()?;
-//     x509_name.append_entry_by_text("C", "US")?;
-//     x509_name.append_entry_by_text("ST", "IN")?;
-//     x509_name.append_entry_by_text("O", "Nodes")?;
-//     x509_name.append_entry_by_text("CN", "nodes.com")?;
-//     let x509_name = x509_name.build();
-//     req_builder.set_subject_name(&x509_name)?;
-
-//     req_builder.sign(&privkey, MessageDigest::sha256())?;
-//     let req = req_builder.build();
-//     Ok(req)
-// }
-
-// /// Make a certificate and private key signed by the given CA cert and private key
-// fn get_signed_cert(
-//     ca_cert: &X509Ref,
-//     ca_privkey: &PKeyRef<Private>,
-// ) -> Result<(X509, PKey<Private>), ErrorStack> {
-//     let rsa = Rsa::generate(2048)?;
-//     let privkey = PKey::from_rsa(rsa)?;
-
-//     let req = mk_request(&privkey)?;
-
-//     let mut cert_builder = X509::builder()?;
-//     cert_builder.set_version(2)?;
-//     let serial_number = {
-//         let mut serial = BigNum::new()?;
-//         serial.rand(159, MsbOption::MAYBE_ZERO, false)?;
-//         serial.to_asn1_integer()?
-//     };
-//     cert_builder.set_serial_number(&serial_number)?;
-//     cert_builder.set_subject_name(req.subject_name())?;
-//     cert_builder.set_issuer_name(ca_cert.subject_name())?;
-//     cert_builder.set_pubkey(&privkey)?;
-//     let not_before = Asn1Time::days_from_now(0)?;
-//     cert_builder.set_not_before(&not_before)?;
-//     let not_after = Asn1Time::days_from_now(365)?;
-//     cert_builder.set_not_after(&not_after)?;
-
-//     cert_builder.append_extension(BasicConstraints::new().build()?)?;
-
-//     cert_builder.append_extension(
-//         KeyUsage::new()
-//             .critical()
-//             .non_repudiation()
-//             .digital_signature()
-//             .key_encipherment()
-//             .build()?,
-//     )?;
-
-//     let subject_key_identifier =
-//         SubjectKeyIdentifier::new().build(&cert_builder.x509v3_context(Some(ca_cert), None))?;
-//     cert_builder.append_extension(subject_key_identifier)?;
-
-//     let auth_key_identifier = AuthorityKeyIdentifier::new()
-//         .keyid(false)
-//         .issuer(false)
-//         .build(&cert_builder.x509v3_context(Some(ca_cert), None))?;
-//     cert_builder.append_extension(auth_key_identifier)?;
-
-//     let subject_alt_name = SubjectAlternativeName::new()
-//     //     .dns("*.example.com")
-//         .dns("nodes.com")
-//         .build(&cert_builder.x509v3_context(Some(ca_cert), None))?;
-//     cert_builder.append_extension(subject_alt_name)?;
-
-//     cert_builder.sign(&ca_privkey, MessageDigest::sha256())?;
-//     let cert = cert_builder.build();
-
-//     Ok((cert, privkey))
-// }
+/// A certificate issued for `name` and signed by `root`, and its PKCS#8
+/// private key, both DER encoded.
+fn get_signed_cert(
+    root: &Certificate,
+    name: &str,
+) -> Result<(Vec<u8>, Vec<u8>), rcgen::RcgenError> {
+    let mut params = CertificateParams::new(vec![name.to_string()]);
+    params.distinguished_name.push(DnType::OrganizationName, "Nodes");
+    params.distinguished_name.push(DnType::CommonName, name);
+    let cert = Certificate::from_params(params)?;
+    Ok((cert.serialize_der_with_signer(root)?, cert.serialize_private_key_der()))
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
     let yaml = load_yaml!("cli.yml");
@@ -158,14 +59,10 @@ fn main() -> Result<(), Box<dyn Error>> {
         .expect("no block_size specified")
         .parse::<usize>()
         .expect("unable to convert blocksize into a number");
-    let client_base_port:u16 = m.value_of("client_base_port")
-        .expect("no client_base_port specified")
-        .parse::<u16>()
-        .expect("unable to parse client_base_port into an integer");
     let t:Algorithm = m.value_of("algorithm")
-        .unwrap_or("NOPKI")
+        .unwrap_or("ED25519")
         .parse::<Algorithm>()
-        .unwrap_or(Algorithm::NOPKI);
+        .unwrap_or(Algorithm::ED25519);
     let out = m.value_of("out_type")
         .unwrap_or("json");
     let target = m.value_of("target")
@@ -182,6 +79,30 @@ fn main() -> Result<(), Box<dyn Error>> {
         .expect("Client port expected")
         .parse::<u16>()
         .expect("unable to parse client's port into an integer");
+    let tls = m.is_present("tls");
+    let confidential = m.is_present("confidential");
+    let key_rotation:u64 = m.value_of("key_rotation")
+        .unwrap_or("0")
+        .parse()
+        .expect("unable to parse the key rotation period into a number");
+    let hash:HashAlgorithm = m.value_of("hash")
+        .unwrap_or("Sha256")
+        .parse()
+        .expect("unknown hash function");
+    let mac:MacAlgorithm = m.value_of("mac")
+        .unwrap_or("HmacSha256")
+        .parse()
+        .expect("unknown MAC");
+    // Keystores are sealed under the passphrase in the environment
+    let passphrase = if m.is_present("keystore") {
+        let passphrase = std::env::var(config::PASSPHRASE_VAR)
+            .expect("--keystore needs a passphrase in KEYSTORE_PASSPHRASE");
+        Some(Zeroizing::new(passphrase))
+    } else {
+        None
+    };
+    // A fresh session for the run these configs are for
+    let session = format!("{:016x}", rand::thread_rng().gen::<u64>());
     let mut client = Client::new();
     client.block_size = blocksize;
     client.crypto_alg = t.clone();
@@ -190,10 +111,11 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let mut node:Vec<Node> = Vec::with_capacity(num_nodes);
 
-    let pk = HashMap::default();
+    let mut pk = HashMap::default();
     let mut ip = HashMap::default();
     
-    //let (cert, privkey) = new_root_cert()?;
+    let root = new_root_cert()?;
+    let root_der = root.serialize_der()?;
     let mut sec_keys:Vec<Vec<SecretKey>> = Vec::with_capacity(num_nodes);
     (0..num_nodes).for_each(|_i| {
         sec_keys.push(Vec::with_capacity(num_nodes));
@@ -211,6 +133,15 @@ fn main() -> Result<(), Box<dyn Error>> {
             }
         }
     }
+    // With ED25519, nodes only get their own identity and derive the
+    // pairwise keys at runtime
+    let identities:Vec<ed25519::Keypair> = match t {
+        Algorithm::ED25519 => (0..num_nodes).map(|_| ed25519::Keypair::generate()).collect(),
+        Algorithm::NOPKI => Vec::new(),
+    };
+    for (i, identity) in identities.iter().enumerate() {
+        pk.insert(i as Replica, identity.public().encode().to_vec());
+    }
     for i in 0..num_nodes {
         node.push(Node::new());
 
@@ -220,7 +151,6 @@ fn main() -> Result<(), Box<dyn Error>> {
         node[i].num_faults = num_faults;
         node[i].block_size = blocksize;
         node[i].payload = payload;
-        node[i].client_port = client_base_port+(i as u16);
         // generate random number for approximate consensus
         let num = rand::thread_rng().gen_range(0, 20000000);
         node[i].prot_payload = format!("a,{},50000,100",num);
@@ -230,26 +160,35 @@ fn main() -> Result<(), Box<dyn Error>> {
         match t {
             Algorithm::NOPKI =>{
                 for j in 0..num_nodes{
-                    node[i].sk_map.insert(j, sec_keys[i][j].to_bytes().to_vec());
+                    node[i].sk_map.insert(j, sec_keys[i][j].to_bytes().to_vec().into());
                 }
             }
+            Algorithm::ED25519 =>{
+                node[i].secret_key_bytes = identities[i].encode().to_vec().into();
+                node[i].key_rotation = key_rotation;
+            }
         };
         ip.insert(i as Replica, 
         format!("{}:{}", "127.0.0.1", base_port+(i as u16))
         );
+        // The syncer reaches every node on its consensus port
         client.net_map.insert(i as Replica, 
-        format!("127.0.0.1:{}", client_base_port+(i as u16))
+        format!("127.0.0.1:{}", base_port+(i as u16))
         );
 
 
-        //let (new_cert, new_pkey) = get_signed_cert(&cert, &privkey)?;
-
-        //node[i].root_cert = cert.to_der()?;
-        //node[i].my_cert = new_cert.to_der()?;
-        //node[i].my_cert_key = new_pkey.private_key_to_der()?;
+        let (cert, key) = get_signed_cert(&root, &format!("replica-{}", i))?;
+        node[i].root_cert = root_der.clone();
+        node[i].my_cert = cert;
+        node[i].my_cert_key = key.into();
+        node[i].tls = tls;
+        node[i].hash = hash;
+        node[i].mac = mac;
+        node[i].confidential = confidential;
+        node[i].session = session.clone();
     }
     ip.insert(num_nodes, format!("127.0.0.1:{}",c_rport));
-    //client.root_cert = cert.to_der()?;
+    client.root_cert = root_der.clone();
 
     for i in 0..num_nodes {
         node[i].pk_map = pk.clone();
@@ -282,29 +221,51 @@ fn main() -> Result<(), Box<dyn Error>> {
     write_json(filename, &client.net_map.clone());
     client.server_pk = pk;
 
+    // The syncer gets its own identity so that replicas can tell it apart
+    // from each other
+    let mut syncer = node[0].clone();
+    let (cert, key) = get_signed_cert(&root, "syncer")?;
+    syncer.id = num_nodes as Replica;
+    syncer.my_cert = cert;
+    syncer.my_cert_key = key.into();
+    // The syncer does not MAC its messages, so it gets no replica secrets
+    syncer.crypto_alg = Algorithm::NOPKI;
+    syncer.secret_key_bytes = Secret::default();
+    syncer.sk_map = HashMap::default();
+    node.push(syncer);
+
     // Write all the files
-    for i in 0..num_nodes {
+    for i in 0..num_nodes+1 {
+        let name = if i == num_nodes {
+            String::from("syncer")
+        } else {
+            format!("{}", i)
+        };
+        node[i].validate()
+            .expect("failed to validate node config");
+        if let Some(passphrase) = &passphrase {
+            let keystore = format!("keystore-{}.json", name);
+            node[i].lock(&Path::new(target).join(&keystore), &keystore, passphrase.as_bytes())?;
+        }
         match out {
             "json" => {
-                let filename = format!("{}/nodes-{}.json",target,i);
+                let filename = format!("{}/nodes-{}.json",target,name);
                 write_json(filename, &node[i]);
             },
             "binary" => {
-                let filename = format!("{}/nodes-{}.dat",target,i);
+                let filename = format!("{}/nodes-{}.dat",target,name);
                 write_bin(filename, &node[i]);
             },
             "toml" => {
-                let filename = format!("{}/nodes-{}.toml",target,i);
+                let filename = format!("{}/nodes-{}.toml",target,name);
                 write_toml(filename, &node[i]);
             },
             "yaml" => {
-                let filename = format!("{}/nodes-{}.yml",target,i);
+                let filename = format!("{}/nodes-{}.yml",target,name);
                 write_yaml(filename, &node[i]);
             },
             _ => (),
         }
-        node[i].validate()
-            .expect("failed to validate node config");
     }
 
     // Write the client file
@@ -335,12 +296,12 @@ fn main() -> Result<(), Box<dyn Error>> {
 
 #[test]
 fn test_codec() -> Result<(), Box<dyn Error>>{
-    // use rustls::{Certificate};
+    use rustls::{Certificate, RootCertStore};
 
-    // let (cert, _key) = new_root_cert()?;
-    // let data = cert.to_der()?;
-    // //let ok = Certificate(data);
-    // //let mut config = ClientConfig::new();
-    // //config.root_store.add(&ok)?;
+    let root = new_root_cert()?;
+    let mut store = RootCertStore::empty();
+    store.add(&Certificate(root.serialize_der()?))?;
+    let (cert, _key) = get_signed_cert(&root, "replica-0")?;
+    assert!(!cert.is_empty());
     Ok(())
 }
\ No newline at end of file
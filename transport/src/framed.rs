@@ -0,0 +1,188 @@
+//! Length delimited bincode frames over any byte stream. Both ends open a
+//! connection by sending the wire versions they speak, and drop it unless
+//! they share one. Every frame after that is answered by the receiver with
+//! an empty acknowledgement frame.
+
+use std::{future::Future, io, time::Duration};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use network::{Acknowledgement, Message};
+use tokio::{io::{AsyncRead, AsyncWrite}, sync::{mpsc::{Sender, UnboundedReceiver, UnboundedSender}, oneshot}};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use types::{decode, reject_frame, DecodeError, Incompatible, Versions};
+
+/// How long to wait before connecting again to a peer that is not up yet.
+const RETRY_DELAY: Duration = Duration::from_millis(50);
+/// How long to wait before connecting again to a peer that speaks none of
+/// our wire versions, e.g. while a rolling upgrade is under way.
+const INCOMPATIBLE_DELAY: Duration = Duration::from_secs(5);
+
+pub(crate) type Outgoing = (Bytes, oneshot::Sender<Acknowledgement>);
+
+fn codec(max_frame_size: usize) -> LengthDelimitedCodec {
+    LengthDelimitedCodec::builder()
+        .max_frame_length(max_frame_size)
+        .new_codec()
+}
+
+enum HandshakeError {
+    Io(io::Error),
+    Malformed(DecodeError),
+    Incompatible(Incompatible),
+}
+
+impl std::fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            HandshakeError::Io(e) => write!(f, "{}", e),
+            HandshakeError::Malformed(e) => write!(f, "{}", e),
+            HandshakeError::Incompatible(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Exchange the wire versions both ends speak, returning the highest one
+/// they share.
+async fn handshake<IO>(framed: &mut Framed<IO, LengthDelimitedCodec>) -> Result<u16, HandshakeError>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    let ours = Versions::SUPPORTED;
+    let hello = bincode::serialize(&ours).expect("Failed to serialize versions");
+    framed.send(hello.into()).await.map_err(HandshakeError::Io)?;
+    let frame = match framed.next().await {
+        Some(Ok(frame)) => frame,
+        Some(Err(e)) => return Err(HandshakeError::Io(e)),
+        None => return Err(HandshakeError::Io(io::ErrorKind::UnexpectedEof.into())),
+    };
+    let theirs: Versions = decode(&frame, std::mem::size_of::<Versions>()).map_err(HandshakeError::Malformed)?;
+    ours.negotiate(&theirs).map_err(HandshakeError::Incompatible)
+}
+
+/// Where the messages of one incoming connection go.
+#[async_trait]
+pub(crate) trait Inbox<M>: Send {
+    /// Hand over `msg`, waiting for room. False once nobody is listening.
+    async fn put(&mut self, msg: M) -> bool;
+}
+
+#[async_trait]
+impl<M: Message> Inbox<M> for Sender<M> {
+    async fn put(&mut self, msg: M) -> bool {
+        self.send(msg).await.is_ok()
+    }
+}
+
+/// Forward the messages read from one incoming connection to `tx`. The
+/// connection is closed if the peer speaks none of our wire versions, and
+/// on the first frame that is larger than `max_frame_size` or does not
+/// decode. A frame is only acknowledged once `tx` has room for it.
+pub(crate) async fn serve<M, IO, I>(io: IO, mut tx: I, max_frame_size: usize)
+where
+    M: Message,
+    IO: AsyncRead + AsyncWrite + Unpin,
+    I: Inbox<M>,
+{
+    let mut framed = Framed::new(io, codec(max_frame_size));
+    match handshake(&mut framed).await {
+        Ok(version) => log::debug!("Accepted a connection at wire version {}", version),
+        Err(HandshakeError::Io(_)) => return,
+        Err(HandshakeError::Malformed(e)) => {
+            reject_frame(&e);
+            return;
+        },
+        Err(HandshakeError::Incompatible(e)) => {
+            log::error!("Refused a connection: {}", e);
+            return;
+        },
+    }
+    loop {
+        let frame = match framed.next().await {
+            Some(Ok(frame)) => frame,
+            Some(Err(e)) if e.kind() == io::ErrorKind::InvalidData => {
+                // The only invalid data the codec reports is an oversized length
+                reject_frame(&DecodeError::TooLarge(max_frame_size));
+                return;
+            },
+            _ => return,
+        };
+        let msg: M = match decode(&frame, max_frame_size) {
+            Ok(msg) => msg,
+            Err(e) => {
+                reject_frame(&e);
+                return;
+            }
+        };
+        if !tx.put(msg).await {
+            return;
+        }
+        if framed.send(Bytes::new()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Deliver the messages for one peer in order, reconnecting and resending
+/// the current message until the peer acknowledges it.
+pub(crate) async fn deliver<IO, F, Fut>(mut connect: F, mut rx: UnboundedReceiver<Outgoing>)
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<IO>>,
+{
+    let mut conn: Option<Framed<IO, LengthDelimitedCodec>> = None;
+    while let Some((bytes, ack)) = rx.recv().await {
+        loop {
+            let framed = match conn.as_mut() {
+                Some(framed) => framed,
+                None => {
+                    let mut framed = match connect().await {
+                        Ok(io) => Framed::new(io, LengthDelimitedCodec::new()),
+                        Err(e) => {
+                            log::debug!("Failed to connect, retrying: {}", e);
+                            tokio::time::sleep(RETRY_DELAY).await;
+                            continue;
+                        }
+                    };
+                    match handshake(&mut framed).await {
+                        Ok(version) => log::debug!("Connected at wire version {}", version),
+                        Err(HandshakeError::Incompatible(e)) => {
+                            log::error!("Cannot talk to a peer, retrying in {:?}: {}", INCOMPATIBLE_DELAY, e);
+                            tokio::time::sleep(INCOMPATIBLE_DELAY).await;
+                            continue;
+                        },
+                        Err(e) => {
+                            log::debug!("Handshake failed, retrying: {}", e);
+                            tokio::time::sleep(RETRY_DELAY).await;
+                            continue;
+                        },
+                    }
+                    conn.insert(framed)
+                },
+            };
+            let sent = framed.send(bytes.clone()).await.is_ok()
+                && matches!(framed.next().await, Some(Ok(_)));
+            if sent {
+                let _ = ack.send(Acknowledgement::Pong);
+                break;
+            }
+            log::debug!("Lost the connection, reconnecting");
+            conn = None;
+        }
+    }
+}
+
+/// Queue `msg` on the delivery task of a peer.
+pub(crate) fn enqueue<M: Message>(peer: Option<&UnboundedSender<Outgoing>>, msg: &M) -> oneshot::Receiver<Acknowledgement> {
+    let (ack, ack_rx) = oneshot::channel();
+    match peer {
+        Some(peer) => {
+            let bytes = bincode::serialize(msg).expect("Failed to serialize message");
+            let _ = peer.send((bytes.into(), ack));
+        },
+        None => log::warn!("Sending to an unknown peer"),
+    }
+    ack_rx
+}
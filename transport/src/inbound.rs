@@ -0,0 +1,247 @@
+use std::{net::IpAddr, sync::{Arc, Mutex}, task::{Context, Poll}};
+
+use fnv::FnvHashMap;
+use futures::future::poll_fn;
+use tokio::sync::mpsc::{channel, unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+use types::Replica;
+
+/// How many messages of a peer a transport holds before it stops
+/// acknowledging them. Small, so that a replica that floods us is held back
+/// by its own unacknowledged messages rather than our memory.
+pub const PEER_QUEUE_SIZE: usize = 256;
+
+/// How many connections that do not authenticate the replica at the other
+/// end a listener serves at once, as each of them gets a queue of its own.
+/// Enough for every replica of a deployment to connect on every channel.
+pub const MAX_UNAUTHENTICATED: usize = 256;
+
+/// How many of those connections may come from one remote host, which
+/// leaves room for a few replicas sharing a machine.
+pub const MAX_UNAUTHENTICATED_PER_HOST: usize = 16;
+
+/// Counts the open connections of a listener, by remote host. Only the
+/// overall limit holds for connections from this machine, which is where
+/// all replicas of a local deployment are, and for hosts that are `None`
+/// because the listener cannot tell them apart, as on Unix sockets.
+#[derive(Debug, Clone)]
+pub struct ConnectionLimit {
+    max: usize,
+    max_per_host: usize,
+    open: Arc<Mutex<FnvHashMap<Option<IpAddr>, usize>>>,
+}
+
+impl ConnectionLimit {
+    pub fn new(max: usize, max_per_host: usize) -> Self {
+        Self { max, max_per_host, open: Arc::default() }
+    }
+
+    /// Count a new connection from `host`, until the returned guard is
+    /// dropped, or `None` if that would exceed a limit.
+    pub fn admit(&self, host: Option<IpAddr>) -> Option<ConnectionGuard> {
+        let mut open = self.open.lock().expect("connection counts lock poisoned");
+        let total: usize = open.values().sum();
+        let from_host = open.get(&host).copied().unwrap_or(0);
+        let remote = host.is_some_and(|host| !host.is_loopback());
+        if total >= self.max || (remote && from_host >= self.max_per_host) {
+            return None;
+        }
+        open.insert(host, from_host + 1);
+        Some(ConnectionGuard { open: self.open.clone(), host })
+    }
+}
+
+impl Default for ConnectionLimit {
+    fn default() -> Self {
+        Self::new(MAX_UNAUTHENTICATED, MAX_UNAUTHENTICATED_PER_HOST)
+    }
+}
+
+/// An open connection counted by a [`ConnectionLimit`].
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    open: Arc<Mutex<FnvHashMap<Option<IpAddr>, usize>>>,
+    host: Option<IpAddr>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut open = self.open.lock().expect("connection counts lock poisoned");
+        if let Some(count) = open.get_mut(&self.host) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&self.host);
+            }
+        }
+    }
+}
+
+/// Create the inbound queues of a transport. Every authenticated replica
+/// gets its own queue of `capacity` messages from [`FairQueueSender::peer`],
+/// whatever connections they come on, and every connection that is not
+/// authenticated one from [`FairQueueSender::queue`].
+pub fn fair_queue<M>(capacity: usize) -> (FairQueueSender<M>, FairQueue<M>) {
+    let (register, new_peers) = unbounded_channel();
+    let sender = FairQueueSender { register, capacity, peers: Arc::default() };
+    let queue = FairQueue { new_peers: Some(new_peers), peers: Vec::new(), next: 0 };
+    (sender, queue)
+}
+
+/// Hands out a bounded queue for every peer.
+#[derive(Debug)]
+pub struct FairQueueSender<M> {
+    register: UnboundedSender<Receiver<M>>,
+    capacity: usize,
+    peers: Arc<Mutex<FnvHashMap<Replica, Sender<M>>>>,
+}
+
+impl<M> Clone for FairQueueSender<M> {
+    fn clone(&self) -> Self {
+        Self { register: self.register.clone(), capacity: self.capacity, peers: self.peers.clone() }
+    }
+}
+
+impl<M> FairQueueSender<M> {
+    /// A new queue for one connection. Sending waits while the queue is full,
+    /// which should hold back the acknowledgement of the message.
+    pub fn queue(&self) -> Sender<M> {
+        let (tx, rx) = channel(self.capacity);
+        // The receiving end is gone, so the queue will just look closed
+        let _ = self.register.send(rx);
+        tx
+    }
+
+    /// The queue of the authenticated replica `peer`, shared by all of its
+    /// connections.
+    pub fn peer(&self, peer: Replica) -> Sender<M> {
+        let mut peers = self.peers.lock().expect("peer queues lock poisoned");
+        peers.entry(peer).or_insert_with(|| self.queue()).clone()
+    }
+}
+
+/// The receiving end of the per-peer queues, drained round robin so that a
+/// flooding peer cannot starve the others.
+#[derive(Debug)]
+pub struct FairQueue<M> {
+    new_peers: Option<UnboundedReceiver<Receiver<M>>>,
+    peers: Vec<Receiver<M>>,
+    next: usize,
+}
+
+impl<M> FairQueue<M> {
+    /// The next message, taking turns between the connections that have one,
+    /// or `None` once every queue is closed. Dropping the future does not
+    /// lose messages.
+    pub async fn recv(&mut self) -> Option<M> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<M>> {
+        if let Some(new_peers) = self.new_peers.as_mut() {
+            loop {
+                match new_peers.poll_recv(cx) {
+                    Poll::Ready(Some(rx)) => self.peers.push(rx),
+                    Poll::Ready(None) => {
+                        self.new_peers = None;
+                        break;
+                    },
+                    Poll::Pending => break,
+                }
+            }
+        }
+        let n = self.peers.len();
+        let mut closed = Vec::new();
+        let mut found = None;
+        for i in 0..n {
+            let idx = (self.next + i) % n;
+            match self.peers[idx].poll_recv(cx) {
+                Poll::Ready(Some(msg)) => {
+                    self.next = idx + 1;
+                    found = Some(msg);
+                    break;
+                },
+                Poll::Ready(None) => closed.push(idx),
+                Poll::Pending => {},
+            }
+        }
+        for idx in closed.into_iter().rev() {
+            self.peers.swap_remove(idx);
+        }
+        match found {
+            Some(msg) => Poll::Ready(Some(msg)),
+            None if self.peers.is_empty() && self.new_peers.is_none() => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn peers_take_turns() {
+        let (sender, mut queue) = fair_queue::<(u8, u32)>(100);
+        let (busy, quiet) = (sender.queue(), sender.queue());
+        for i in 0..50 {
+            busy.send((0, i)).await.unwrap();
+        }
+        quiet.send((1, 0)).await.unwrap();
+        quiet.send((1, 1)).await.unwrap();
+        let first: Vec<u8> = [queue.recv().await, queue.recv().await, queue.recv().await, queue.recv().await]
+            .iter()
+            .map(|m| m.unwrap().0)
+            .collect();
+        assert_eq!(first, vec![0, 1, 0, 1]);
+        // Order is kept within a connection
+        assert_eq!(queue.recv().await, Some((0, 2)));
+    }
+
+    #[tokio::test]
+    async fn connections_of_a_peer_share_its_queue() {
+        let (sender, mut queue) = fair_queue::<u32>(2);
+        let (first, second) = (sender.peer(1), sender.peer(1));
+        first.send(0).await.unwrap();
+        second.send(1).await.unwrap();
+        assert!(first.try_send(2).is_err());
+        // Another peer still has room
+        sender.peer(2).try_send(3).unwrap();
+        assert_eq!(queue.recv().await, Some(0));
+        assert_eq!(queue.recv().await, Some(3));
+    }
+
+    #[test]
+    fn connections_are_limited_overall_and_by_host() {
+        let limit = ConnectionLimit::new(3, 2);
+        let (a, b) = (Some(IpAddr::from([10, 0, 0, 1])), Some(IpAddr::from([10, 0, 0, 2])));
+        let local = Some(IpAddr::from([127, 0, 0, 1]));
+        let first = limit.admit(a).unwrap();
+        let _second = limit.admit(a).unwrap();
+        assert!(limit.admit(a).is_none());
+        let _third = limit.admit(b).unwrap();
+        assert!(limit.admit(b).is_none());
+        assert!(limit.admit(None).is_none());
+        // A closed connection makes room for another
+        drop(first);
+        assert!(limit.admit(b).is_some());
+        assert!(limit.admit(a).is_some());
+
+        let local_only = ConnectionLimit::new(3, 1);
+        let _open: Vec<_> = (0..3).map(|_| local_only.admit(local).unwrap()).collect();
+        assert!(local_only.admit(local).is_none());
+    }
+
+    #[tokio::test]
+    async fn full_queue_holds_the_sender_back() {
+        let (sender, mut queue) = fair_queue::<u32>(2);
+        let tx = sender.queue();
+        tx.send(0).await.unwrap();
+        tx.send(1).await.unwrap();
+        assert!(tx.try_send(2).is_err());
+        assert_eq!(queue.recv().await, Some(0));
+        tx.try_send(2).unwrap();
+        drop((tx, sender));
+        assert_eq!(queue.recv().await, Some(1));
+        assert_eq!(queue.recv().await, Some(2));
+        assert_eq!(queue.recv().await, None);
+    }
+}
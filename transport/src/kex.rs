@@ -0,0 +1,377 @@
+use std::{collections::{HashMap, VecDeque}, sync::{Arc, RwLock}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use async_trait::async_trait;
+use crypto::{ed25519, kex::{Ephemeral, Hello}};
+use network::{plaintcp::CancelHandler, Acknowledgement, Message};
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use tokio::time::{interval_at, Instant, Interval};
+use types::{Authored, Replica};
+
+use crate::Transport;
+
+/// How far the epoch of a key exchange message may be from our clock.
+/// Epochs are the time of the exchange, so a message recorded in an earlier
+/// run cannot be replayed to a restarted replica once this has passed.
+/// Replicas' clocks must agree to within this.
+pub const HELLO_WINDOW: Duration = Duration::from_secs(60);
+
+/// Milliseconds since the Unix epoch.
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("the clock is before the epoch")
+        .as_millis() as u64
+}
+
+/// What a [`KeyedTransport`] puts on the wire: key exchange messages
+/// alongside the protocol's own.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum Keyed<M> {
+    Hello(Hello),
+    Msg(M),
+}
+
+impl<M: Authored> Authored for Keyed<M> {
+    fn sender(&self) -> Replica {
+        match self {
+            Keyed::Hello(hello) => hello.from,
+            Keyed::Msg(msg) => msg.sender(),
+        }
+    }
+}
+
+/// The pairwise MAC keys of a replica. A message MACed with the key a peer
+/// just rotated out is still accepted with `previous`, so that messages in
+/// flight during a rotation are not lost.
+#[derive(Debug, Clone, Default)]
+pub struct KeyRing {
+    pub current: HashMap<Replica, Vec<u8>>,
+    pub previous: HashMap<Replica, Vec<u8>>,
+}
+
+/// A replica's view of the keys a [`KeyedTransport`] derived.
+#[derive(Debug, Clone)]
+pub struct KeyWatch {
+    ring: Arc<RwLock<(u64, KeyRing)>>,
+    seen: u64,
+}
+
+impl KeyWatch {
+    /// The key ring, if it changed since the last call. Keys are always
+    /// updated before the transport hands out any message MACed with them.
+    pub fn fresh(&mut self) -> Option<KeyRing> {
+        let ring = self.ring.read().expect("key ring lock poisoned");
+        if ring.0 == self.seen {
+            return None;
+        }
+        self.seen = ring.0;
+        Some(ring.1.clone())
+    }
+}
+
+/// Derives the pairwise MAC keys with every peer through an authenticated
+/// Diffie-Hellman exchange over `inner`, so that replicas only need their
+/// own identity key and the public identities of the others. Keys are
+/// derived again every `rotation`, if set. The epoch of an exchange is the
+/// time its first message was sent, in milliseconds, so epochs keep growing
+/// across restarts.
+pub struct KeyedTransport<M, T> {
+    inner: T,
+    myid: Replica,
+    num_faults: usize,
+    identity: ed25519::Keypair,
+    identities: HashMap<Replica, ed25519::PublicKey>,
+    /// Our half of an exchange in progress, by peer
+    ephemerals: HashMap<Replica, (u64, Ephemeral)>,
+    /// The cancel handler of the last key exchange message to every peer,
+    /// which is resent until acknowledged
+    hellos: HashMap<Replica, CancelHandler<Acknowledgement>>,
+    /// The epoch of the current key with every peer
+    epochs: HashMap<Replica, u64>,
+    ring: Arc<RwLock<(u64, KeyRing)>>,
+    /// Protocol messages received while waiting for keys
+    buffered: VecDeque<M>,
+    rotation: Option<Interval>,
+}
+
+impl<M: Message, T: Transport<Keyed<M>>> KeyedTransport<M, T> {
+    /// `identities` holds the public identity of every replica, including
+    /// `myid`, of which at most `num_faults` may fail.
+    pub fn new(
+        inner: T,
+        myid: Replica,
+        num_faults: usize,
+        identity: ed25519::Keypair,
+        identities: HashMap<Replica, ed25519::PublicKey>,
+        rotation: Option<Duration>,
+    ) -> Self {
+        // Messages to ourselves never leave the replica, any key will do
+        let mut own = vec![0u8; crypto::SECRET_KEY_SIZE];
+        rand::thread_rng().fill_bytes(&mut own);
+        let mut ring = KeyRing::default();
+        ring.current.insert(myid, own);
+        Self {
+            inner,
+            myid,
+            num_faults,
+            identity,
+            identities,
+            ephemerals: HashMap::default(),
+            hellos: HashMap::default(),
+            epochs: HashMap::default(),
+            ring: Arc::new(RwLock::new((0, ring))),
+            buffered: VecDeque::new(),
+            rotation: rotation.map(|every| interval_at(Instant::now() + every, every)),
+        }
+    }
+
+    fn peers(&self) -> Vec<Replica> {
+        let mut peers: Vec<Replica> = self.identities.keys()
+            .filter(|id| **id != self.myid)
+            .copied()
+            .collect();
+        peers.sort_unstable();
+        peers
+    }
+
+    /// Start an exchange for the next epoch with every peer we are not
+    /// already exchanging keys with.
+    async fn rotate(&mut self) {
+        let now = now_millis();
+        for peer in self.peers() {
+            let epoch = self.epochs.get(&peer).map_or(now, |e| now.max(e + 1));
+            if self.ephemerals.get(&peer).is_some_and(|(e, _)| *e >= epoch) {
+                continue;
+            }
+            self.offer(peer, epoch).await;
+        }
+    }
+
+    async fn offer(&mut self, peer: Replica, epoch: u64) {
+        let ephemeral = Ephemeral::generate();
+        let hello = ephemeral.hello(&self.identity, self.myid, peer, epoch);
+        self.ephemerals.insert(peer, (epoch, ephemeral));
+        // A peer that is down gets the hello once it is back. Replacing the
+        // handler stops resending the hello of an earlier epoch
+        let handler = self.inner.send(peer, Keyed::Hello(hello)).await;
+        self.hellos.insert(peer, handler);
+    }
+
+    async fn handle_hello(&mut self, hello: Hello) {
+        let peer = hello.from;
+        let verified = hello.to == self.myid && self.identities.get(&peer)
+            .is_some_and(|pk| hello.verify(pk));
+        if !verified {
+            log::warn!("Dropping a key exchange message that claims to be from {}", peer);
+            return;
+        }
+        if now_millis().abs_diff(hello.epoch) > HELLO_WINDOW.as_millis() as u64 {
+            log::warn!("Dropping a key exchange message from {} for epoch {}, too far from our clock", peer, hello.epoch);
+            return;
+        }
+        // Stale or replayed
+        if self.epochs.get(&peer).is_some_and(|e| *e >= hello.epoch) {
+            return;
+        }
+        match self.ephemerals.get(&peer) {
+            Some((epoch, _)) if *epoch > hello.epoch => return,
+            Some((epoch, _)) if *epoch == hello.epoch => {},
+            // The peer rotates first, answer it
+            _ => self.offer(peer, hello.epoch).await,
+        }
+        let (_, ephemeral) = self.ephemerals.remove(&peer).expect("an ephemeral was just offered");
+        let key = match ephemeral.derive(self.myid, &hello) {
+            Some(key) => key,
+            None => {
+                log::warn!("Replica {} sent a degenerate key exchange message", peer);
+                return;
+            }
+        };
+        self.epochs.insert(peer, hello.epoch);
+        let mut ring = self.ring.write().expect("key ring lock poisoned");
+        ring.0 += 1;
+        if let Some(old) = ring.1.current.insert(peer, key.to_vec()) {
+            ring.1.previous.insert(peer, old);
+        }
+        log::debug!("Derived the key for epoch {} with {}", hello.epoch, peer);
+    }
+}
+
+async fn tick(rotation: &mut Option<Interval>) {
+    match rotation {
+        Some(rotation) => { rotation.tick().await; },
+        None => std::future::pending().await,
+    }
+}
+
+#[async_trait]
+impl<M: Message, T: Transport<Keyed<M>>> Transport<M> for KeyedTransport<M, T> {
+    async fn send(&mut self, to: Replica, msg: M) -> CancelHandler<Acknowledgement> {
+        self.inner.send(to, Keyed::Msg(msg)).await
+    }
+
+    async fn recv(&mut self) -> Option<M> {
+        if let Some(msg) = self.buffered.pop_front() {
+            return Some(msg);
+        }
+        loop {
+            tokio::select! {
+                msg = self.inner.recv() => match msg? {
+                    Keyed::Hello(hello) => self.handle_hello(hello).await,
+                    Keyed::Msg(msg) => return Some(msg),
+                },
+                _ = tick(&mut self.rotation) => self.rotate().await,
+            }
+        }
+    }
+
+    /// Exchange keys with all but `num_faults` peers, so that a replica
+    /// that is down does not hold up the others. Keys with the rest are
+    /// derived as their messages arrive in `recv`.
+    async fn ready(&mut self) {
+        self.rotate().await;
+        let quorum = (self.identities.len() - 1).saturating_sub(self.num_faults);
+        while self.epochs.len() < quorum {
+            match self.inner.recv().await {
+                Some(Keyed::Hello(hello)) => self.handle_hello(hello).await,
+                Some(Keyed::Msg(msg)) => self.buffered.push_back(msg),
+                None => return,
+            }
+        }
+        log::info!("Derived the keys with {} of {} peers", self.epochs.len(), self.identities.len() - 1);
+    }
+
+    fn key_watch(&self) -> Option<KeyWatch> {
+        Some(KeyWatch { ring: self.ring.clone(), seen: 0 })
+    }
+
+    fn authenticates_senders(&self) -> bool {
+        self.inner.authenticates_senders()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mesh;
+
+    use super::*;
+
+    type Net = KeyedTransport<u64, crate::MemTransport<Keyed<u64>>>;
+
+    fn keyed(keys: &[ed25519::Keypair], num_faults: usize, rotation: Option<Duration>) -> Vec<Net> {
+        let n = keys.len();
+        let identities: HashMap<Replica, ed25519::PublicKey> = keys.iter()
+            .enumerate()
+            .map(|(i, key)| (i, key.public()))
+            .collect();
+        let mut mesh = mesh(0..n);
+        keys.iter()
+            .enumerate()
+            .map(|(i, key)| KeyedTransport::new(mesh.remove(&i).unwrap(), i, num_faults, key.clone(), identities.clone(), rotation))
+            .collect()
+    }
+
+    fn identities(n: usize) -> Vec<ed25519::Keypair> {
+        (0..n).map(|_| ed25519::Keypair::generate()).collect()
+    }
+
+    fn keys_of(net: &Net) -> KeyRing {
+        net.key_watch().unwrap().fresh().unwrap()
+    }
+
+    #[tokio::test]
+    async fn peers_derive_matching_keys() {
+        let mut nets = keyed(&identities(3), 0, None);
+        let mut handles = Vec::new();
+        for mut net in nets.drain(..) {
+            handles.push(tokio::spawn(async move {
+                net.ready().await;
+                net
+            }));
+        }
+        let mut nets = Vec::new();
+        for handle in handles {
+            nets.push(handle.await.unwrap());
+        }
+        let rings: Vec<KeyRing> = nets.iter().map(keys_of).collect();
+        for i in 0..3 {
+            assert_eq!(rings[i].current.len(), 3);
+            for j in 0..3 {
+                if i != j {
+                    assert_eq!(rings[i].current[&j], rings[j].current[&i]);
+                }
+            }
+        }
+        assert_ne!(rings[0].current[&1], rings[0].current[&2]);
+
+        // Protocol messages pass through once keys are in place
+        nets[0].send(1, 5).await;
+        assert_eq!(nets[1].recv().await, Some(5));
+    }
+
+    #[tokio::test]
+    async fn keys_rotate() {
+        let mut nets = keyed(&identities(2), 0, Some(Duration::from_millis(20)));
+        let mut b = nets.pop().unwrap();
+        let mut a = nets.pop().unwrap();
+        tokio::join!(a.ready(), b.ready());
+        let first = keys_of(&a).current[&1].clone();
+        // Neither side receives protocol messages, so this only drives the
+        // rotations
+        let _ = tokio::time::timeout(Duration::from_millis(100), async {
+            tokio::join!(a.recv(), b.recv())
+        }).await;
+        let (ring_a, ring_b) = (keys_of(&a), keys_of(&b));
+        assert_ne!(ring_a.current[&1], first);
+        // A rotation may still be half way through
+        assert!(ring_a.current[&1] == ring_b.current[&0]
+            || ring_a.current[&1] == ring_b.previous[&0]
+            || ring_a.previous[&1] == ring_b.current[&0]);
+    }
+
+    #[tokio::test]
+    async fn replayed_and_forged_hellos_are_ignored() {
+        let keys = identities(2);
+        let mut nets = keyed(&keys, 0, None);
+        let mut b = nets.pop().unwrap();
+        let mut a = nets.pop().unwrap();
+        tokio::join!(a.ready(), b.ready());
+        let mut watch = b.key_watch().unwrap();
+        watch.fresh();
+        let epoch = b.epochs[&0];
+        // An epoch that is already keyed
+        b.handle_hello(Ephemeral::generate().hello(&keys[0], 0, 1, epoch)).await;
+        // Signed by someone else
+        b.handle_hello(Ephemeral::generate().hello(&ed25519::Keypair::generate(), 0, 1, epoch + 1)).await;
+        assert!(watch.fresh().is_none());
+
+        // A hello recorded in an earlier run, replayed to a restarted replica
+        let mut nets = keyed(&keys, 0, None);
+        let mut b = nets.pop().unwrap();
+        let mut watch = b.key_watch().unwrap();
+        watch.fresh();
+        let recorded = now_millis() - HELLO_WINDOW.as_millis() as u64 - 1;
+        b.handle_hello(Ephemeral::generate().hello(&keys[0], 0, 1, recorded)).await;
+        assert!(watch.fresh().is_none());
+        assert!(b.epochs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn ready_without_the_faulty_peers() {
+        let mut nets = keyed(&identities(4), 1, None);
+        // Replica 3 never starts
+        nets.pop();
+        let mut handles = Vec::new();
+        for mut net in nets.drain(..) {
+            handles.push(tokio::spawn(async move {
+                net.ready().await;
+                net
+            }));
+        }
+        for handle in handles {
+            let net = handle.await.unwrap();
+            assert_eq!(keys_of(&net).current.len(), 3);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Transports carry protocol messages between replicas. A protocol's
+//! `Context` only talks to the network through [`Transport`], so the same
+//! protocol code runs over TCP, over in-memory channels or over Unix domain
+//! sockets.
+
+use async_trait::async_trait;
+use network::{plaintcp::CancelHandler, Acknowledgement, Message};
+use types::Replica;
+
+mod framed;
+
+mod inbound;
+pub use inbound::*;
+
+mod pending;
+pub use pending::*;
+
+mod tcp;
+pub use tcp::*;
+
+mod mem;
+pub use mem::*;
+
+mod unix;
+pub use unix::*;
+
+mod tls;
+pub use tls::*;
+
+mod mux;
+pub use mux::*;
+
+mod kex;
+pub use kex::*;
+
+mod node;
+pub use node::*;
+
+#[async_trait]
+pub trait Transport<M: Message>: Send + 'static {
+    /// Send `msg` to `to`. The handler resolves once `to` acknowledged it.
+    async fn send(&mut self, to: Replica, msg: M) -> CancelHandler<Acknowledgement>;
+
+    /// Send the same `msg` to every replica in `peers`.
+    async fn broadcast(&mut self, msg: M, peers: Vec<Replica>) -> Vec<CancelHandler<Acknowledgement>> {
+        let mut handlers = Vec::with_capacity(peers.len());
+        for to in peers {
+            handlers.push(self.send(to, msg.clone()).await);
+        }
+        handlers
+    }
+
+    /// The next message received from any replica, or `None` once the
+    /// transport is closed. Dropping the future does not lose messages.
+    async fn recv(&mut self) -> Option<M>;
+
+    /// Wait until the transport can carry protocol messages, e.g. until
+    /// keys are agreed with enough peers.
+    async fn ready(&mut self) {}
+
+    /// The pairwise MAC keys, if the transport derives them.
+    fn key_watch(&self) -> Option<KeyWatch> {
+        None
+    }
+
+    /// Whether every received message is known to come from the sender it
+    /// names, e.g. because the connection it came on authenticated it.
+    fn authenticates_senders(&self) -> bool {
+        false
+    }
+}
+
+#[async_trait]
+impl<M: Message> Transport<M> for Box<dyn Transport<M>> {
+    async fn send(&mut self, to: Replica, msg: M) -> CancelHandler<Acknowledgement> {
+        (**self).send(to, msg).await
+    }
+
+    async fn broadcast(&mut self, msg: M, peers: Vec<Replica>) -> Vec<CancelHandler<Acknowledgement>> {
+        (**self).broadcast(msg, peers).await
+    }
+
+    async fn recv(&mut self) -> Option<M> {
+        (**self).recv().await
+    }
+
+    async fn ready(&mut self) {
+        (**self).ready().await
+    }
+
+    fn key_watch(&self) -> Option<KeyWatch> {
+        (**self).key_watch()
+    }
+
+    fn authenticates_senders(&self) -> bool {
+        (**self).authenticates_senders()
+    }
+}
@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use fnv::FnvHashMap;
+use network::{plaintcp::CancelHandler, Acknowledgement, Message};
+use tokio::sync::{mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender}, oneshot};
+use types::Replica;
+
+use crate::Transport;
+
+/// One end of an in-memory mesh built by [`mesh`]. Messages are delivered
+/// and acknowledged immediately, in the order they are sent.
+pub struct MemTransport<M> {
+    peers: Arc<FnvHashMap<Replica, UnboundedSender<M>>>,
+    rx: UnboundedReceiver<M>,
+}
+
+/// Connect every replica in `ids` to every other one (and itself).
+pub fn mesh<M>(ids: impl IntoIterator<Item = Replica>) -> FnvHashMap<Replica, MemTransport<M>> {
+    let mut senders = FnvHashMap::default();
+    let mut receivers = Vec::new();
+    for id in ids {
+        let (tx, rx) = unbounded_channel();
+        senders.insert(id, tx);
+        receivers.push((id, rx));
+    }
+    let peers = Arc::new(senders);
+    receivers.into_iter()
+        .map(|(id, rx)| (id, MemTransport { peers: peers.clone(), rx }))
+        .collect()
+}
+
+#[async_trait]
+impl<M: Message> Transport<M> for MemTransport<M> {
+    async fn send(&mut self, to: Replica, msg: M) -> CancelHandler<Acknowledgement> {
+        let (ack, ack_rx) = oneshot::channel();
+        match self.peers.get(&to) {
+            Some(peer) => {
+                // A closed peer never acknowledges, like a crashed one
+                if peer.send(msg).is_ok() {
+                    let _ = ack.send(Acknowledgement::Pong);
+                }
+            },
+            None => log::warn!("No replica {} in the mesh", to),
+        }
+        ack_rx
+    }
+
+    async fn recv(&mut self) -> Option<M> {
+        self.rx.recv().await
+    }
+}
@@ -0,0 +1,240 @@
+//! One listener per node for all of its traffic. Every frame is an
+//! [`Envelope`] tagged with the [`Channel`] it belongs to, and the listener
+//! hands its payload to the transport of that channel. Envelopes of a wire
+//! version or message schema this build cannot read are rejected.
+
+use std::{collections::HashMap, io, net::SocketAddr};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use fnv::FnvHashMap;
+use network::{plaintcp::CancelHandler, Acknowledgement, Message};
+use tokio::sync::mpsc::Sender;
+use types::{decode, reject_frame, Authored, Channel, Envelope, Replica};
+
+use crate::{ConnectionLimit, FairQueue, FairQueueSender, PEER_QUEUE_SIZE, TlsIdentity, Transport, fair_queue, framed::Inbox, tcp::tcp_listen, tls::tls_listen};
+
+/// The channels a node serves on its listener. Envelopes of a channel that
+/// was not registered are dropped.
+pub struct Listener {
+    channels: HashMap<Channel, Box<dyn Route>>,
+    max_frame_size: usize,
+}
+
+impl Listener {
+    /// A listener for frames of at most `max_frame_size` bytes.
+    pub fn new(max_frame_size: usize) -> Self {
+        Self { channels: HashMap::default(), max_frame_size }
+    }
+
+    /// Register `channel`, whose messages arrive in the returned queue.
+    /// Every replica gets a queue of `PEER_QUEUE_SIZE` messages for every
+    /// channel, and so does every connection that does not authenticate
+    /// the replica at the other end, of which there are at most
+    /// `MAX_UNAUTHENTICATED`.
+    pub fn channel<M: Message + Authored>(&mut self, channel: Channel) -> FairQueue<M> {
+        let (tx, rx) = fair_queue(PEER_QUEUE_SIZE);
+        self.channels.insert(channel, Box::new(ChannelRoute { queues: tx, max_frame_size: self.max_frame_size }));
+        rx
+    }
+
+    fn routes(&self, peer: Option<Replica>) -> Routes {
+        Routes {
+            queues: self.channels.iter()
+                .map(|(channel, route)| (*channel, route.queue(peer)))
+                .collect(),
+            peer,
+        }
+    }
+
+    /// Serve the registered channels on `listen` over plain TCP.
+    pub fn spawn_tcp(self, listen: SocketAddr) -> io::Result<()> {
+        let max_frame_size = self.max_frame_size;
+        tcp_listen::<Envelope, _, _>(listen, max_frame_size, ConnectionLimit::default(), move || self.routes(None))
+    }
+
+    /// Serve the registered channels on `listen` over mutual TLS, for the
+    /// replicas whose certificates are issued for their names in `accept`.
+    /// A connection is closed on the first message that names another
+    /// sender than the replica at the other end.
+    pub fn spawn_tls(
+        self,
+        listen: SocketAddr,
+        accept: FnvHashMap<Replica, String>,
+        identity: &TlsIdentity,
+    ) -> io::Result<()> {
+        let max_frame_size = self.max_frame_size;
+        tls_listen::<Envelope, _, _>(listen, accept, identity, max_frame_size, move |peer| self.routes(Some(peer)))
+    }
+}
+
+/// A channel registered on a [`Listener`].
+trait Route: Send + Sync {
+    /// The queue of the channel for a new connection, from `peer` if the
+    /// connection authenticated it.
+    fn queue(&self, peer: Option<Replica>) -> Box<dyn Payloads>;
+}
+
+/// Where the payloads of one channel go, from one connection.
+#[async_trait]
+trait Payloads: Send + Sync {
+    /// Decode `payload` and queue it, waiting for room. False if it does
+    /// not decode, if it names another sender than the authenticated
+    /// `peer`, or once the channel's transport is gone.
+    async fn put(&self, payload: Bytes, peer: Option<Replica>) -> bool;
+}
+
+struct ChannelRoute<M> {
+    queues: FairQueueSender<M>,
+    max_frame_size: usize,
+}
+
+impl<M: Message + Authored> Route for ChannelRoute<M> {
+    fn queue(&self, peer: Option<Replica>) -> Box<dyn Payloads> {
+        let tx = match peer {
+            Some(peer) => self.queues.peer(peer),
+            None => self.queues.queue(),
+        };
+        Box::new(ChannelQueue { tx, max_frame_size: self.max_frame_size })
+    }
+}
+
+struct ChannelQueue<M> {
+    tx: Sender<M>,
+    max_frame_size: usize,
+}
+
+#[async_trait]
+impl<M: Message + Authored> Payloads for ChannelQueue<M> {
+    async fn put(&self, payload: Bytes, peer: Option<Replica>) -> bool {
+        let msg: M = match decode(&payload, self.max_frame_size) {
+            Ok(msg) => msg,
+            Err(e) => {
+                reject_frame(&e);
+                return false;
+            }
+        };
+        if let Some(peer) = peer {
+            if msg.sender() != peer {
+                log::warn!("Replica {} sent a message as {}, closing the connection", peer, msg.sender());
+                return false;
+            }
+        }
+        self.tx.send(msg).await.is_ok()
+    }
+}
+
+/// The queues of one connection, one for every channel, and the replica at
+/// the other end if the connection authenticated it.
+pub(crate) struct Routes {
+    queues: HashMap<Channel, Box<dyn Payloads>>,
+    peer: Option<Replica>,
+}
+
+impl Routes {
+    /// Queue the payload of `envelope`, waiting for room. False if the
+    /// envelope cannot be read by this build, if its payload is not from
+    /// the replica at the other end, or once the channel's transport is
+    /// gone.
+    async fn route(&self, envelope: Envelope) -> bool {
+        if let Err(e) = envelope.check() {
+            reject_frame(&e);
+            return false;
+        }
+        match self.queues.get(&envelope.channel) {
+            Some(queue) => queue.put(envelope.payload, self.peer).await,
+            None => {
+                log::warn!("Dropping a message for {:?}, which this node does not serve", envelope.channel);
+                true
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Inbox<Envelope> for Routes {
+    async fn put(&mut self, envelope: Envelope) -> bool {
+        self.route(envelope).await
+    }
+}
+
+/// The transport of one channel. Messages go out through `out` in envelopes
+/// of the channel, and come in from the channel's queue on the listener.
+pub struct MuxTransport<M> {
+    channel: Channel,
+    out: Box<dyn Transport<Envelope>>,
+    rx: FairQueue<M>,
+}
+
+impl<M> MuxTransport<M> {
+    pub fn new(channel: Channel, out: Box<dyn Transport<Envelope>>, rx: FairQueue<M>) -> Self {
+        Self { channel, out, rx }
+    }
+}
+
+#[async_trait]
+impl<M: Message> Transport<M> for MuxTransport<M> {
+    async fn send(&mut self, to: Replica, msg: M) -> CancelHandler<Acknowledgement> {
+        self.out.send(to, Envelope::new(self.channel, &msg)).await
+    }
+
+    async fn broadcast(&mut self, msg: M, peers: Vec<Replica>) -> Vec<CancelHandler<Acknowledgement>> {
+        self.out.broadcast(Envelope::new(self.channel, &msg), peers).await
+    }
+
+    async fn recv(&mut self) -> Option<M> {
+        self.rx.recv().await
+    }
+
+    // A node listens over the same kind of connections it dials out on
+    fn authenticates_senders(&self) -> bool {
+        self.out.authenticates_senders()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use types::{Protocol, MAX_FRAME_SIZE};
+
+    use super::*;
+    use crate::{TlsPeer, TlsTransport, replica_name, tls::tests::{Note, free_addr, identities, names}};
+
+    #[tokio::test]
+    async fn one_listener_serves_every_channel() {
+        let (ids, _root) = identities(&["replica-0", "replica-1"]);
+        let addr = free_addr();
+        let mut listener = Listener::new(MAX_FRAME_SIZE);
+        let consensus_rx = listener.channel(Channel::Consensus(Protocol::Pbft));
+        let sync_rx = listener.channel(Channel::Sync);
+        listener.spawn_tls(addr, names([1]), &ids[0]).unwrap();
+        let mut consensus = MuxTransport::<Note>::new(
+            Channel::Consensus(Protocol::Pbft),
+            Box::new(TlsTransport::<Envelope>::dial(FnvHashMap::default(), &ids[0]).unwrap()),
+            consensus_rx,
+        );
+        let mut sync = MuxTransport::<types::SyncMsg>::new(
+            Channel::Sync,
+            Box::new(TlsTransport::<Envelope>::dial(FnvHashMap::default(), &ids[0]).unwrap()),
+            sync_rx,
+        );
+
+        let peers: FnvHashMap<Replica, TlsPeer> = [(0, TlsPeer { addr: addr.to_string(), name: replica_name(0) })].into_iter().collect();
+        let mut dialer = TlsTransport::<Envelope>::dial(peers.clone(), &ids[1]).unwrap();
+        let started = types::SyncMsg { sender: 1, state: types::SyncState::STARTED, value: String::new() };
+        dialer.send(0, Envelope::new(Channel::Sync, &started)).await.await.unwrap();
+        dialer.send(0, Envelope::new(Channel::Consensus(Protocol::Pbft), &Note(1, 7))).await.await.unwrap();
+        // Not served here
+        dialer.send(0, Envelope::new(Channel::Consensus(Protocol::Acs), &Note(1, 8))).await.await.unwrap();
+        dialer.send(0, Envelope::new(Channel::Consensus(Protocol::Pbft), &Note(1, 9))).await.await.unwrap();
+        assert_eq!(sync.recv().await.map(|msg| msg.sender), Some(1));
+        assert_eq!(consensus.recv().await, Some(Note(1, 7)));
+        assert_eq!(consensus.recv().await, Some(Note(1, 9)));
+
+        // Replica 1 cannot send as replica 0
+        let mut forger = TlsTransport::<Envelope>::dial(peers, &ids[1]).unwrap();
+        let ack = forger.send(0, Envelope::new(Channel::Consensus(Protocol::Pbft), &Note(0, 10))).await;
+        let res = tokio::time::timeout(std::time::Duration::from_millis(300), consensus.recv()).await;
+        assert!(res.is_err());
+        drop(ack);
+    }
+}
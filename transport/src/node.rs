@@ -0,0 +1,118 @@
+//! Transports set up from the node configuration.
+
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+use anyhow::{Result, anyhow};
+use config::Node;
+use crypto::{Algorithm, ed25519};
+use fnv::FnvHashMap;
+use network::Message;
+use types::{Authored, Channel, Envelope, Protocol, Replica, SyncMsg};
+
+use crate::{Keyed, KeyedTransport, Listener, MuxTransport, TcpTransport, TlsIdentity, TlsPeer, TlsTransport, Transport, SYNCER_NAME, replica_name};
+
+impl TlsIdentity {
+    pub fn from_node(config: &Node) -> Self {
+        Self {
+            cert: config.my_cert.clone(),
+            key: config.my_cert_key.clone(),
+            root: config.root_cert.clone(),
+        }
+    }
+}
+
+/// A replica's consensus and syncer transports.
+pub type ReplicaTransports<M> = (Box<dyn Transport<M>>, Box<dyn Transport<SyncMsg>>);
+
+/// The transports replica `config.id` uses for the consensus messages of
+/// `protocol` and for talking to the syncer. Both are served by a single
+/// listener on the replica's consensus port, over mutual TLS if `config.tls`
+/// is set and plain TCP otherwise. The addresses of the peers are looked up
+/// every time the replica connects to them. With `Algorithm::ED25519`, the
+/// consensus transport also derives the pairwise MAC keys, see
+/// [`KeyedTransport`].
+pub fn replica_transports<M: Message + Authored>(config: &Node, protocol: Protocol) -> Result<ReplicaTransports<M>> {
+    match config.crypto_alg {
+        Algorithm::NOPKI => channel_transports(config, protocol),
+        Algorithm::ED25519 => {
+            let (net, sync) = channel_transports::<Keyed<M>>(config, protocol)?;
+            let identity = ed25519::Keypair::decode(&mut config.secret_key_bytes.clone())?;
+            let mut identities = HashMap::default();
+            for (replica, pk) in config.pk_map.iter() {
+                identities.insert(*replica, ed25519::PublicKey::decode(pk)?);
+            }
+            let rotation = if config.key_rotation > 0 {
+                Some(Duration::from_secs(config.key_rotation))
+            } else {
+                None
+            };
+            Ok((Box::new(KeyedTransport::new(net, config.id, config.num_faults, identity, identities, rotation)), sync))
+        }
+    }
+}
+
+type Outgoing = Box<dyn Transport<Envelope>>;
+
+fn channel_transports<M: Message + Authored>(config: &Node, protocol: Protocol) -> Result<ReplicaTransports<M>> {
+    let my_addr = config.net_map.get(&config.id)
+        .ok_or_else(|| anyhow!("No address for ourselves, replica {}", config.id))?;
+    let listen = config::wildcard(my_addr)?;
+    let replicas = config.net_map.iter().filter(|(replica, _)| **replica < config.num_nodes);
+    let consensus = Channel::Consensus(protocol);
+    let mut listener = Listener::new(config.max_frame_size);
+    let consensus_rx = listener.channel(consensus);
+    let sync_rx = listener.channel(Channel::Sync);
+
+    let (consensus_out, sync_out): (Outgoing, Outgoing) = if !config.tls {
+        listener.spawn_tcp(listen)?;
+        let peers = replicas.map(|(replica, addr)| (*replica, addr.clone())).collect();
+        let mut syncer_map = FnvHashMap::default();
+        syncer_map.insert(0, config.client_addr.clone());
+        (Box::new(TcpTransport::dial(peers)), Box::new(TcpTransport::dial(syncer_map)))
+    } else {
+        let identity = TlsIdentity::from_node(config);
+        let mut accept: FnvHashMap<Replica, String> = (0..config.num_nodes)
+            .map(|replica| (replica, replica_name(replica)))
+            .collect();
+        // The syncer signs its messages with the id after the last replica
+        accept.insert(config.num_nodes, SYNCER_NAME.to_string());
+        listener.spawn_tls(listen, accept, &identity)?;
+        let peers = replicas
+            .map(|(replica, addr)| (*replica, TlsPeer { addr: addr.clone(), name: replica_name(*replica) }))
+            .collect();
+        let mut syncer_map = FnvHashMap::default();
+        syncer_map.insert(0, TlsPeer { addr: config.client_addr.clone(), name: SYNCER_NAME.to_string() });
+        (Box::new(TlsTransport::dial(peers, &identity)?), Box::new(TlsTransport::dial(syncer_map, &identity)?))
+    };
+    Ok((
+        Box::new(MuxTransport::new(consensus, consensus_out, consensus_rx)),
+        Box::new(MuxTransport::new(Channel::Sync, sync_out, sync_rx)),
+    ))
+}
+
+/// The transport of the syncer listening on `listen` and talking to the
+/// replicas at `nodes`, over mutual TLS if `tls` is given. The addresses of
+/// the replicas are looked up every time the syncer connects.
+pub fn syncer_transport(
+    listen: SocketAddr,
+    nodes: FnvHashMap<Replica, String>,
+    tls: Option<TlsIdentity>,
+) -> Result<Box<dyn Transport<SyncMsg>>> {
+    let mut listener = Listener::new(types::MAX_FRAME_SIZE);
+    let rx = listener.channel(Channel::Sync);
+    let out: Outgoing = match tls {
+        None => {
+            listener.spawn_tcp(listen)?;
+            Box::new(TcpTransport::dial(nodes))
+        },
+        Some(identity) => {
+            let replicas = nodes.keys().map(|replica| (*replica, replica_name(*replica))).collect();
+            listener.spawn_tls(listen, replicas, &identity)?;
+            let peers = nodes.into_iter()
+                .map(|(replica, addr)| (replica, TlsPeer { addr, name: replica_name(replica) }))
+                .collect();
+            Box::new(TlsTransport::dial(peers, &identity)?)
+        }
+    };
+    Ok(Box::new(MuxTransport::new(Channel::Sync, out, rx)))
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use network::{plaintcp::CancelHandler, Acknowledgement};
+use tokio::sync::oneshot::error::TryRecvError;
+use types::Replica;
+
+/// What an outgoing message belongs to: a protocol instance and a round
+/// within it. Messages that belong to no instance, such as the ones to and
+/// from the syncer, go under [`Slot::CONTROL`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Slot {
+    pub instance: Replica,
+    pub round: usize,
+}
+
+impl Slot {
+    pub const CONTROL: Slot = Slot { instance: Replica::MAX, round: 0 };
+
+    pub fn new(instance: Replica, round: usize) -> Self {
+        Self { instance, round }
+    }
+}
+
+/// The cancel handlers of messages that were sent but not acknowledged yet.
+/// Dropping a handler tells the transport to stop resending its message, so
+/// handlers are only dropped once acknowledged, or at shutdown: a replica
+/// that is done with an instance still owes its messages to the peers that
+/// are not, or reliable broadcast would lose its totality.
+#[derive(Debug, Default)]
+pub struct PendingAcks {
+    handlers: HashMap<Slot, Vec<CancelHandler<Acknowledgement>>>,
+    len: usize,
+    // Acknowledged handlers are swept out once `len` doubles past this
+    swept: usize,
+}
+
+impl PendingAcks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, slot: Slot, handler: CancelHandler<Acknowledgement>) {
+        self.handlers.entry(slot).or_default().push(handler);
+        self.len += 1;
+        if self.len >= 2 * self.swept.max(64) {
+            self.reap();
+        }
+    }
+
+    /// Forget the handlers whose messages were acknowledged, or given up on
+    /// by the transport. Returns how many are still pending.
+    pub fn reap(&mut self) -> usize {
+        self.handlers.retain(|_, handlers| {
+            handlers.retain_mut(|h| matches!(h.try_recv(), Err(TryRecvError::Empty)));
+            !handlers.is_empty()
+        });
+        self.len = self.handlers.values().map(Vec::len).sum();
+        self.swept = self.len;
+        log::debug!("{} messages are waiting for an acknowledgement", self.len);
+        self.len
+    }
+
+    /// Stop resending everything, e.g. at shutdown. Returns how many
+    /// messages were still unacknowledged.
+    pub fn cancel_all(&mut self) -> usize {
+        let pending = self.reap();
+        self.handlers.clear();
+        self.len = 0;
+        pending
+    }
+
+    /// The number of handlers held, acknowledged or not.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use network::Acknowledgement;
+    use tokio::sync::oneshot;
+
+    #[test]
+    fn handlers_go_away_when_acknowledged_or_cancelled() {
+        let mut pending = PendingAcks::new();
+        let mut acks = Vec::new();
+        for round in 0..3 {
+            let (tx, rx) = oneshot::channel();
+            pending.add(Slot::new(1, round), rx);
+            acks.push(tx);
+        }
+        let (tx, rx) = oneshot::channel();
+        pending.add(Slot::new(2, 0), rx);
+        assert_eq!(pending.len(), 4);
+
+        acks.remove(2).send(Acknowledgement::Pong).unwrap();
+        assert_eq!(pending.reap(), 3);
+        assert!(!acks[0].is_closed() && !acks[1].is_closed());
+
+        assert_eq!(pending.cancel_all(), 3);
+        assert!(pending.is_empty());
+        assert!(acks[0].is_closed() && acks[1].is_closed() && tx.is_closed());
+    }
+}
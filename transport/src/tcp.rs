@@ -0,0 +1,165 @@
+use std::{io, net::SocketAddr};
+
+use async_trait::async_trait;
+use fnv::FnvHashMap;
+use network::{plaintcp::CancelHandler, Acknowledgement, Message};
+use tokio::{net::{TcpListener, TcpStream}, sync::mpsc::{unbounded_channel, UnboundedSender}};
+use types::Replica;
+
+use crate::{ConnectionLimit, FairQueue, PEER_QUEUE_SIZE, Transport, fair_queue, framed::{Inbox, Outgoing, serve, deliver, enqueue}};
+
+/// Plain TCP. Every message is a length delimited bincode frame, answered
+/// by the receiver with an acknowledgement frame, as over Unix sockets.
+pub struct TcpTransport<M> {
+    peers: FnvHashMap<Replica, UnboundedSender<Outgoing>>,
+    rx: FairQueue<M>,
+}
+
+/// Listen on `listen`, and serve every connection with a fresh inbox from
+/// `inbox`. Incoming frames are limited to `max_frame_size` bytes. Nothing
+/// authenticates the other end, so connections beyond `limit` are closed
+/// right away.
+pub(crate) fn tcp_listen<M, I, F>(listen: SocketAddr, max_frame_size: usize, limit: ConnectionLimit, inbox: F) -> io::Result<()>
+where
+    M: Message,
+    I: Inbox<M> + 'static,
+    F: Fn() -> I + Send + 'static,
+{
+    let listener = std::net::TcpListener::bind(listen)?;
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, remote)) => {
+                    let guard = match limit.admit(Some(remote.ip())) {
+                        Some(guard) => guard,
+                        None => {
+                            log::warn!("Too many connections, refusing the one from {}", remote);
+                            continue;
+                        }
+                    };
+                    let inbox = inbox();
+                    tokio::spawn(async move {
+                        serve::<M, _, _>(stream, inbox, max_frame_size).await;
+                        drop(guard);
+                    });
+                },
+                Err(e) => {
+                    log::error!("Failed to accept a connection: {}", e);
+                    return;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Start a delivery task for every peer. The address of a peer is
+/// `host:port`, and its host is looked up on every connect.
+fn tcp_dial(peers: FnvHashMap<Replica, String>) -> FnvHashMap<Replica, UnboundedSender<Outgoing>> {
+    peers.into_iter()
+        .map(|(id, addr)| {
+            let (peer_tx, peer_rx) = unbounded_channel();
+            tokio::spawn(deliver(move || {
+                let addr = addr.clone();
+                async move { TcpStream::connect(addr.as_str()).await }
+            }, peer_rx));
+            (id, peer_tx)
+        })
+        .collect()
+}
+
+impl<M: Message> TcpTransport<M> {
+    /// Listen on `listen` and connect to `peers`. Incoming frames are
+    /// limited to `max_frame_size` bytes.
+    pub fn spawn(listen: SocketAddr, peers: FnvHashMap<Replica, String>, max_frame_size: usize) -> io::Result<Self> {
+        let (tx, rx) = fair_queue(PEER_QUEUE_SIZE);
+        tcp_listen::<M, _, _>(listen, max_frame_size, ConnectionLimit::default(), move || tx.queue())?;
+        Ok(Self { peers: tcp_dial(peers), rx })
+    }
+
+    /// Only connect to `peers`, for a node whose listener is elsewhere.
+    /// Nothing is ever received.
+    pub fn dial(peers: FnvHashMap<Replica, String>) -> Self {
+        let (_, rx) = fair_queue(1);
+        Self { peers: tcp_dial(peers), rx }
+    }
+}
+
+#[async_trait]
+impl<M: Message> Transport<M> for TcpTransport<M> {
+    async fn send(&mut self, to: Replica, msg: M) -> CancelHandler<Acknowledgement> {
+        enqueue(self.peers.get(&to), &msg)
+    }
+
+    async fn recv(&mut self) -> Option<M> {
+        self.rx.recv().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{SinkExt, StreamExt};
+    use tokio_util::codec::{Framed, LengthDelimitedCodec};
+    use types::{MAX_FRAME_SIZE, Versions};
+
+    use super::*;
+    use crate::tls::tests::free_addr;
+
+    #[tokio::test]
+    async fn oversized_frames_close_the_connection() {
+        let addr = free_addr();
+        let mut a = TcpTransport::<Vec<u8>>::spawn(addr, FnvHashMap::default(), 64).unwrap();
+        let hello = || bytes::Bytes::from(bincode::serialize(&Versions::SUPPORTED).unwrap());
+        let before = types::rejected_frames();
+
+        let mut conn = Framed::new(TcpStream::connect(addr).await.unwrap(), LengthDelimitedCodec::new());
+        conn.send(hello()).await.unwrap();
+        assert!(conn.next().await.is_some());
+        conn.send(bincode::serialize(&vec![0u8; 80]).unwrap().into()).await.unwrap();
+        assert!(conn.next().await.is_none());
+        assert!(types::rejected_frames() > before);
+
+        // Another peer still gets through
+        let peers: FnvHashMap<Replica, String> = [(0, format!("localhost:{}", addr.port()))].into_iter().collect();
+        let mut b = TcpTransport::<Vec<u8>>::dial(peers);
+        b.send(0, vec![1, 2, 3]).await.await.unwrap();
+        assert_eq!(a.recv().await, Some(vec![1, 2, 3]));
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_connections_are_limited() {
+        let addr = free_addr();
+        let (tx, mut rx) = fair_queue::<Vec<u8>>(PEER_QUEUE_SIZE);
+        tcp_listen::<Vec<u8>, _, _>(addr, MAX_FRAME_SIZE, ConnectionLimit::new(2, 2), move || tx.queue()).unwrap();
+        let hello = || bytes::Bytes::from(bincode::serialize(&Versions::SUPPORTED).unwrap());
+        let connect = || async {
+            let mut conn = Framed::new(TcpStream::connect(addr).await.unwrap(), LengthDelimitedCodec::new());
+            let _ = conn.send(hello()).await;
+            let answered = matches!(conn.next().await, Some(Ok(_)));
+            (conn, answered)
+        };
+
+        let mut open = Vec::new();
+        for _ in 0..2 {
+            let (conn, answered) = connect().await;
+            assert!(answered);
+            open.push(conn);
+        }
+        // Refused before it gets a queue
+        let (_, answered) = connect().await;
+        assert!(!answered);
+
+        // Once a connection closes, another one may open
+        drop(open.pop());
+        let mut conn = loop {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            if let (conn, true) = connect().await {
+                break conn;
+            }
+        };
+        conn.send(bincode::serialize(&vec![1u8]).unwrap().into()).await.unwrap();
+        assert_eq!(rx.recv().await, Some(vec![1]));
+    }
+}
@@ -0,0 +1,314 @@
+use std::{io, net::SocketAddr, sync::Arc};
+
+use async_trait::async_trait;
+use config::Secret;
+use fnv::FnvHashMap;
+use network::{plaintcp::CancelHandler, Acknowledgement, Message};
+use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig, ServerName, server::AllowAnyAuthenticatedClient};
+use tokio::{net::{TcpListener, TcpStream}, sync::mpsc::{unbounded_channel, Sender, UnboundedSender}};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use types::{Authored, Replica};
+
+use crate::{FairQueue, PEER_QUEUE_SIZE, Transport, fair_queue, framed::{Inbox, Outgoing, serve, deliver, enqueue}};
+
+/// The name certificates of the syncer are issued for.
+pub const SYNCER_NAME: &str = "syncer";
+
+/// The name the certificate of replica `id` is issued for.
+pub fn replica_name(id: Replica) -> String {
+    format!("replica-{}", id)
+}
+
+/// A DER encoded certificate and PKCS#8 key, and the root they chain to.
+#[derive(Debug, Clone)]
+pub struct TlsIdentity {
+    pub cert: Vec<u8>,
+    pub key: Secret,
+    pub root: Vec<u8>,
+}
+
+/// A peer to connect to, and the name its certificate must be issued for.
+/// The address is `host:port`, and its host is looked up on every connect.
+#[derive(Debug, Clone)]
+pub struct TlsPeer {
+    pub addr: String,
+    pub name: String,
+}
+
+/// Mutually authenticated TLS over TCP. Both ends present a certificate
+/// signed by the common root: a connecting peer checks that the listener's
+/// certificate is issued for the name it expects, and a listener only
+/// accepts peers whose certificate is issued for one of the `accept` names,
+/// and only messages that name the replica of that certificate as their
+/// sender.
+pub struct TlsTransport<M> {
+    peers: FnvHashMap<Replica, UnboundedSender<Outgoing>>,
+    rx: FairQueue<M>,
+}
+
+fn invalid<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+impl TlsIdentity {
+    fn configs(&self) -> io::Result<(ServerConfig, ClientConfig)> {
+        let mut roots = RootCertStore::empty();
+        roots.add(&Certificate(self.root.clone())).map_err(invalid)?;
+        let chain = vec![Certificate(self.cert.clone())];
+        let key = PrivateKey(self.key.to_vec());
+        let server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots.clone()))
+            .with_single_cert(chain.clone(), key.clone())
+            .map_err(invalid)?;
+        let client_config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_single_cert(chain, key)
+            .map_err(invalid)?;
+        Ok((server_config, client_config))
+    }
+}
+
+/// Listen on `listen` for the replicas whose certificates are issued for
+/// their names in `accept`, and serve every connection with a fresh inbox
+/// from `inbox` for the replica at the other end. Incoming frames are
+/// limited to `max_frame_size` bytes.
+pub(crate) fn tls_listen<M, I, F>(
+    listen: SocketAddr,
+    accept: FnvHashMap<Replica, String>,
+    identity: &TlsIdentity,
+    max_frame_size: usize,
+    inbox: F,
+) -> io::Result<()>
+where
+    M: Message,
+    I: Inbox<M> + 'static,
+    F: Fn(Replica) -> I + Send + Sync + 'static,
+{
+    let (server_config, _) = identity.configs()?;
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let listener = std::net::TcpListener::bind(listen)?;
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    let accept = Arc::new(accept);
+    let inbox = Arc::new(inbox);
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    log::error!("Failed to accept a connection: {}", e);
+                    return;
+                }
+            };
+            let (acceptor, accept, inbox) = (acceptor.clone(), accept.clone(), inbox.clone());
+            tokio::spawn(async move {
+                let stream = match acceptor.accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::warn!("TLS handshake failed: {}", e);
+                        return;
+                    }
+                };
+                let peer = stream.get_ref().1.peer_certificates()
+                    .and_then(|certs| certs.first())
+                    .and_then(|cert| peer_id(&cert.0, &accept));
+                match peer {
+                    Some(peer) => {
+                        log::debug!("Accepted a TLS connection from {}", accept[&peer]);
+                        serve::<M, _, _>(stream, inbox(peer), max_frame_size).await;
+                    },
+                    None => log::warn!("Rejected a TLS connection from an unexpected peer"),
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Start a delivery task for every peer, connecting only if the peer's
+/// certificate is issued for its name.
+pub(crate) fn tls_dial(
+    peers: FnvHashMap<Replica, TlsPeer>,
+    identity: &TlsIdentity,
+) -> io::Result<FnvHashMap<Replica, UnboundedSender<Outgoing>>> {
+    let (_, client_config) = identity.configs()?;
+    let connector = TlsConnector::from(Arc::new(client_config));
+    let mut senders = FnvHashMap::default();
+    for (id, peer) in peers {
+        let name = ServerName::try_from(peer.name.as_str()).map_err(invalid)?;
+        let (peer_tx, peer_rx) = unbounded_channel();
+        let connector = connector.clone();
+        tokio::spawn(deliver(move || {
+            let (connector, name, addr) = (connector.clone(), name.clone(), peer.addr.clone());
+            async move {
+                let stream = TcpStream::connect(addr.as_str()).await?;
+                connector.connect(name, stream).await
+            }
+        }, peer_rx));
+        senders.insert(id, peer_tx);
+    }
+    Ok(senders)
+}
+
+/// The inbox of a connection from `peer`, which takes only the messages
+/// `peer` sends as itself.
+struct FromPeer<M> {
+    tx: Sender<M>,
+    peer: Replica,
+}
+
+#[async_trait]
+impl<M: Message + Authored> Inbox<M> for FromPeer<M> {
+    async fn put(&mut self, msg: M) -> bool {
+        if msg.sender() != self.peer {
+            log::warn!("Replica {} sent a message as {}, closing the connection", self.peer, msg.sender());
+            return false;
+        }
+        self.tx.put(msg).await
+    }
+}
+
+impl<M: Message + Authored> TlsTransport<M> {
+    /// Listen on `listen` for the replicas whose certificates are issued for
+    /// their names in `accept`, and connect to `peers`. Incoming frames are
+    /// limited to `max_frame_size` bytes.
+    pub fn spawn(
+        listen: SocketAddr,
+        peers: FnvHashMap<Replica, TlsPeer>,
+        accept: FnvHashMap<Replica, String>,
+        identity: &TlsIdentity,
+        max_frame_size: usize,
+    ) -> io::Result<Self> {
+        let (tx, rx) = fair_queue(PEER_QUEUE_SIZE);
+        tls_listen::<M, _, _>(listen, accept, identity, max_frame_size, move |peer| FromPeer { tx: tx.peer(peer), peer })?;
+        Ok(Self { peers: tls_dial(peers, identity)?, rx })
+    }
+}
+
+impl<M: Message> TlsTransport<M> {
+    /// Only connect to `peers`, for a node whose listener is elsewhere.
+    /// Nothing is ever received.
+    pub fn dial(peers: FnvHashMap<Replica, TlsPeer>, identity: &TlsIdentity) -> io::Result<Self> {
+        let (_, rx) = fair_queue(1);
+        Ok(Self { peers: tls_dial(peers, identity)?, rx })
+    }
+}
+
+/// The replica in `accept` whose name the (already verified) certificate
+/// `cert` is issued for.
+fn peer_id(cert: &[u8], accept: &FnvHashMap<Replica, String>) -> Option<Replica> {
+    let cert = webpki::EndEntityCert::try_from(cert).ok()?;
+    accept.iter()
+        .find(|(_, name)| {
+            webpki::DnsNameRef::try_from_ascii_str(name)
+                .map(|dns| cert.verify_is_valid_for_dns_name(dns).is_ok())
+                .unwrap_or(false)
+        })
+        .map(|(replica, _)| *replica)
+}
+
+#[async_trait]
+impl<M: Message> Transport<M> for TlsTransport<M> {
+    async fn send(&mut self, to: Replica, msg: M) -> CancelHandler<Acknowledgement> {
+        enqueue(self.peers.get(&to), &msg)
+    }
+
+    async fn recv(&mut self) -> Option<M> {
+        self.rx.recv().await
+    }
+
+    fn authenticates_senders(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use rcgen::{BasicConstraints, Certificate as RcCert, CertificateParams, IsCa};
+    use types::MAX_FRAME_SIZE;
+
+    use super::*;
+
+    pub(crate) fn identities(names: &[&str]) -> (Vec<TlsIdentity>, RcCert) {
+        let mut params = CertificateParams::new(vec![]);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let root = RcCert::from_params(params).unwrap();
+        let root_der = root.serialize_der().unwrap();
+        let ids = names.iter().map(|name| {
+            let cert = RcCert::from_params(CertificateParams::new(vec![name.to_string()])).unwrap();
+            TlsIdentity {
+                cert: cert.serialize_der_with_signer(&root).unwrap(),
+                key: cert.serialize_private_key_der().into(),
+                root: root_der.clone(),
+            }
+        }).collect();
+        (ids, root)
+    }
+
+    pub(crate) fn free_addr() -> SocketAddr {
+        std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+    }
+
+    /// A message that says who sent it.
+    #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+    pub(crate) struct Note(pub Replica, pub u64);
+
+    impl Authored for Note {
+        fn sender(&self) -> Replica {
+            self.0
+        }
+    }
+
+    pub(crate) fn names(replicas: impl IntoIterator<Item = Replica>) -> FnvHashMap<Replica, String> {
+        replicas.into_iter().map(|i| (i, replica_name(i))).collect()
+    }
+
+    #[tokio::test]
+    async fn tls_peers_authenticate_each_other() {
+        let (ids, _root) = identities(&["replica-0", "replica-1"]);
+        let addrs = [free_addr(), free_addr()];
+        let peers: FnvHashMap<Replica, TlsPeer> = (0..2)
+            .map(|i| (i, TlsPeer { addr: addrs[i].to_string(), name: replica_name(i) }))
+            .collect();
+        let mut a = TlsTransport::<Note>::spawn(addrs[0], peers.clone(), names(0..2), &ids[0], MAX_FRAME_SIZE).unwrap();
+        let mut b = TlsTransport::<Note>::spawn(addrs[1], peers, names(0..2), &ids[1], MAX_FRAME_SIZE).unwrap();
+        a.send(1, Note(0, 7)).await.await.unwrap();
+        assert_eq!(b.recv().await, Some(Note(0, 7)));
+        b.send(0, Note(1, 8)).await;
+        assert_eq!(a.recv().await, Some(Note(1, 8)));
+    }
+
+    #[tokio::test]
+    async fn tls_rejects_wrong_identity() {
+        let (ids, _root) = identities(&["replica-0", "replica-2"]);
+        let addrs = [free_addr(), free_addr()];
+        // Replica 1's listener only accepts replica 0, but the dialer
+        // holds a certificate for replica 2
+        let mut a = TlsTransport::<Note>::spawn(addrs[0], FnvHashMap::default(), names([0]), &ids[0], MAX_FRAME_SIZE).unwrap();
+        let peers: FnvHashMap<Replica, TlsPeer> = [(0, TlsPeer { addr: addrs[0].to_string(), name: replica_name(0) })].into_iter().collect();
+        let mut c = TlsTransport::<Note>::spawn(addrs[1], peers, FnvHashMap::default(), &ids[1], MAX_FRAME_SIZE).unwrap();
+        let ack = c.send(0, Note(2, 9)).await;
+        let res = tokio::time::timeout(std::time::Duration::from_millis(300), a.recv()).await;
+        assert!(res.is_err());
+        drop(ack);
+    }
+
+    #[tokio::test]
+    async fn tls_peers_cannot_send_as_others() {
+        let (ids, _root) = identities(&["replica-0", "replica-1"]);
+        let addr = free_addr();
+        let mut a = TlsTransport::<Note>::spawn(addr, FnvHashMap::default(), names(0..3), &ids[0], MAX_FRAME_SIZE).unwrap();
+        let peers: FnvHashMap<Replica, TlsPeer> = [(0, TlsPeer { addr: addr.to_string(), name: replica_name(0) })].into_iter().collect();
+        // Replica 1 claims to be replica 2
+        let mut forger = TlsTransport::<Note>::dial(peers.clone(), &ids[1]).unwrap();
+        let ack = forger.send(0, Note(2, 9)).await;
+        let res = tokio::time::timeout(std::time::Duration::from_millis(300), a.recv()).await;
+        assert!(res.is_err());
+        drop(ack);
+        let mut honest = TlsTransport::<Note>::dial(peers, &ids[1]).unwrap();
+        honest.send(0, Note(1, 10)).await.await.unwrap();
+        assert_eq!(a.recv().await, Some(Note(1, 10)));
+    }
+}
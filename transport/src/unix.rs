@@ -0,0 +1,171 @@
+use std::{io, path::PathBuf};
+
+use async_trait::async_trait;
+use fnv::FnvHashMap;
+use network::{plaintcp::CancelHandler, Acknowledgement, Message};
+use tokio::{net::{UnixListener, UnixStream}, sync::mpsc::{unbounded_channel, UnboundedSender}};
+use types::Replica;
+
+use crate::{ConnectionLimit, FairQueue, PEER_QUEUE_SIZE, Transport, fair_queue, framed::{Outgoing, serve, deliver, enqueue}};
+
+/// Replicas on the same host talking over Unix domain sockets. Every message
+/// is a length delimited bincode frame, answered by the receiver with an
+/// acknowledgement frame.
+pub struct UnixTransport<M> {
+    peers: FnvHashMap<Replica, UnboundedSender<Outgoing>>,
+    rx: FairQueue<M>,
+}
+
+impl<M: Message> UnixTransport<M> {
+    /// Listen on the socket at `path` and connect to the sockets of `peers`.
+    /// A stale socket file at `path` is replaced. Incoming frames are limited
+    /// to `max_frame_size` bytes.
+    pub fn spawn(path: PathBuf, peers: FnvHashMap<Replica, PathBuf>, max_frame_size: usize) -> io::Result<Self> {
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        let (tx, rx) = fair_queue(PEER_QUEUE_SIZE);
+        // Peers on the same host look alike, so only the overall limit holds
+        let limit = ConnectionLimit::default();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let guard = match limit.admit(None) {
+                            Some(guard) => guard,
+                            None => {
+                                log::warn!("Too many connections on the unix socket, refusing one");
+                                continue;
+                            }
+                        };
+                        let queue = tx.queue();
+                        tokio::spawn(async move {
+                            serve::<M, _, _>(stream, queue, max_frame_size).await;
+                            drop(guard);
+                        });
+                    },
+                    Err(e) => {
+                        log::error!("Failed to accept a unix socket connection: {}", e);
+                        return;
+                    }
+                }
+            }
+        });
+        let peers = peers.into_iter()
+            .map(|(id, peer_path)| {
+                let (peer_tx, peer_rx) = unbounded_channel();
+                tokio::spawn(deliver(move || UnixStream::connect(peer_path.clone()), peer_rx));
+                (id, peer_tx)
+            })
+            .collect();
+        Ok(Self { peers, rx })
+    }
+}
+
+#[async_trait]
+impl<M: Message> Transport<M> for UnixTransport<M> {
+    async fn send(&mut self, to: Replica, msg: M) -> CancelHandler<Acknowledgement> {
+        enqueue(self.peers.get(&to), &msg)
+    }
+
+    async fn recv(&mut self) -> Option<M> {
+        self.rx.recv().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use types::{MAX_FRAME_SIZE, Versions};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn unix_sockets_deliver_in_order() {
+        let dir = std::env::temp_dir().join(format!("transport-unix-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths: FnvHashMap<Replica, PathBuf> = (0..2)
+            .map(|i| (i, dir.join(format!("{}.sock", i))))
+            .collect();
+        let mut a = UnixTransport::<u64>::spawn(paths[&0].clone(), paths.clone(), MAX_FRAME_SIZE).unwrap();
+        let mut b = UnixTransport::<u64>::spawn(paths[&1].clone(), paths.clone(), MAX_FRAME_SIZE).unwrap();
+        let mut acks = Vec::new();
+        for i in 0..10 {
+            acks.push(a.send(1, i).await);
+        }
+        for i in 0..10 {
+            assert_eq!(b.recv().await, Some(i));
+        }
+        for ack in acks {
+            ack.await.unwrap();
+        }
+        b.send(0, 42).await;
+        assert_eq!(a.recv().await, Some(42));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn garbage_frames_close_the_connection() {
+        use futures::{SinkExt, StreamExt};
+        use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+        let dir = std::env::temp_dir().join(format!("transport-garbage-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("0.sock");
+        let mut a = UnixTransport::<(u64, u64)>::spawn(path.clone(), FnvHashMap::default(), 64).unwrap();
+        let before = types::rejected_frames();
+        let hello = || bytes::Bytes::from(bincode::serialize(&Versions::SUPPORTED).unwrap());
+
+        // A hello too short for the versions
+        let mut conn = Framed::new(UnixStream::connect(&path).await.unwrap(), LengthDelimitedCodec::new());
+        conn.send(bytes::Bytes::from_static(&[1, 2, 3])).await.unwrap();
+        assert!(conn.next().await.is_some());
+        assert!(conn.next().await.is_none());
+
+        // A frame too short for the message type
+        let mut conn = Framed::new(UnixStream::connect(&path).await.unwrap(), LengthDelimitedCodec::new());
+        conn.send(hello()).await.unwrap();
+        conn.send(bytes::Bytes::from_static(&[1, 2, 3])).await.unwrap();
+        assert!(conn.next().await.is_some());
+        assert!(conn.next().await.is_none());
+
+        // A frame over the limit
+        let mut conn = Framed::new(UnixStream::connect(&path).await.unwrap(), LengthDelimitedCodec::new());
+        conn.send(hello()).await.unwrap();
+        conn.send(bytes::Bytes::from(vec![0u8; 65])).await.unwrap();
+        assert!(conn.next().await.is_some());
+        assert!(conn.next().await.is_none());
+        assert!(types::rejected_frames() >= before + 3);
+
+        // The replica still takes well formed messages
+        let mut conn = Framed::new(UnixStream::connect(&path).await.unwrap(), LengthDelimitedCodec::new());
+        conn.send(hello()).await.unwrap();
+        conn.send(bincode::serialize(&(4u64, 2u64)).unwrap().into()).await.unwrap();
+        assert_eq!(a.recv().await, Some((4, 2)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn peers_without_a_common_version_are_refused() {
+        use futures::{SinkExt, StreamExt};
+        use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+        let dir = std::env::temp_dir().join(format!("transport-version-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("0.sock");
+        let mut a = UnixTransport::<u64>::spawn(path.clone(), FnvHashMap::default(), MAX_FRAME_SIZE).unwrap();
+
+        // A replica of a future release, which no longer speaks ours
+        let future = Versions { min: Versions::SUPPORTED.max + 1, max: Versions::SUPPORTED.max + 1 };
+        let mut conn = Framed::new(UnixStream::connect(&path).await.unwrap(), LengthDelimitedCodec::new());
+        conn.send(bincode::serialize(&future).unwrap().into()).await.unwrap();
+        let theirs: Versions = bincode::deserialize(&conn.next().await.unwrap().unwrap()).unwrap();
+        assert_eq!(theirs, Versions::SUPPORTED);
+        assert!(future.negotiate(&theirs).is_err());
+        let _ = conn.send(bincode::serialize(&7u64).unwrap().into()).await;
+        assert!(conn.next().await.is_none());
+        let res = tokio::time::timeout(std::time::Duration::from_millis(100), a.recv()).await;
+        assert!(res.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
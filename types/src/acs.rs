@@ -0,0 +1,146 @@
+use bytes::Bytes;
+use crypto::hash::Algorithms;
+use crypto::coin::CoinShare;
+use serde::{Serialize, Deserialize};
+use crate::{Authenticated, Authored, Priority, Prioritized, Schema, WireReady, Replica, Authenticator, DecodeError, MAX_FRAME_SIZE, SealedKeys, Wrapped, decode, decrypt, open, seal};
+
+/// Messages of the asynchronous common subset. Every reliable broadcast and
+/// every binary agreement instance is identified by the replica whose
+/// proposal it is about.
+#[derive(Debug,Serialize,Deserialize,Clone)]
+pub enum ProtMsg{
+    // Reliable broadcast of the sender's own proposal
+    RbcInit(u64),
+    // instance, value
+    RbcEcho(Replica, u64),
+    RbcReady(Replica, u64),
+    // Binary agreement: instance, round, estimate
+    BVal(Replica, usize, bool),
+    Aux(Replica, usize, bool),
+    // Share of the common coin of an instance and round
+    Coin(Replica, usize, Box<CoinShare>),
+    // The sender decided on a value in the instance
+    Term(Replica, bool),
+}
+
+impl Prioritized for ProtMsg{
+    const KINDS: &'static [&'static str] = &["RbcInit", "RbcEcho", "RbcReady", "BVal", "Aux", "Coin", "Term"];
+
+    fn kind(&self) -> &'static str {
+        match self {
+            ProtMsg::RbcInit(_) => "RbcInit",
+            ProtMsg::RbcEcho(..) => "RbcEcho",
+            ProtMsg::RbcReady(..) => "RbcReady",
+            ProtMsg::BVal(..) => "BVal",
+            ProtMsg::Aux(..) => "Aux",
+            ProtMsg::Coin(..) => "Coin",
+            ProtMsg::Term(..) => "Term",
+        }
+    }
+
+    fn priority(&self) -> Priority {
+        match self {
+            ProtMsg::RbcInit(_) => Priority::Proposal,
+            ProtMsg::RbcEcho(..) => Priority::Vote,
+            ProtMsg::RbcReady(..) => Priority::Vote,
+            ProtMsg::BVal(..) => Priority::Vote,
+            ProtMsg::Aux(..) => Priority::Vote,
+            ProtMsg::Coin(..) => Priority::Vote,
+            ProtMsg::Term(..) => Priority::Control,
+        }
+    }
+}
+
+/// A protocol message, serialized once for all of its recipients, with a
+/// MAC for each of them, and encrypted for them if the deployment asks so.
+#[derive(Debug,Serialize,Deserialize,Clone)]
+pub struct WrapperMsg{
+    /// The serialized `ProtMsg`, encrypted if `sealed_keys` is not empty
+    pub payload: Bytes,
+    pub sender:Replica,
+    pub nonce:u64,
+    pub macs:Authenticator,
+    pub sealed_keys:SealedKeys,
+}
+
+impl WrapperMsg{
+    /// `msg` from `sender` to every replica in `keys`, MACed under `algs`
+    /// with the key `sender` shares with that replica, and encrypted if
+    /// `confidential`.
+    pub fn new<'a>(msg:&ProtMsg,sender:Replica, nonce:u64, keys: impl IntoIterator<Item = (Replica, &'a [u8])>, confidential: bool, algs: Algorithms) -> Self{
+        let (payload, macs, sealed_keys) = seal(msg, sender, nonce, keys, confidential, algs);
+        Self{
+            payload,
+            sender,
+            nonce,
+            macs,
+            sealed_keys,
+        }
+    }
+}
+
+impl Wrapped for WrapperMsg{
+    type Msg = ProtMsg;
+
+    fn wrap<'a>(msg:&ProtMsg, sender:Replica, nonce:u64, keys: impl IntoIterator<Item = (Replica, &'a [u8])>, confidential: bool, algs: Algorithms) -> Self{
+        Self::new(msg, sender, nonce, keys, confidential, algs)
+    }
+
+    fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    fn is_for(&self, receiver: Replica) -> bool {
+        self.macs.iter().any(|(r, _)| *r == receiver)
+    }
+
+    fn protmsg(&self) -> Result<ProtMsg, DecodeError> {
+        decode(&self.payload, MAX_FRAME_SIZE)
+    }
+}
+
+impl Authored for WrapperMsg{
+    fn sender(&self) -> Replica {
+        self.sender
+    }
+}
+
+impl Authenticated for WrapperMsg{
+    fn verify(&self, receiver: Replica, sk: &[u8], algs: Algorithms) -> bool {
+        open(&self.payload, self.sender, self.nonce, &self.macs, &self.sealed_keys, (receiver, sk), algs)
+    }
+
+    fn decrypt(&mut self, receiver: Replica, sk: &[u8], confidential: bool) -> bool {
+        match decrypt(&self.payload, self.sender, self.nonce, &self.sealed_keys, receiver, sk, confidential) {
+            Some(payload) => {
+                self.payload = payload;
+                self.sealed_keys = SealedKeys::new();
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+// Bump whenever `WrapperMsg` or `ProtMsg` change
+impl Schema for WrapperMsg{
+    const SCHEMA: u16 = 4;
+}
+
+impl WireReady for WrapperMsg{
+    fn from_bytes_bounded(bytes: &[u8], max: usize) -> Result<Self, DecodeError> {
+        let c:Self = decode(bytes, max)?;
+        Ok(c.init())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize client message");
+        bytes
+    }
+
+    fn init(self) -> Self {
+        match self {
+            _x=>_x
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use std::{collections::{BTreeSet, HashMap}, convert::TryInto, time::{SystemTime, UNIX_EPOCH}};
+
+use bytes::Bytes;
+use crypto::{aead, hash::{Algorithms, TaggedHash, TaggedMac}};
+use serde::Serialize;
+use zeroize::Zeroizing;
+
+use crate::{DecodeError, Replica};
+
+/// How far behind the newest nonce of a peer a message may arrive, to allow
+/// for reordering, before it is rejected as stale.
+pub const REPLAY_WINDOW: u64 = 1 << 12;
+
+/// The MAC of every recipient of a message, tagged with its algorithm.
+pub type Authenticator = Vec<(Replica, TaggedMac)>;
+
+/// The bytes a MAC covers: the digest of the serialized protocol message,
+/// which carries the instance and round it belongs to, bound to the sender,
+/// the intended receiver, the sender's nonce and the content key sealed for
+/// the receiver, empty if the payload is in the clear. The digest carries
+/// its hash function, so that replicas hashing differently fail to verify.
+pub fn mac_bytes(digest: &TaggedHash, sender: Replica, receiver: Replica, nonce: u64, sealed_key: &[u8]) -> Vec<u8> {
+    bincode::serialize(&(sender, receiver, nonce, digest, sealed_key))
+        .expect("Failed to serialize MAC input")
+}
+
+/// The content key of an encrypted payload, sealed for every recipient
+/// under the key the sender shares with it. Empty if the payload is in the
+/// clear.
+pub type SealedKeys = Vec<(Replica, Vec<u8>)>;
+
+/// The content key sealed for `receiver`, empty if there is none.
+fn sealed_key(sealed_keys: &SealedKeys, receiver: Replica) -> &[u8] {
+    sealed_keys.iter()
+        .find(|(r, _)| *r == receiver)
+        .map_or(&[][..], |(_, sealed)| sealed.as_slice())
+}
+
+/// What the key of a recipient is derived into to seal content keys with.
+const CONTENT_KEY_LABEL: &[u8] = b"content key";
+
+/// The nonce of the content keys sealed by `sender` with its `nonce`. The
+/// two replicas sharing a key never use the same one, as they put their own
+/// ids in it.
+fn content_key_nonce(sender: Replica, nonce: u64) -> [u8; aead::NONCE_SIZE] {
+    let mut n = [0u8; aead::NONCE_SIZE];
+    n[..4].copy_from_slice(&(sender as u32).to_le_bytes());
+    n[4..].copy_from_slice(&nonce.to_le_bytes());
+    n
+}
+
+fn content_key_aad(sender: Replica, receiver: Replica, nonce: u64) -> Vec<u8> {
+    bincode::serialize(&(sender, receiver, nonce)).expect("Failed to serialize content key header")
+}
+
+/// Serialize `protmsg` once and MAC it for every replica in `keys`, under
+/// the key `sender` shares with that replica. Only the digest of the
+/// payload goes into each MAC, so a broadcast hashes the payload once.
+///
+/// If `confidential`, the payload is encrypted once under a fresh content
+/// key, which is sealed for every recipient, and the MACs cover the
+/// ciphertext and the content key sealed for their recipient.
+///
+/// The digest and the MACs are computed with `algs`.
+pub fn seal<'a, M: Serialize>(
+    protmsg: &M,
+    sender: Replica,
+    nonce: u64,
+    keys: impl IntoIterator<Item = (Replica, &'a [u8])>,
+    confidential: bool,
+    algs: Algorithms,
+) -> (Bytes, Authenticator, SealedKeys) {
+    let mut payload = bincode::serialize(protmsg).expect("Failed to serialize protocol message");
+    let keys: Vec<(Replica, &[u8])> = keys.into_iter().collect();
+    let mut sealed_keys = SealedKeys::new();
+    if confidential {
+        let content_key = aead::random_key();
+        let aad = bincode::serialize(&(sender, nonce)).expect("Failed to serialize payload header");
+        // Every content key seals a single payload
+        let plaintext = Zeroizing::new(payload);
+        payload = aead::seal(&content_key, &[0u8; aead::NONCE_SIZE], &aad, &plaintext);
+        sealed_keys = keys.iter()
+            .map(|(receiver, sk)| {
+                let key = aead::derive_key(sk, CONTENT_KEY_LABEL);
+                let aad = content_key_aad(sender, *receiver, nonce);
+                (*receiver, aead::seal(&key, &content_key_nonce(sender, nonce), &aad, &content_key[..]))
+            })
+            .collect();
+    }
+    let digest = algs.hash.digest(&payload);
+    let macs = keys.into_iter()
+        .map(|(receiver, sk)| {
+            let bytes = mac_bytes(&digest, sender, receiver, nonce, sealed_key(&sealed_keys, receiver));
+            (receiver, algs.mac.tagged(&bytes, sk))
+        })
+        .collect();
+    (payload.into(), macs, sealed_keys)
+}
+
+/// Decrypt a payload from `seal` for `receiver`, with the key it shares
+/// with `sender`. Payloads in the clear are returned as they are, unless
+/// the receiver is `confidential`. None if the payload was not encrypted
+/// for `receiver`, or does not decrypt.
+pub fn decrypt(payload: &Bytes, sender: Replica, nonce: u64, sealed_keys: &SealedKeys, receiver: Replica, sk: &[u8], confidential: bool) -> Option<Bytes> {
+    if sealed_keys.is_empty() {
+        return (!confidential).then(|| payload.clone());
+    }
+    let (_, sealed) = sealed_keys.iter().find(|(r, _)| *r == receiver)?;
+    let key = aead::derive_key(sk, CONTENT_KEY_LABEL);
+    let content_key = Zeroizing::new(
+        aead::open(&key, &content_key_nonce(sender, nonce), &content_key_aad(sender, receiver, nonce), sealed).ok()?
+    );
+    let content_key: &[u8; aead::KEY_SIZE] = content_key.as_slice().try_into().ok()?;
+    let aad = bincode::serialize(&(sender, nonce)).expect("Failed to serialize payload header");
+    aead::open(content_key, &[0u8; aead::NONCE_SIZE], &aad, payload).ok().map(Bytes::from)
+}
+
+/// Check the MAC the receiver got in `macs` with the key `sk` it shares
+/// with `sender`, under `algs`. False if the message was not meant for the
+/// receiver, or was sealed with other algorithms.
+pub fn open(payload: &[u8], sender: Replica, nonce: u64, macs: &Authenticator, sealed_keys: &SealedKeys, (receiver, sk): (Replica, &[u8]), algs: Algorithms) -> bool {
+    match macs.iter().find(|(r, _)| *r == receiver) {
+        Some((_, mac)) => {
+            let bytes = mac_bytes(&algs.hash.digest(payload), sender, receiver, nonce, sealed_key(sealed_keys, receiver));
+            algs.mac.verify_tagged(&bytes, sk, mac)
+        },
+        None => false,
+    }
+}
+
+/// A message that names the replica it comes from. A transport that knows
+/// who is at the other end of a connection drops the messages that name
+/// anyone else.
+pub trait Authored {
+    fn sender(&self) -> Replica;
+}
+
+/// A message that carries a MAC for each of its recipients.
+pub trait Authenticated: Authored + Send + 'static {
+    /// Check the MAC of `receiver` with the key it shares with the sender,
+    /// under the algorithms of the deployment.
+    fn verify(&self, receiver: Replica, sk: &[u8], algs: Algorithms) -> bool;
+
+    /// Decrypt the payload for `receiver` in place, with the key whose MAC
+    /// verified. False if it does not decrypt, or if it is in the clear and
+    /// `receiver` is `confidential`. Schemes that encrypt override this.
+    fn decrypt(&mut self, _receiver: Replica, _sk: &[u8], confidential: bool) -> bool {
+        !confidential
+    }
+
+    /// Check the MACs of `receiver` on several messages, each with the key
+    /// its sender shares with `receiver`. Schemes that are faster in batches
+    /// override this.
+    fn verify_batch(receiver: Replica, batch: &[(&Self, &[u8])], algs: Algorithms) -> Vec<bool> where Self: Sized {
+        batch.iter().map(|(msg, sk)| msg.verify(receiver, sk, algs)).collect()
+    }
+}
+
+/// A protocol message serialized once for all of its recipients by
+/// [`seal`], as every protocol wraps its messages.
+pub trait Wrapped: Authenticated + Sized {
+    /// The protocol message inside
+    type Msg;
+
+    /// `msg` from `sender` to every replica in `keys`, MACed under `algs`
+    /// with the key `sender` shares with that replica, and encrypted if
+    /// `confidential`.
+    fn wrap<'a>(msg: &Self::Msg, sender: Replica, nonce: u64, keys: impl IntoIterator<Item = (Replica, &'a [u8])>, confidential: bool, algs: Algorithms) -> Self;
+
+    fn nonce(&self) -> u64;
+
+    /// Whether the message carries a MAC for `receiver`.
+    fn is_for(&self, receiver: Replica) -> bool;
+
+    /// Decode the protocol message. Only trust it once `verify` passed.
+    fn protmsg(&self) -> Result<Self::Msg, DecodeError>;
+}
+
+/// The nonces a replica puts on its messages, and the ones it has seen from
+/// every peer.
+#[derive(Debug)]
+pub struct Nonces {
+    last: u64,
+    seen: HashMap<Replica, BTreeSet<u64>>,
+}
+
+impl Default for Nonces {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Nonces {
+    /// Start counting from the current time, so that a restarted replica
+    /// does not reuse the nonces of its previous run.
+    pub fn new() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("the clock is before the epoch")
+            .as_nanos() as u64;
+        Self { last: now, seen: HashMap::default() }
+    }
+
+    /// A fresh nonce for the next outgoing message.
+    pub fn issue(&mut self) -> u64 {
+        self.last += 1;
+        self.last
+    }
+
+    /// Record `nonce` from `peer`. False if it was seen before, or is more
+    /// than `REPLAY_WINDOW` behind the newest one.
+    pub fn accept(&mut self, peer: Replica, nonce: u64) -> bool {
+        let seen = self.seen.entry(peer).or_default();
+        let newest = seen.iter().next_back().copied().unwrap_or(0);
+        if nonce.saturating_add(REPLAY_WINDOW) <= newest || !seen.insert(nonce) {
+            return false;
+        }
+        // Forget the nonces that fell out of the window
+        if let Some(oldest) = newest.max(nonce).checked_sub(REPLAY_WINDOW) {
+            *seen = seen.split_off(&(oldest + 1));
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::hash::{HashAlgorithm, MacAlgorithm};
+
+    #[test]
+    fn every_recipient_checks_its_own_mac() {
+        let algs = Algorithms::default();
+        let keys = [(1, vec![1u8; 32]), (2, vec![2u8; 32])];
+        let (payload, macs, sealed_keys) = seal(&"hello", 0, 7, keys.iter().map(|(r, k)| (*r, k.as_slice())), false, algs);
+        assert!(sealed_keys.is_empty());
+        assert_eq!(macs.len(), 2);
+        assert!(open(&payload, 0, 7, &macs, &sealed_keys, (1, &keys[0].1), algs));
+        assert!(open(&payload, 0, 7, &macs, &sealed_keys, (2, &keys[1].1), algs));
+        // Another receiver, key, nonce or payload
+        assert!(!open(&payload, 0, 7, &macs, &sealed_keys, (3, &keys[0].1), algs));
+        assert!(!open(&payload, 0, 7, &macs, &sealed_keys, (1, &keys[1].1), algs));
+        assert!(!open(&payload, 0, 8, &macs, &sealed_keys, (1, &keys[0].1), algs));
+        assert!(!open(b"hellp", 0, 7, &macs, &sealed_keys, (1, &keys[0].1), algs));
+        // Replicas hashing or MACing otherwise
+        let hash = Algorithms { hash: HashAlgorithm::Blake3, ..algs };
+        let mac = Algorithms { mac: MacAlgorithm::Blake3, ..algs };
+        assert!(!open(&payload, 0, 7, &macs, &sealed_keys, (1, &keys[0].1), hash));
+        assert!(!open(&payload, 0, 7, &macs, &sealed_keys, (1, &keys[0].1), mac));
+        // A receiver that wants confidentiality refuses it
+        assert!(decrypt(&payload, 0, 7, &sealed_keys, 1, &keys[0].1, true).is_none());
+        assert_eq!(decrypt(&payload, 0, 7, &sealed_keys, 1, &keys[0].1, false).unwrap(), payload);
+    }
+
+    #[test]
+    fn only_recipients_decrypt_confidential_payloads() {
+        let algs = Algorithms::default();
+        let keys = [(1, vec![1u8; 32]), (2, vec![2u8; 32])];
+        let (payload, macs, sealed_keys) = seal(&"hello", 0, 7, keys.iter().map(|(r, k)| (*r, k.as_slice())), true, algs);
+        assert_eq!(sealed_keys.len(), 2);
+        assert!(!payload.windows(5).any(|w| w == b"hello"));
+        // The MACs cover the ciphertext and the sealed content keys
+        assert!(open(&payload, 0, 7, &macs, &sealed_keys, (1, &keys[0].1), algs));
+        let mut swapped = sealed_keys.clone();
+        swapped[0].1[0] ^= 1;
+        assert!(!open(&payload, 0, 7, &macs, &swapped, (1, &keys[0].1), algs));
+        assert!(!open(&payload, 0, 7, &macs, &SealedKeys::new(), (1, &keys[0].1), algs));
+        let plaintext = bincode::serialize(&"hello").unwrap();
+        for (receiver, sk) in &keys {
+            assert_eq!(decrypt(&payload, 0, 7, &sealed_keys, *receiver, sk, true).unwrap(), plaintext);
+        }
+        // Another receiver, key or nonce
+        assert!(decrypt(&payload, 0, 7, &sealed_keys, 3, &keys[0].1, true).is_none());
+        assert!(decrypt(&payload, 0, 7, &sealed_keys, 1, &keys[1].1, true).is_none());
+        assert!(decrypt(&payload, 0, 8, &sealed_keys, 1, &keys[0].1, true).is_none());
+    }
+
+    #[test]
+    fn replays_and_stale_nonces_are_rejected() {
+        let mut nonces = Nonces::new();
+        assert!(nonces.accept(1, 100_000));
+        assert!(!nonces.accept(1, 100_000));
+        // Reordered within the window
+        assert!(nonces.accept(1, 99_999));
+        assert!(nonces.accept(1, 100_002));
+        assert!(nonces.accept(1, 100_001));
+        // Too old
+        assert!(!nonces.accept(1, 100_002 - REPLAY_WINDOW));
+        // Peers are independent
+        assert!(nonces.accept(2, 100_000));
+    }
+}
@@ -0,0 +1,141 @@
+use bytes::Bytes;
+use crypto::hash::{Algorithms, TaggedHash};
+use crypto::merkle::MerkleProof;
+use serde::{Serialize, Deserialize};
+use crate::{Authenticated, Authored, Priority, Prioritized, Schema, WireReady, Replica, Authenticator, DecodeError, MAX_FRAME_SIZE, SealedKeys, Wrapped, decode, decrypt, open, seal};
+
+/// One erasure coded fragment of a broadcast payload, with the proof of its
+/// inclusion in the Merkle tree that commits to all fragments. The index of
+/// the fragment is the index of its leaf.
+#[derive(Debug,Serialize,Deserialize,Clone)]
+pub struct Fragment{
+    pub shard: Vec<u8>,
+    pub proof: MerkleProof,
+}
+
+/// Messages of the erasure coded reliable broadcast of Cachin and Tessaro.
+/// Every instance is identified by its broadcaster and the Merkle root of
+/// the fragments.
+#[derive(Debug,Serialize,Deserialize,Clone)]
+pub enum ProtMsg{
+    // The broadcaster hands every replica its own fragment
+    Send(TaggedHash, Fragment),
+    // broadcaster, root, the sender's own fragment
+    Echo(Replica, TaggedHash, Fragment),
+    // broadcaster, root
+    Ready(Replica, TaggedHash),
+}
+
+impl Prioritized for ProtMsg{
+    const KINDS: &'static [&'static str] = &["Send", "Echo", "Ready"];
+
+    fn kind(&self) -> &'static str {
+        match self {
+            ProtMsg::Send(..) => "Send",
+            ProtMsg::Echo(..) => "Echo",
+            ProtMsg::Ready(..) => "Ready",
+        }
+    }
+
+    fn priority(&self) -> Priority {
+        match self {
+            ProtMsg::Send(..) => Priority::Proposal,
+            ProtMsg::Echo(..) => Priority::Proposal,
+            ProtMsg::Ready(..) => Priority::Vote,
+        }
+    }
+}
+
+/// A protocol message, serialized once for all of its recipients, with a
+/// MAC for each of them, and encrypted for them if the deployment asks so.
+#[derive(Debug,Serialize,Deserialize,Clone)]
+pub struct WrapperMsg{
+    /// The serialized `ProtMsg`, encrypted if `sealed_keys` is not empty
+    pub payload: Bytes,
+    pub sender:Replica,
+    pub nonce:u64,
+    pub macs:Authenticator,
+    pub sealed_keys:SealedKeys,
+}
+
+impl WrapperMsg{
+    /// `msg` from `sender` to every replica in `keys`, MACed under `algs`
+    /// with the key `sender` shares with that replica, and encrypted if
+    /// `confidential`.
+    pub fn new<'a>(msg:&ProtMsg,sender:Replica, nonce:u64, keys: impl IntoIterator<Item = (Replica, &'a [u8])>, confidential: bool, algs: Algorithms) -> Self{
+        let (payload, macs, sealed_keys) = seal(msg, sender, nonce, keys, confidential, algs);
+        Self{
+            payload,
+            sender,
+            nonce,
+            macs,
+            sealed_keys,
+        }
+    }
+}
+
+impl Wrapped for WrapperMsg{
+    type Msg = ProtMsg;
+
+    fn wrap<'a>(msg:&ProtMsg, sender:Replica, nonce:u64, keys: impl IntoIterator<Item = (Replica, &'a [u8])>, confidential: bool, algs: Algorithms) -> Self{
+        Self::new(msg, sender, nonce, keys, confidential, algs)
+    }
+
+    fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    fn is_for(&self, receiver: Replica) -> bool {
+        self.macs.iter().any(|(r, _)| *r == receiver)
+    }
+
+    fn protmsg(&self) -> Result<ProtMsg, DecodeError> {
+        decode(&self.payload, MAX_FRAME_SIZE)
+    }
+}
+
+impl Authored for WrapperMsg{
+    fn sender(&self) -> Replica {
+        self.sender
+    }
+}
+
+impl Authenticated for WrapperMsg{
+    fn verify(&self, receiver: Replica, sk: &[u8], algs: Algorithms) -> bool {
+        open(&self.payload, self.sender, self.nonce, &self.macs, &self.sealed_keys, (receiver, sk), algs)
+    }
+
+    fn decrypt(&mut self, receiver: Replica, sk: &[u8], confidential: bool) -> bool {
+        match decrypt(&self.payload, self.sender, self.nonce, &self.sealed_keys, receiver, sk, confidential) {
+            Some(payload) => {
+                self.payload = payload;
+                self.sealed_keys = SealedKeys::new();
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+// Bump whenever `WrapperMsg` or `ProtMsg` change
+impl Schema for WrapperMsg{
+    const SCHEMA: u16 = 5;
+}
+
+impl WireReady for WrapperMsg{
+    fn from_bytes_bounded(bytes: &[u8], max: usize) -> Result<Self, DecodeError> {
+        let c:Self = decode(bytes, max)?;
+        Ok(c.init())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let bytes = bincode::serialize(self).expect("Failed to serialize client message");
+        bytes
+    }
+
+    fn init(self) -> Self {
+        match self {
+            _x=>_x
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+
+use crate::{acs, avid, dkg, Schema, SyncMsg, WireReady, WrapperMsg, DecodeError, MIN_WIRE_VERSION, WIRE_VERSION, decode};
+
+/// The consensus protocols a replica can run.
+#[derive(Debug,Serialize,Deserialize,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum Protocol{
+    Pbft,
+    Acs,
+    Avid,
+    Dkg,
+}
+
+/// What the messages arriving at a node's single listener are for.
+#[derive(Debug,Serialize,Deserialize,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum Channel{
+    // Between the replicas and the syncer
+    Sync,
+    // Between the replicas running a protocol
+    Consensus(Protocol),
+}
+
+impl Channel{
+    /// The schema id of the messages on this channel.
+    pub fn schema(&self) -> u16 {
+        match self {
+            Channel::Sync => SyncMsg::SCHEMA,
+            Channel::Consensus(Protocol::Pbft) => WrapperMsg::SCHEMA,
+            Channel::Consensus(Protocol::Acs) => acs::WrapperMsg::SCHEMA,
+            Channel::Consensus(Protocol::Avid) => avid::WrapperMsg::SCHEMA,
+            Channel::Consensus(Protocol::Dkg) => dkg::WrapperMsg::SCHEMA,
+        }
+    }
+}
+
+/// A message of some channel. The payload is the serialized message, only
+/// decoded by the subsystem the channel belongs to.
+#[derive(Debug,Serialize,Deserialize,Clone)]
+pub struct Envelope{
+    // Leads every frame, whatever the version, so that it can always be read
+    pub version: u16,
+    pub schema: u16,
+    pub channel: Channel,
+    pub payload: Bytes,
+}
+
+impl Envelope{
+    pub fn new<M: Serialize>(channel: Channel, msg: &M) -> Self{
+        let payload = bincode::serialize(msg).expect("Failed to serialize message");
+        Self{
+            version: WIRE_VERSION,
+            schema: channel.schema(),
+            channel,
+            payload: payload.into(),
+        }
+    }
+
+    /// Whether this build can read the payload: the envelope must be of a
+    /// supported wire version, and of the schema this build has for its
+    /// channel.
+    pub fn check(&self) -> Result<(), DecodeError> {
+        if !(MIN_WIRE_VERSION..=WIRE_VERSION).contains(&self.version) {
+            return Err(DecodeError::Version(self.version));
+        }
+        if self.schema != self.channel.schema() {
+            return Err(DecodeError::Schema(self.channel, self.schema, self.channel.schema()));
+        }
+        Ok(())
+    }
+}
+
+impl WireReady for Envelope{
+    fn from_bytes_bounded(bytes: &[u8], max: usize) -> Result<Self, DecodeError> {
+        let c:Self = decode(bytes, max)?;
+        Ok(c.init())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("Failed to serialize envelope")
+    }
+
+    fn init(self) -> Self {
+        self
+    }
+}
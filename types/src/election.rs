@@ -0,0 +1,91 @@
+use crypto::{ed25519, vrf};
+use fnv::FnvHashMap as HashMap;
+use serde::{Serialize, Deserialize};
+
+use crate::Replica;
+
+/// A replica's draw for the leadership of a view: the output of its VRF on
+/// the view and a seed, with the proof of that output. Nobody can tell the
+/// draw of a replica before it publishes it, so nobody knows the leader of
+/// a view ahead of time.
+#[derive(Debug,Serialize,Deserialize,Clone,PartialEq,Eq)]
+pub struct Ticket{
+    pub replica: Replica,
+    pub view: u64,
+    pub output: Vec<u8>,
+    pub proof: Vec<u8>,
+}
+
+/// The input of the VRF for `view`. The seed should not be known long
+/// before the view, e.g. the output of the previous leader or a common coin.
+fn alpha(view: u64, seed: &[u8]) -> Vec<u8> {
+    bincode::serialize(&(view, seed)).expect("Failed to serialize VRF input")
+}
+
+impl Ticket{
+    /// Draw the ticket of `replica`, whose identity is `keypair`, for `view`.
+    pub fn draw(replica: Replica, keypair: &ed25519::Keypair, view: u64, seed: &[u8]) -> Self {
+        let (output, proof) = vrf::prove(keypair, &alpha(view, seed));
+        Self{ replica, view, output: output.to_vec(), proof: proof.to_vec() }
+    }
+
+    /// Whether the ticket was drawn by the holder of `pk` for its view.
+    pub fn verify(&self, pk: &ed25519::PublicKey, seed: &[u8]) -> bool {
+        vrf::verify(pk, &alpha(self.view, seed), &self.proof)
+            .is_some_and(|output| output[..] == self.output[..])
+    }
+}
+
+/// The leader of `view`: the replica whose ticket has the lowest output.
+/// Tickets of other views, of replicas not in `pk_map`, the Ed25519
+/// identities of the replicas, or that do not verify are ignored, so that
+/// every replica that sees the same tickets elects the same leader.
+pub fn elect<'a>(
+    view: u64,
+    seed: &[u8],
+    tickets: impl IntoIterator<Item = &'a Ticket>,
+    pk_map: &HashMap<Replica, Vec<u8>>,
+) -> Option<Replica> {
+    tickets.into_iter()
+        .filter(|ticket| ticket.view == view)
+        .filter(|ticket| pk_map.get(&ticket.replica)
+            .and_then(|pk| ed25519::PublicKey::decode(pk).ok())
+            .is_some_and(|pk| ticket.verify(&pk, seed)))
+        .min_by(|a, b| a.output.cmp(&b.output).then(a.replica.cmp(&b.replica)))
+        .map(|ticket| ticket.replica)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_lowest_valid_ticket_wins() {
+        let keypairs: Vec<ed25519::Keypair> = (0..4).map(|_| ed25519::Keypair::generate()).collect();
+        let pk_map: HashMap<Replica, Vec<u8>> = keypairs.iter().enumerate()
+            .map(|(i, kp)| (i, kp.public().encode().to_vec()))
+            .collect();
+        let seed = b"seed";
+        let mut tickets: Vec<Ticket> = keypairs.iter().enumerate()
+            .map(|(i, kp)| Ticket::draw(i, kp, 7, seed))
+            .collect();
+        let lowest = tickets.iter().min_by(|a, b| a.output.cmp(&b.output)).unwrap().replica;
+        assert_eq!(elect(7, seed, &tickets, &pk_map), Some(lowest));
+        // The order tickets arrive in does not matter
+        tickets.reverse();
+        assert_eq!(elect(7, seed, &tickets, &pk_map), Some(lowest));
+
+        // A forged low output, a ticket of another view and one of another
+        // seed are ignored
+        let winner = tickets.iter_mut().find(|t| t.replica == lowest).unwrap();
+        winner.output = vec![0u8; vrf::OUTPUT_SIZE];
+        let runner_up = elect(7, seed, &tickets, &pk_map).unwrap();
+        assert_ne!(runner_up, lowest);
+        let stale = Ticket::draw(lowest, &keypairs[lowest], 6, seed);
+        let reseeded = Ticket::draw(lowest, &keypairs[lowest], 7, b"other");
+        tickets.push(stale);
+        tickets.push(reseeded);
+        assert_eq!(elect(7, seed, &tickets, &pk_map), Some(runner_up));
+        assert_eq!(elect(8, seed, &tickets, &pk_map), None);
+    }
+}
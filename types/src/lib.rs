@@ -7,4 +7,29 @@ pub use msg::*;
 mod traits;
 pub use traits::*;
 
+mod wire;
+pub use wire::*;
+
+mod priority;
+pub use priority::*;
+
+mod version;
+pub use version::*;
+
+mod channel;
+pub use channel::*;
+
+mod auth;
+pub use auth::*;
+
+mod reputation;
+pub use reputation::*;
+
+mod election;
+pub use election::*;
+
+pub mod acs;
+pub mod avid;
+pub mod dkg;
+
 pub type View = usize;
\ No newline at end of file
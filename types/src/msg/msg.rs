@@ -1,7 +1,7 @@
-use crypto::hash::{Hash};
-use crypto::hash::{do_mac};
+use bytes::Bytes;
+use crypto::hash::{Algorithms, TaggedHash};
 use serde::{Serialize, Deserialize};
-use crate::{WireReady, Replica};
+use crate::{Ticket, Authenticated, Authored, Priority, Prioritized, Schema, WireReady, Replica, Authenticator, DecodeError, MAX_FRAME_SIZE, SealedKeys, Wrapped, decode, decrypt, open, seal};
 
 #[derive(Debug,Serialize,Deserialize,Clone)]
 pub struct Msg {
@@ -16,34 +16,129 @@ pub enum ProtMsg{
     // Example type is a ping message, which takes a Message and the sender replica
     Init(u64),
     Prepare(Msg),
-    Echo(Msg),
+    // Echoes only carry the digest of the proposed vector
+    Echo(TaggedHash),
+    // Ask for the vector with the given digest if it is missing locally
+    Request(TaggedHash),
+    Response(Msg),
+    // A draw for the leadership, relayed while it is the lowest one known
+    Ticket(Ticket),
+    // The lowest ticket a replica knew once the tickets had their time
+    ElectEcho(Ticket),
+    // The leader a replica is ready to adopt
+    ElectReady(Replica),
 }
 
+impl Prioritized for ProtMsg{
+    const KINDS: &'static [&'static str] = &["Init", "Prepare", "Echo", "Request", "Response", "Ticket", "ElectEcho", "ElectReady"];
+
+    fn kind(&self) -> &'static str {
+        match self {
+            ProtMsg::Init(_) => "Init",
+            ProtMsg::Prepare(_) => "Prepare",
+            ProtMsg::Echo(_) => "Echo",
+            ProtMsg::Request(_) => "Request",
+            ProtMsg::Response(_) => "Response",
+            ProtMsg::Ticket(_) => "Ticket",
+            ProtMsg::ElectEcho(_) => "ElectEcho",
+            ProtMsg::ElectReady(_) => "ElectReady",
+        }
+    }
+
+    fn priority(&self) -> Priority {
+        match self {
+            ProtMsg::Init(_) => Priority::Proposal,
+            ProtMsg::Prepare(_) => Priority::Proposal,
+            ProtMsg::Echo(_) => Priority::Vote,
+            ProtMsg::Request(_) => Priority::Bulk,
+            ProtMsg::Response(_) => Priority::Bulk,
+            ProtMsg::Ticket(_) => Priority::Vote,
+            ProtMsg::ElectEcho(_) => Priority::Vote,
+            ProtMsg::ElectReady(_) => Priority::Vote,
+        }
+    }
+}
+
+/// A protocol message, serialized once for all of its recipients, with a
+/// MAC for each of them, and encrypted for them if the deployment asks so.
 #[derive(Debug,Serialize,Deserialize,Clone)]
 pub struct WrapperMsg{
-    pub protmsg: ProtMsg,
+    /// The serialized `ProtMsg`, encrypted if `sealed_keys` is not empty
+    pub payload: Bytes,
     pub sender:Replica,
-    pub mac:Hash,
+    pub nonce:u64,
+    pub macs:Authenticator,
+    pub sealed_keys:SealedKeys,
 }
 
 impl WrapperMsg{
-    pub fn new(msg:ProtMsg,sender:Replica, sk: &[u8]) -> Self{
-        let new_msg = msg.clone();
-        let bytes = bincode::serialize(&new_msg).expect("Failed to serialize protocol message");
-        let mac = do_mac(&bytes.as_slice(), sk);
+    /// `msg` from `sender` to every replica in `keys`, MACed under `algs`
+    /// with the key `sender` shares with that replica, and encrypted if
+    /// `confidential`.
+    pub fn new<'a>(msg:&ProtMsg,sender:Replica, nonce:u64, keys: impl IntoIterator<Item = (Replica, &'a [u8])>, confidential: bool, algs: Algorithms) -> Self{
+        let (payload, macs, sealed_keys) = seal(msg, sender, nonce, keys, confidential, algs);
         Self{
-            protmsg: new_msg,
-            mac: mac,
-            sender:sender
+            payload,
+            sender,
+            nonce,
+            macs,
+            sealed_keys,
         }
     }
 }
 
+impl Wrapped for WrapperMsg{
+    type Msg = ProtMsg;
+
+    fn wrap<'a>(msg:&ProtMsg, sender:Replica, nonce:u64, keys: impl IntoIterator<Item = (Replica, &'a [u8])>, confidential: bool, algs: Algorithms) -> Self{
+        Self::new(msg, sender, nonce, keys, confidential, algs)
+    }
+
+    fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    fn is_for(&self, receiver: Replica) -> bool {
+        self.macs.iter().any(|(r, _)| *r == receiver)
+    }
+
+    fn protmsg(&self) -> Result<ProtMsg, DecodeError> {
+        decode(&self.payload, MAX_FRAME_SIZE)
+    }
+}
+
+impl Authored for WrapperMsg{
+    fn sender(&self) -> Replica {
+        self.sender
+    }
+}
+
+impl Authenticated for WrapperMsg{
+    fn verify(&self, receiver: Replica, sk: &[u8], algs: Algorithms) -> bool {
+        open(&self.payload, self.sender, self.nonce, &self.macs, &self.sealed_keys, (receiver, sk), algs)
+    }
+
+    fn decrypt(&mut self, receiver: Replica, sk: &[u8], confidential: bool) -> bool {
+        match decrypt(&self.payload, self.sender, self.nonce, &self.sealed_keys, receiver, sk, confidential) {
+            Some(payload) => {
+                self.payload = payload;
+                self.sealed_keys = SealedKeys::new();
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+// Bump whenever `WrapperMsg` or `ProtMsg` change
+impl Schema for WrapperMsg{
+    const SCHEMA: u16 = 7;
+}
+
 impl WireReady for WrapperMsg{
-    fn from_bytes(bytes: &[u8]) -> Self {
-        let c:Self = bincode::deserialize(bytes)
-            .expect("failed to decode the protocol message");
-        c.init()
+    fn from_bytes_bounded(bytes: &[u8], max: usize) -> Result<Self, DecodeError> {
+        let c:Self = decode(bytes, max)?;
+        Ok(c.init())
     }
 
     fn to_bytes(&self) -> Vec<u8> {
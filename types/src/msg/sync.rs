@@ -1,6 +1,6 @@
 use serde::{Serialize, Deserialize};
 
-use crate::{WireReady, Replica};
+use crate::{Authored, Schema, WireReady, Replica, DecodeError, decode};
 
 #[derive(Debug,Serialize,Deserialize,Clone)]
 pub enum SyncState{
@@ -19,11 +19,21 @@ pub struct SyncMsg{
     pub value: String
 }
 
+impl Authored for SyncMsg{
+    fn sender(&self) -> Replica {
+        self.sender
+    }
+}
+
+// Bump whenever `SyncMsg` or `SyncState` change
+impl Schema for SyncMsg{
+    const SCHEMA: u16 = 1;
+}
+
 impl WireReady for SyncMsg{
-    fn from_bytes(bytes: &[u8]) -> Self {
-        let c:Self = bincode::deserialize(bytes)
-            .expect("failed to decode the protocol message");
-        c.init()
+    fn from_bytes_bounded(bytes: &[u8], max: usize) -> Result<Self, DecodeError> {
+        let c:Self = decode(bytes, max)?;
+        Ok(c.init())
     }
 
     fn to_bytes(&self) -> Vec<u8> {
@@ -0,0 +1,27 @@
+use serde::{Serialize, Deserialize};
+
+/// How urgently a received message is handled. Messages of a higher class
+/// overtake the ones of lower classes that are waiting.
+#[derive(Debug,Serialize,Deserialize,Clone,Copy,PartialEq,Eq,PartialOrd,Ord,Hash)]
+pub enum Priority{
+    // Bulk transfers, e.g. answers to requests for missing state
+    Bulk,
+    // Proposals and the payloads that travel with them
+    Proposal,
+    // Votes that move an instance forward
+    Vote,
+    // Messages that end or repair an instance, e.g. terminations
+    Control,
+}
+
+/// A protocol message with a default priority. Every kind of message has a
+/// name, the one its class can be configured with.
+pub trait Prioritized {
+    /// The names of all kinds of messages
+    const KINDS: &'static [&'static str];
+
+    /// The name of the kind of this message
+    fn kind(&self) -> &'static str;
+
+    fn priority(&self) -> Priority;
+}
@@ -0,0 +1,169 @@
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+use crate::Replica;
+
+/// Misbehaviour a peer is blamed for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offence {
+    /// A message whose MAC does not verify
+    InvalidMac,
+    /// A message that decodes but whose contents are invalid, e.g. a share
+    /// that does not match its commitment
+    Malformed,
+    /// A message the protocol did not expect, e.g. a replay or an answer to
+    /// a request that was never made
+    Unexpected,
+    /// A message over the peer's rate limit
+    Flood,
+}
+
+impl Offence {
+    fn weight(&self) -> u64 {
+        match self {
+            Offence::InvalidMac | Offence::Malformed => 10,
+            Offence::Unexpected => 2,
+            Offence::Flood => 1,
+        }
+    }
+}
+
+/// Score at which a peer is banned.
+pub const BAN_THRESHOLD: u64 = 100;
+/// How long a ban lasts. The peer starts over with a clean score after it.
+pub const BAN_PERIOD: Duration = Duration::from_secs(60);
+/// Messages a peer may send per second on average, and in a burst.
+pub const RATE_LIMIT: f64 = 10_000.0;
+pub const BURST_LIMIT: f64 = 20_000.0;
+
+#[derive(Debug)]
+struct Peer {
+    score: u64,
+    tokens: f64,
+    refilled: Instant,
+    banned_until: Option<Instant>,
+}
+
+/// Misbehaviour scores of the other replicas. Messages from a peer are
+/// rate limited, and a peer whose score reaches `BAN_THRESHOLD` is ignored
+/// for `BAN_PERIOD`.
+///
+/// Only charge a replica with what it is known to have sent: a message
+/// whose MAC verified, or one that came on a connection authenticated as
+/// that replica. Anyone can put another replica's id on a message.
+#[derive(Debug)]
+pub struct Reputation {
+    num_nodes: usize,
+    peers: HashMap<Replica, Peer>,
+}
+
+impl Reputation {
+    pub fn new(num_nodes: usize) -> Self {
+        Self { num_nodes, peers: HashMap::default() }
+    }
+
+    fn peer(&mut self, peer: Replica, now: Instant) -> &mut Peer {
+        self.peers.entry(peer).or_insert_with(|| Peer {
+            score: 0,
+            tokens: BURST_LIMIT,
+            refilled: now,
+            banned_until: None,
+        })
+    }
+
+    /// Whether to process a message from `peer` arriving at `now`. Unknown
+    /// replicas, banned peers and messages over the rate limit are turned
+    /// away, the latter counting as a flood.
+    pub fn admit(&mut self, peer: Replica, now: Instant) -> bool {
+        if peer >= self.num_nodes {
+            return false;
+        }
+        let state = self.peer(peer, now);
+        match state.banned_until {
+            Some(until) if now < until => return false,
+            Some(_) => {
+                log::info!("Lifting the ban on replica {}", peer);
+                state.banned_until = None;
+                state.score = 0;
+            },
+            None => {},
+        }
+        let elapsed = now.saturating_duration_since(state.refilled).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * RATE_LIMIT).min(BURST_LIMIT);
+        state.refilled = now;
+        if state.tokens < 1.0 {
+            self.penalize(peer, Offence::Flood, now);
+            return false;
+        }
+        state.tokens -= 1.0;
+        true
+    }
+
+    /// Charge `peer` with `offence`, banning it if its score gets too high.
+    pub fn penalize(&mut self, peer: Replica, offence: Offence, now: Instant) {
+        if peer >= self.num_nodes {
+            return;
+        }
+        let state = self.peer(peer, now);
+        if state.banned_until.is_some() {
+            return;
+        }
+        state.score += offence.weight();
+        log::warn!("Replica {} scored {} for {:?}", peer, state.score, offence);
+        if state.score >= BAN_THRESHOLD {
+            log::warn!("Banning replica {} for {:?}", peer, BAN_PERIOD);
+            state.banned_until = Some(now + BAN_PERIOD);
+        }
+    }
+
+    /// The current score of every peer that misbehaved so far.
+    pub fn scores(&self) -> Vec<(Replica, u64)> {
+        let mut scores: Vec<(Replica, u64)> = self.peers.iter()
+            .filter(|(_, state)| state.score > 0)
+            .map(|(peer, state)| (*peer, state.score))
+            .collect();
+        scores.sort_unstable();
+        scores
+    }
+
+    pub fn is_banned(&self, peer: Replica, now: Instant) -> bool {
+        self.peers.get(&peer)
+            .and_then(|state| state.banned_until)
+            .is_some_and(|until| now < until)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn misbehaving_peers_are_banned_for_a_while() {
+        let now = Instant::now();
+        let mut reputation = Reputation::new(4);
+        assert!(reputation.admit(1, now));
+        for _ in 0..BAN_THRESHOLD / Offence::InvalidMac.weight() {
+            reputation.penalize(1, Offence::InvalidMac, now);
+        }
+        assert!(reputation.is_banned(1, now));
+        assert!(!reputation.admit(1, now));
+        assert!(reputation.admit(2, now));
+        assert_eq!(reputation.scores(), vec![(1, BAN_THRESHOLD)]);
+
+        let later = now + BAN_PERIOD;
+        assert!(reputation.admit(1, later));
+        assert!(reputation.scores().is_empty());
+        // Not a replica
+        assert!(!reputation.admit(4, now));
+    }
+
+    #[test]
+    fn floods_are_throttled() {
+        let now = Instant::now();
+        let mut reputation = Reputation::new(2);
+        let admitted = (0..BURST_LIMIT as u64 + 10).filter(|_| reputation.admit(0, now)).count();
+        assert_eq!(admitted, BURST_LIMIT as usize);
+        assert_eq!(reputation.scores(), vec![(0, 10)]);
+        // Tokens come back with time
+        assert!(reputation.admit(0, now + Duration::from_millis(1)));
+    }
+}
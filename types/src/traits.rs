@@ -1,10 +1,18 @@
 use std::sync::Arc;
 
+use crate::{DecodeError, MAX_FRAME_SIZE};
+
 /// A wire trait tells us that the object can be encoded to/decoded from the
 /// network.
 pub trait WireReady: Send + Sync + Clone {
-    /// How to decode from bytes
-    fn from_bytes(data: &[u8]) -> Self;
+    /// How to decode from bytes. Fails on anything that is not a message of
+    /// this type, or larger than `MAX_FRAME_SIZE`.
+    fn from_bytes(data: &[u8]) -> Result<Self, DecodeError> {
+        Self::from_bytes_bounded(data, MAX_FRAME_SIZE)
+    }
+
+    /// Like `from_bytes`, with a maximum of `max` bytes.
+    fn from_bytes_bounded(data: &[u8], max: usize) -> Result<Self, DecodeError>;
 
     /// How to initialize self
     fn init(self) -> Self;
@@ -16,9 +24,8 @@ pub trait WireReady: Send + Sync + Clone {
 impl<A> WireReady for Arc<A> 
 where A:WireReady,
 {
-    fn from_bytes(data: &[u8]) -> Arc<A> {
-        let a = A::from_bytes(data);
-        Arc::new(a)
+    fn from_bytes_bounded(data: &[u8], max: usize) -> Result<Arc<A>, DecodeError> {
+        A::from_bytes_bounded(data, max).map(Arc::new)
     }
 
     fn to_bytes(&self) -> Vec<u8> {
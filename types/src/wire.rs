@@ -0,0 +1,75 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::de::DeserializeOwned;
+
+use crate::{Channel, MIN_WIRE_VERSION, WIRE_VERSION};
+
+/// The largest frame a replica accepts unless configured otherwise.
+pub const MAX_FRAME_SIZE: usize = 16 << 20;
+
+/// Why bytes received from the network could not be turned into a message.
+#[derive(Debug)]
+pub enum DecodeError {
+    // max
+    TooLarge(usize),
+    Malformed(bincode::Error),
+    // A version of the wire format this build does not read
+    Version(u16),
+    // The channel, its schema id in the frame, and the one expected
+    Schema(Channel, u16, u16),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DecodeError::TooLarge(max) =>
+            write!(f, "frame exceeds the maximum size of {} bytes", max),
+            DecodeError::Malformed(e) =>
+            write!(f, "malformed message: {}", e),
+            DecodeError::Version(version) =>
+            write!(f, "wire version {} is not supported, this replica reads {} to {}", version, MIN_WIRE_VERSION, WIRE_VERSION),
+            DecodeError::Schema(channel, found, expected) =>
+            write!(f, "message schema {} on {:?} differs from this replica's {}; run the same release everywhere", found, channel, expected),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decode a bincode encoded message of at most `max` bytes.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8], max: usize) -> Result<T, DecodeError> {
+    if bytes.len() > max {
+        return Err(DecodeError::TooLarge(max));
+    }
+    bincode::deserialize(bytes).map_err(DecodeError::Malformed)
+}
+
+static REJECTED_FRAMES: AtomicU64 = AtomicU64::new(0);
+
+/// Count and log a frame that failed to decode. The caller is expected to
+/// close the connection it came from.
+pub fn reject_frame(err: &DecodeError) {
+    let count = REJECTED_FRAMES.fetch_add(1, Ordering::Relaxed) + 1;
+    log::warn!("Rejected a frame ({} so far): {}", count, err);
+}
+
+/// The number of frames rejected by this process so far.
+pub fn rejected_frames() -> u64 {
+    REJECTED_FRAMES.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SyncMsg, SyncState, WireReady};
+
+    #[test]
+    fn garbage_is_an_error() {
+        let msg = SyncMsg { sender: 1, state: SyncState::ALIVE, value: "ok".to_string() };
+        let bytes = msg.to_bytes();
+        assert_eq!(SyncMsg::from_bytes(&bytes).unwrap().value, "ok");
+        assert!(matches!(SyncMsg::from_bytes(&bytes[..3]), Err(DecodeError::Malformed(_))));
+        assert!(matches!(SyncMsg::from_bytes(&[0xff; 64]), Err(DecodeError::Malformed(_))));
+        assert!(matches!(decode::<SyncMsg>(&bytes, 4), Err(DecodeError::TooLarge(4))));
+    }
+}
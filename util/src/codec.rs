@@ -57,9 +57,11 @@ where O:WireReady,
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         match self.0.decode(src)? {
-            Some(in_data) => {Ok(
-                Some(O::from_bytes(&in_data))
-            )},
+            Some(in_data) => {
+                O::from_bytes(&in_data)
+                    .map(Some)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            },
             None => Ok(None),
         }
     }